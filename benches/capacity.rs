@@ -0,0 +1,155 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use toy_payments::{Accounts, Amount, Engine, Transaction, Transactions, Type};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+///
+/// Allocator that counts the allocations made through it, so the benchmark can report them
+///
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+///
+/// Creates an empty collection for the given number of transactions
+///
+type Constructor = fn(usize) -> Transactions;
+
+///
+/// Creates an engine for the given number of transactions
+///
+type EngineConstructor = fn(usize) -> Engine;
+
+///
+/// Number of transactions passed to each `Engine::process` call, like a reader feeding the engine in chunks
+///
+const CHUNK_SIZE: usize = 1_000;
+
+fn deposits(size: usize) -> Vec<Transaction> {
+    (0..size)
+        .map(|tx| {
+            let tx = u32::try_from(tx).unwrap_or(u32::MAX);
+            common::transaction(Type::Deposit, 1, tx, Some(Amount::ONE))
+        })
+        .collect()
+}
+
+fn push_all(mut transactions: Transactions, deposits: Vec<Transaction>) -> Transactions {
+    for deposit in deposits {
+        transactions.push(deposit);
+    }
+
+    transactions
+}
+
+///
+/// Compares pushing transactions into a default collection against one created with enough capacity
+///
+/// The number of allocations of each variant is printed once before timing them
+///
+fn push_with_capacity(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("push");
+
+    for size in [10_000, 100_000] {
+        let variants: [(&str, Constructor); 2] = [
+            ("default", |_| Transactions::default()),
+            ("with_capacity", Transactions::with_capacity),
+        ];
+
+        for (name, create) in variants {
+            let input = deposits(size);
+            let before = ALLOCATIONS.load(Ordering::Relaxed);
+            let transactions = push_all(create(size), input);
+            let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+            drop(transactions);
+            println!("push/{name}/{size}: {allocations} allocations");
+
+            group.bench_with_input(BenchmarkId::new(name, size), &size, |bencher, &size| {
+                bencher.iter_batched(
+                    || deposits(size),
+                    |deposits| push_all(create(size), deposits),
+                    BatchSize::LargeInput,
+                );
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn deposit_chunks(size: usize) -> Vec<Transactions> {
+    deposits(size)
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| Transactions::from(chunk.to_vec()))
+        .collect()
+}
+
+fn process_chunks(mut engine: Engine, chunks: Vec<Transactions>) -> Engine {
+    for chunk in chunks {
+        engine.process(chunk);
+    }
+
+    engine
+}
+
+///
+/// Compares processing transactions in chunks with a new engine against one created with enough capacity
+///
+/// The number of allocations of each variant is printed once before timing them
+///
+fn process_with_capacity(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("process");
+
+    for size in [10_000, 100_000] {
+        let variants: [(&str, EngineConstructor); 2] = [
+            ("new", |_| Engine::new(Accounts::new())),
+            ("with_capacity", |size| {
+                Engine::with_capacity(Accounts::new(), size)
+            }),
+        ];
+
+        for (name, create) in variants {
+            let input = deposit_chunks(size);
+            let before = ALLOCATIONS.load(Ordering::Relaxed);
+            let engine = process_chunks(create(size), input);
+            let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+            drop(engine);
+            println!("process/{name}/{size}: {allocations} allocations");
+
+            group.bench_with_input(BenchmarkId::new(name, size), &size, |bencher, &size| {
+                bencher.iter_batched(
+                    || deposit_chunks(size),
+                    |chunks| process_chunks(create(size), chunks),
+                    BatchSize::LargeInput,
+                );
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, push_with_capacity, process_with_capacity);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use toy_payments::{Accounts, Amount, Engine, Transactions, Type};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+///
+/// Measures the throughput of `Engine::process` in transactions per second
+///
+/// The transactions are generated outside of the measurement, only processing is timed
+///
+fn process_throughput(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("process");
+    group.sample_size(10);
+
+    for size in [10_000, 100_000, 1_000_000] {
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &size,
+            |bencher, &size| {
+                bencher.iter_batched(
+                    || common::synthetic_transactions(size),
+                    |transactions| {
+                        let mut engine = Engine::new(Accounts::new());
+                        engine.process(transactions);
+                        engine
+                    },
+                    BatchSize::LargeInput,
+                );
+            },
+        );
+    }
+
+    group.finish();
+}
+
+///
+/// Generates `size` transactions where every deposit is disputed and resolved,
+/// followed by a resolve of the no longer disputed deposit that is skipped
+///
+fn resolve_heavy_transactions(size: usize) -> Transactions {
+    let transaction = |r#type, tx, amount| {
+        let client = u16::try_from(tx % 1_000).unwrap_or_default() + 1;
+        common::transaction(r#type, client, tx, amount)
+    };
+
+    let mut transactions = (0..)
+        .flat_map(|tx| {
+            [
+                transaction(Type::Deposit, tx, Some(Amount::ONE)),
+                transaction(Type::Dispute, tx, None),
+                transaction(Type::Resolve, tx, None),
+                transaction(Type::Resolve, tx, None),
+            ]
+        })
+        .take(size)
+        .collect::<Vec<_>>();
+    transactions.shrink_to_fit();
+
+    Transactions::from(transactions)
+}
+
+///
+/// Measures the throughput of `Engine::process` on a workload dominated by disputes and resolves
+///
+fn resolve_heavy_throughput(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("resolve_heavy");
+    group.sample_size(10);
+
+    for size in [10_000, 100_000, 1_000_000] {
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &size,
+            |bencher, &size| {
+                bencher.iter_batched(
+                    || resolve_heavy_transactions(size),
+                    |transactions| {
+                        let mut engine = Engine::new(Accounts::new());
+                        engine.process(transactions);
+                        engine
+                    },
+                    BatchSize::LargeInput,
+                );
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, process_throughput, resolve_heavy_throughput);
+criterion_main!(benches);
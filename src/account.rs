@@ -1,154 +1,2205 @@
-use anyhow::{Context, Result};
+use crate::error::{ErrorHook, PaymentsError, Result};
+use crate::money::{Amount, Money, Rounding};
+use crate::report::RejectReason;
+use crate::snapshot::AccountsSnapshot;
 use rust_decimal::Decimal;
-use serde::Serialize;
-use std::collections::BTreeMap;
+use serde::{Serialize, Serializer};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
 
 ///
 /// Represents an account of a client
 ///
-#[derive(Serialize, Debug)]
+/// A client has one account per currency, `None` being the unspecified currency
+///
+/// The held funds are the funds of the account's open disputes, they never go negative
+/// and the total is always the sum of the available and held funds
+///
+/// The first and last tx indexes are the indexes in the engine's transaction store of the transactions
+/// that created the account and last changed it
+///
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
 pub struct Account {
     pub client: u16,
-    pub available: Decimal,
-    pub held: Decimal,
-    pub total: Decimal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
     pub locked: bool,
+    /// The id of the charged back transaction that locked the account, `None` while unlocked
+    pub locked_by: Option<u32>,
+    /// The lifetime sum of the applied deposits, disputes and chargebacks don't reduce it
+    pub total_deposited: Amount,
+    /// The lifetime sum of the applied withdrawals, without withdrawal fees
+    pub total_withdrawn: Amount,
+    /// The number of withdrawals rejected for insufficient funds, a high count may indicate probing
+    pub rejected_withdrawals: u32,
+    pub first_tx_index: usize,
+    pub last_tx_index: usize,
 }
 
+///
+/// The available, held and total funds and the locked flag of an account, used to detect changes
+///
+#[allow(clippy::redundant_pub_crate)]
+pub(crate) type Balances = (Amount, Amount, Amount, bool);
+
 impl Account {
     pub const fn new(client: u16) -> Self {
+        Self::new_with_currency(client, None)
+    }
+
+    pub const fn new_with_currency(client: u16, currency: Option<String>) -> Self {
         Self {
             client,
-            available: Decimal::ZERO,
-            held: Decimal::ZERO,
-            total: Decimal::ZERO,
+            currency,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            total: Amount::ZERO,
             locked: false,
+            locked_by: None,
+            total_deposited: Amount::ZERO,
+            total_withdrawn: Amount::ZERO,
+            rejected_withdrawals: 0,
+            first_tx_index: 0,
+            last_tx_index: 0,
+        }
+    }
+
+    ///
+    /// Adds funds to the available and total funds
+    ///
+    /// # Errors
+    ///
+    /// Returns `RejectReason::Overflow` if either balance would overflow, in which case the account is left untouched
+    ///
+    pub fn deposit(&mut self, amount: Amount) -> std::result::Result<(), RejectReason> {
+        let (Some(available), Some(total)) = (
+            self.available.checked_add(amount),
+            self.total.checked_add(amount),
+        ) else {
+            return Err(RejectReason::Overflow);
+        };
+
+        self.available = available;
+        self.total = total;
+
+        Ok(())
+    }
+
+    ///
+    /// Removes funds from the available and total funds, held funds can never be withdrawn
+    ///
+    /// # Errors
+    ///
+    /// Returns `RejectReason::InsufficientFunds` if the available funds are lower than the amount,
+    /// or `RejectReason::Overflow` if either balance would overflow, in which case the account is left untouched
+    ///
+    pub fn withdraw(&mut self, amount: Amount) -> std::result::Result<(), RejectReason> {
+        if self.available < amount {
+            return Err(RejectReason::InsufficientFunds);
+        }
+
+        let (Some(available), Some(total)) = (
+            self.available.checked_sub(amount),
+            self.total.checked_sub(amount),
+        ) else {
+            return Err(RejectReason::Overflow);
+        };
+
+        self.available = available;
+        self.total = total;
+
+        Ok(())
+    }
+
+    ///
+    /// Removes the funds of a deposit that is undone, without checking the available funds
+    ///
+    pub fn reverse_deposit(&mut self, amount: Amount) {
+        self.available -= amount;
+        self.total -= amount;
+    }
+
+    ///
+    /// Gives back the funds of a withdrawal that is undone
+    ///
+    pub fn reverse_withdrawal(&mut self, amount: Amount) {
+        self.available += amount;
+        self.total += amount;
+    }
+
+    ///
+    /// Moves funds from available to held while they are disputed, the total funds don't change
+    /// The available funds may go negative if the disputed funds were already withdrawn
+    ///
+    /// # Errors
+    ///
+    /// Returns `RejectReason::Overflow` if either balance would overflow, in which case the account is left untouched
+    ///
+    pub fn hold(&mut self, amount: Amount) -> std::result::Result<(), RejectReason> {
+        let (Some(available), Some(held)) = (
+            self.available.checked_sub(amount),
+            self.held.checked_add(amount),
+        ) else {
+            return Err(RejectReason::Overflow);
+        };
+
+        self.available = available;
+        self.held = held;
+
+        Ok(())
+    }
+
+    ///
+    /// Moves held funds back to available once a dispute is resolved, the total funds don't change
+    ///
+    /// # Errors
+    ///
+    /// Returns `RejectReason::Overflow` if either balance would overflow, in which case the account is left untouched
+    ///
+    pub fn release(&mut self, amount: Amount) -> std::result::Result<(), RejectReason> {
+        let (Some(held), Some(available)) = (
+            self.held.checked_sub(amount),
+            self.available.checked_add(amount),
+        ) else {
+            return Err(RejectReason::Overflow);
+        };
+
+        self.held = held;
+        self.available = available;
+
+        Ok(())
+    }
+
+    ///
+    /// Removes charged back funds from held and total, and locks the account
+    ///
+    pub fn chargeback(&mut self, amount: Amount) {
+        self.held -= amount;
+        self.total -= amount;
+        self.locked = true;
+    }
+
+    ///
+    /// Provisionally credits the funds of a disputed withdrawal as held, the available funds don't change
+    /// since the withdrawn money already left the account
+    ///
+    pub fn hold_withdrawal(&mut self, amount: Amount) {
+        self.held += amount;
+        self.total += amount;
+    }
+
+    ///
+    /// Removes the provisional credit of a disputed withdrawal once the dispute is resolved in favor of the withdrawal
+    ///
+    pub fn release_withdrawal(&mut self, amount: Amount) {
+        self.held -= amount;
+        self.total -= amount;
+    }
+
+    ///
+    /// Permanently credits the held funds of a charged back withdrawal to available, and locks the account
+    ///
+    pub fn chargeback_withdrawal(&mut self, amount: Amount) {
+        self.held -= amount;
+        self.available += amount;
+        self.locked = true;
+    }
+
+    ///
+    /// Returns the fraction of the total funds that is held under dispute, from 0 to 1 while the available funds
+    /// aren't negative, e.g. for risk scoring
+    ///
+    /// Returns `None` when the total funds are zero or negative, since the fraction isn't meaningful
+    ///
+    pub fn utilization(&self) -> Option<Decimal> {
+        if self.total <= Amount::ZERO {
+            return None;
         }
+
+        self.held.ratio(self.total)
+    }
+
+    ///
+    /// Returns the amount currently under dispute for this account
+    /// This is the same as the held funds, since only active disputes move funds into held
+    ///
+    pub const fn disputed_total(&self) -> Amount {
+        self.held
+    }
+
+    ///
+    /// Returns the csv record of the account without a line ending, formatted like `Accounts::write_csv_with`
+    /// with the given options, the currency column is only written if the account has a currency
+    ///
+    /// # Errors
+    ///
+    /// If the record fails to be serialized
+    ///
+    #[cfg(feature = "net")]
+    pub(crate) fn to_csv_line(&self, options: &CsvOptions) -> Result<String> {
+        let mut csv_writer = csv::WriterBuilder::default()
+            .has_headers(false)
+            .from_writer(vec![]);
+        csv_writer
+            .serialize(AccountRecord::new(self, *options, self.currency.is_some()))
+            .map_err(|source| PaymentsError::Serialize {
+                client: self.client,
+                source,
+            })?;
+        let line = csv_writer
+            .into_inner()
+            .map_err(csv::IntoInnerError::into_error)?;
+
+        Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+    }
+
+    pub(crate) const fn balances(&self) -> Balances {
+        (self.available, self.held, self.total, self.locked)
+    }
+
+    ///
+    /// Returns true if the account has no available, held or total funds and isn't locked
+    /// A locked account is never empty, since the lock itself is meaningful
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.available == Amount::ZERO
+            && self.held == Amount::ZERO
+            && self.total == Amount::ZERO
+            && !self.locked
+    }
+
+    ///
+    /// Returns true if the available or total funds went negative,
+    /// e.g. when a deposit is disputed after its funds were withdrawn
+    ///
+    pub fn is_overdrawn(&self) -> bool {
+        self.available.is_negative() || self.total.is_negative()
     }
 }
 
 ///
-/// Represents a collection of accounts
-/// Client id is used for the key for faster lookups
+/// The order in which accounts are written when serializing them
+/// Ties are always broken by the client id so the output stays deterministic
 ///
-pub struct Accounts(BTreeMap<u16, Account>);
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortKey {
+    #[default]
+    Client,
+    TotalAsc,
+    TotalDesc,
+    HeldDesc,
+    /// The order in which the accounts were created, i.e. the order their clients first appeared in the input
+    /// The accounts merged from the shards of a `SharedEngine` are in shard order instead, see `Accounts::merge`
+    InsertionOrder,
+}
 
-impl Default for Accounts {
+///
+/// Options controlling how accounts are written as csv
+///
+/// The default options write the accounts sorted by client id with the standard columns,
+/// flushing the writer every [`CsvOptions::DEFAULT_FLUSH_INTERVAL`] records
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CsvOptions {
+    sort_key: SortKey,
+    overdrawn: bool,
+    omit_empty: bool,
+    activity: bool,
+    fixed_scale: bool,
+    locked_by: bool,
+    lifetime_totals: bool,
+    rejected_withdrawals: bool,
+    strict_serialize: bool,
+    skipped_account_hook: Option<ErrorHook>,
+    rounding: Rounding,
+    flush_interval: usize,
+}
+
+impl Default for CsvOptions {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl Accounts {
+impl CsvOptions {
+    ///
+    /// Number of records written between two flushes of the writer by default
+    ///
+    pub const DEFAULT_FLUSH_INTERVAL: usize = 1024;
+
+    ///
+    /// Number of decimal places amounts are rounded to when written
+    ///
+    pub const SCALE: u32 = 4;
+
     pub const fn new() -> Self {
-        Self(BTreeMap::new())
+        Self {
+            sort_key: SortKey::Client,
+            overdrawn: false,
+            omit_empty: false,
+            activity: false,
+            fixed_scale: false,
+            locked_by: false,
+            lifetime_totals: false,
+            rejected_withdrawals: false,
+            strict_serialize: true,
+            skipped_account_hook: None,
+            rounding: Rounding::HalfEven,
+            flush_interval: Self::DEFAULT_FLUSH_INTERVAL,
+        }
     }
 
-    pub fn get(&self, client: u16) -> Option<&Account> {
-        self.0.get(&client)
+    ///
+    /// Sets the order in which the accounts are written
+    ///
+    #[must_use]
+    pub const fn sorted_by(mut self, sort_key: SortKey) -> Self {
+        self.sort_key = sort_key;
+        self
     }
 
     ///
-    /// Returns a mutable account for a given client id
-    /// If the account does not exist, it will be created and returned
+    /// Adds an `overdrawn` column flagging accounts with negative available or total funds
     ///
-    pub fn get_mut(&mut self, client: u16) -> &mut Account {
-        self.0.entry(client).or_insert_with(|| Account::new(client))
+    #[must_use]
+    pub const fn with_overdrawn(mut self, overdrawn: bool) -> Self {
+        self.overdrawn = overdrawn;
+        self
     }
 
     ///
-    /// Writes to stdout the state of all accounts in a CSV format
-    /// Since the accounts are stored in a `BTreeMap`, the output is sorted by the client id
+    /// Leaves out accounts without any funds that aren't locked, see `Account::is_empty`
     ///
-    /// # Errors
+    #[must_use]
+    pub const fn with_omit_empty(mut self, omit_empty: bool) -> Self {
+        self.omit_empty = omit_empty;
+        self
+    }
+
     ///
-    /// If the csv writer fails to serialize the account to a csv record
+    /// Adds `first_tx_index` and `last_tx_index` columns with the indexes of the transactions
+    /// that created each account and last changed it
     ///
-    pub fn print_state(&self) -> Result<()> {
-        let lock = std::io::stdout().lock();
+    #[must_use]
+    pub const fn with_activity(mut self, activity: bool) -> Self {
+        self.activity = activity;
+        self
+    }
 
-        let mut csv_writer = csv::WriterBuilder::default()
-            .delimiter(b',')
-            .has_headers(true)
-            .from_writer(lock);
+    ///
+    /// Adds a `locked_by` column with the id of the charged back transaction that locked each account,
+    /// empty for unlocked accounts
+    ///
+    #[must_use]
+    pub const fn with_locked_by(mut self, locked_by: bool) -> Self {
+        self.locked_by = locked_by;
+        self
+    }
 
-        for account in self.0.values() {
-            csv_writer.serialize(account).with_context(|| {
-                format!("Failed to serialize account to csv record: {account:?}")
-            })?;
-        }
+    ///
+    /// Adds `total_deposited` and `total_withdrawn` columns with the lifetime gross sums of the deposits
+    /// and withdrawals of each account, e.g. for statements
+    ///
+    #[must_use]
+    pub const fn with_lifetime_totals(mut self, lifetime_totals: bool) -> Self {
+        self.lifetime_totals = lifetime_totals;
+        self
+    }
 
-        csv_writer.flush().with_context(|| {
-            "Failed to flush csv writer to stdout while attempting to print accounts"
-        })?;
+    ///
+    /// Adds a `rejected_withdrawals` column with the number of withdrawals of each account
+    /// that were rejected for insufficient funds, e.g. as a fraud signal
+    ///
+    #[must_use]
+    pub const fn with_rejected_withdrawals(mut self, rejected_withdrawals: bool) -> Self {
+        self.rejected_withdrawals = rejected_withdrawals;
+        self
+    }
 
-        Ok(())
+    ///
+    /// Fails the whole output on the first account that fails to be serialized, the default
+    /// Otherwise the account is skipped and the rest of the accounts are still written, a skipped account
+    /// is dropped silently unless a hook is set with `CsvOptions::with_skipped_account_hook`
+    ///
+    #[must_use]
+    pub const fn with_strict_serialize(mut self, strict_serialize: bool) -> Self {
+        self.strict_serialize = strict_serialize;
+        self
+    }
+
+    ///
+    /// Calls the hook with the `PaymentsError::Serialize` error of every account skipped without strict serialization,
+    /// e.g. to warn about it
+    ///
+    #[must_use]
+    pub const fn with_skipped_account_hook(mut self, hook: fn(&PaymentsError)) -> Self {
+        self.skipped_account_hook = Some(ErrorHook(hook));
+        self
+    }
+
+    ///
+    /// Writes every amount with exactly `CsvOptions::SCALE` decimal places, e.g. `0.0000` and `100.5000`,
+    /// instead of the natural formatting of the amount
+    ///
+    #[must_use]
+    pub const fn with_fixed_scale(mut self, fixed_scale: bool) -> Self {
+        self.fixed_scale = fixed_scale;
+        self
+    }
+
+    ///
+    /// Sets how amounts with more than `CsvOptions::SCALE` decimal places are rounded, half-even by default
+    ///
+    #[must_use]
+    pub const fn with_rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    ///
+    /// Flushes the writer every `flush_interval` records, so partial output reaches slow consumers early
+    ///
+    /// An interval of `0` disables the periodic flushes and only flushes once all records are written
+    ///
+    #[must_use]
+    pub const fn with_flush_interval(mut self, flush_interval: usize) -> Self {
+        self.flush_interval = flush_interval;
+        self
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+///
+/// The csv record of an account
+///
+/// The currency column is only written when at least one account has a currency,
+/// so single currency outputs keep the same columns
+///
+#[derive(Serialize)]
+struct AccountRecord<'a> {
+    client: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<&'a str>,
+    available: CsvAmount,
+    held: CsvAmount,
+    total: CsvAmount,
+    locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locked_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    overdrawn: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_deposited: Option<CsvAmount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_withdrawn: Option<CsvAmount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rejected_withdrawals: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_tx_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_tx_index: Option<usize>,
+}
 
-    #[test]
-    fn new_account() {
-        let account = Account::new(1);
+impl<'a> AccountRecord<'a> {
+    fn new(account: &'a Account, options: CsvOptions, with_currency: bool) -> Self {
+        let amount = |amount: Amount| CsvAmount {
+            amount: amount.round_with(CsvOptions::SCALE, options.rounding),
+            fixed_scale: options.fixed_scale,
+        };
 
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, 0.into());
-        assert_eq!(account.held, 0.into());
-        assert_eq!(account.total, 0.into());
-        assert!(!account.locked);
+        Self {
+            client: account.client,
+            currency: with_currency.then(|| account.currency.as_deref().unwrap_or_default()),
+            available: amount(account.available),
+            held: amount(account.held),
+            total: amount(account.total),
+            locked: account.locked,
+            locked_by: options.locked_by.then(|| {
+                account
+                    .locked_by
+                    .map(|tx| tx.to_string())
+                    .unwrap_or_default()
+            }),
+            overdrawn: options.overdrawn.then(|| account.is_overdrawn()),
+            total_deposited: options
+                .lifetime_totals
+                .then(|| amount(account.total_deposited)),
+            total_withdrawn: options
+                .lifetime_totals
+                .then(|| amount(account.total_withdrawn)),
+            rejected_withdrawals: options
+                .rejected_withdrawals
+                .then_some(account.rejected_withdrawals),
+            first_tx_index: options.activity.then_some(account.first_tx_index),
+            last_tx_index: options.activity.then_some(account.last_tx_index),
+        }
     }
+}
 
-    #[test]
-    fn new_accounts_is_empty() {
-        let accounts = Accounts::new();
+///
+/// An amount of a csv record, written with its natural formatting or with exactly `CsvOptions::SCALE` decimal places
+///
+struct CsvAmount {
+    amount: Amount,
+    fixed_scale: bool,
+}
 
-        assert!(accounts.0.is_empty());
+impl Serialize for CsvAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if self.fixed_scale {
+            serializer.collect_str(&format_args!(
+                "{:.*}",
+                CsvOptions::SCALE as usize,
+                self.amount
+            ))
+        } else {
+            Serialize::serialize(&self.amount, serializer)
+        }
     }
+}
 
-    #[test]
-    fn get_mut_account() {
-        let mut accounts = Accounts::new();
+///
+/// Represents a collection of accounts
+///
+/// The client id and currency are used for the key for faster lookups
+/// Transactions without a currency are booked in the base currency, if one is configured
+/// The clients whose accounts were mutably accessed since the last `write_csv_dirty` are tracked as dirty
+/// The distinct clients with an account are tracked too, so counting them doesn't walk every account
+///
+/// Every account gets an insertion sequence number when it's created, increasing with every account,
+/// so ordering by it gives the order in which the clients first appeared in the input
+///
+#[allow(clippy::struct_field_names)]
+pub struct Accounts {
+    accounts: BTreeMap<(u16, Option<String>), Account>,
+    base_currency: Option<String>,
+    dirty: BTreeSet<u16>,
+    clients: BTreeSet<u16>,
+    sequences: BTreeMap<(u16, Option<String>), u64>,
+    next_sequence: u64,
+}
 
-        let account = accounts.get_mut(1);
+impl Default for Accounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, 0.into());
-        assert_eq!(account.held, 0.into());
-        assert_eq!(account.total, 0.into());
-        assert!(!account.locked);
+impl Accounts {
+    pub const fn new() -> Self {
+        Self {
+            accounts: BTreeMap::new(),
+            base_currency: None,
+            dirty: BTreeSet::new(),
+            clients: BTreeSet::new(),
+            sequences: BTreeMap::new(),
+            next_sequence: 0,
+        }
     }
 
-    #[test]
-    fn get_mut_account_twice() {
-        let mut accounts = Accounts::new();
+    ///
+    /// Sets the currency used for transactions and lookups that don't specify one
+    ///
+    #[must_use]
+    pub fn with_base_currency(mut self, currency: impl Into<String>) -> Self {
+        self.base_currency = Some(currency.into());
+        self
+    }
 
-        let account = accounts.get_mut(1);
-        account.available = Decimal::from(100);
+    pub(crate) fn base_currency(&self) -> Option<&str> {
+        self.base_currency.as_deref()
+    }
 
-        let account = accounts.get_mut(1);
+    ///
+    /// Resolves an optional currency to the currency the account is booked in
+    ///
+    pub(crate) fn resolve_currency<'a>(&'a self, currency: Option<&'a str>) -> Option<&'a str> {
+        currency.or(self.base_currency.as_deref())
+    }
 
-        assert_eq!(account.client, 1);
-        assert_eq!(account.available, Decimal::from(100));
-        assert_eq!(account.held, 0.into());
-        assert!(!account.locked);
+    ///
+    /// Returns the account of a client in the base currency
+    ///
+    pub fn get(&self, client: u16) -> Option<&Account> {
+        self.get_in(client, None)
     }
 
-    #[test]
-    fn get_mut_account_twice_different() {
-        let mut accounts = Accounts::new();
+    ///
+    /// Returns the account of a client in the given currency, or the base currency if none is given
+    ///
+    pub fn get_in(&self, client: u16, currency: Option<&str>) -> Option<&Account> {
+        let currency = self.resolve_currency(currency).map(ToString::to_string);
+        self.accounts.get(&(client, currency))
+    }
 
-        let account = accounts.get_mut(1);
-        account.available = Decimal::from(100);
+    ///
+    /// Returns an iterator over all accounts, ordered by client id (and currency)
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
 
-        let account = accounts.get_mut(2);
+    ///
+    /// Captures the accounts and the base currency for an engine snapshot
+    ///
+    pub(crate) fn snapshot(&self) -> AccountsSnapshot {
+        AccountsSnapshot {
+            accounts: self
+                .accounts
+                .iter()
+                .map(|(key, account)| (account, self.sequences[key]).into())
+                .collect(),
+            base_currency: self.base_currency.clone(),
+        }
+    }
 
-        assert_eq!(account.client, 2);
-        assert_eq!(account.available, 0.into());
-        assert_eq!(account.held, 0.into());
-        assert_eq!(account.total, 0.into());
+    ///
+    /// Restores the accounts of an engine snapshot, no client is dirty after restoring
+    ///
+    pub(crate) fn from_snapshot(snapshot: AccountsSnapshot) -> Self {
+        let mut accounts = BTreeMap::new();
+        let mut sequences = BTreeMap::new();
+        for (account, sequence) in snapshot.accounts.into_iter().map(<(Account, u64)>::from) {
+            let key = (account.client, account.currency.clone());
+            sequences.insert(key.clone(), sequence);
+            accounts.insert(key, account);
+        }
+        let next_sequence = sequences
+            .values()
+            .map(|sequence| sequence + 1)
+            .max()
+            .unwrap_or_default();
+        let clients = accounts.keys().map(|(client, _)| *client).collect();
+
+        Self {
+            accounts,
+            base_currency: snapshot.base_currency,
+            dirty: BTreeSet::new(),
+            clients,
+            sequences,
+            next_sequence,
+        }
+    }
+
+    ///
+    /// Folds another collection of accounts into this one, e.g. the result of processing another shard of clients
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if both collections have an account for the same client id,
+    /// since that means the shards were not disjoint. No account is merged in that case
+    ///
+    /// The merged accounts are inserted after the existing ones, keeping their own insertion order
+    ///
+    pub fn merge(&mut self, other: Self) -> Result<()> {
+        if let Some(client) = other.clients.intersection(&self.clients).next() {
+            return Err(PaymentsError::OverlappingClient(*client));
+        }
+
+        self.dirty.extend(&other.clients);
+        self.clients.extend(&other.clients);
+
+        let mut merged = other.accounts.into_iter().collect::<Vec<_>>();
+        merged.sort_by_key(|(key, _)| other.sequences[key]);
+        for (key, account) in merged {
+            self.sequences.insert(key.clone(), self.next_sequence);
+            self.next_sequence += 1;
+            self.accounts.insert(key, account);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Returns the accounts matching the predicate, ordered by client id (and currency)
+    ///
+    pub fn filter(&self, predicate: impl Fn(&Account) -> bool) -> Vec<&Account> {
+        self.iter().filter(|account| predicate(account)).collect()
+    }
+
+    ///
+    /// Returns the accounts whose total funds are within `min` and `max` (inclusive), ordered by client id (and currency)
+    ///
+    pub fn filter_by_total(&self, min: Amount, max: Amount) -> Vec<&Account> {
+        self.filter(|account| (min..=max).contains(&account.total))
+    }
+
+    ///
+    /// Returns the number of accounts, an account per client and currency
+    ///
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    ///
+    /// Removes every account and forgets the dirty clients, the base currency is kept
+    ///
+    pub fn clear(&mut self) {
+        self.accounts.clear();
+        self.dirty.clear();
+        self.clients.clear();
+        self.sequences.clear();
+        self.next_sequence = 0;
+    }
+
+    ///
+    /// Returns the number of distinct clients, a client with accounts in several currencies is counted once
+    ///
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    ///
+    /// Returns true if the client has an account in any currency
+    ///
+    pub fn contains_client(&self, client: u16) -> bool {
+        self.clients.contains(&client)
+    }
+
+    ///
+    /// Returns the number of locked accounts
+    ///
+    pub fn locked_count(&self) -> usize {
+        self.accounts
+            .values()
+            .filter(|account| account.locked)
+            .count()
+    }
+
+    ///
+    /// Returns the sum of held funds across all accounts
+    ///
+    pub fn total_held(&self) -> Amount {
+        self.accounts.values().map(|account| account.held).sum()
+    }
+
+    ///
+    /// Returns a stable 64-bit hash of the state of all accounts, to compare the results of two runs
+    /// without diffing their csv outputs
+    ///
+    /// The accounts are hashed in client id (and currency) order with FNV-1a, over a canonical
+    /// `client,currency,available,held,total,locked` line per account. Amounts are rounded half-even
+    /// to `CsvOptions::SCALE` decimal places and written with exactly that many, so `2.5` and `2.50` hash the same.
+    /// The checksum doesn't depend on the platform, the amount backend or the order the accounts were created in
+    ///
+    pub fn checksum(&self) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        let places = CsvOptions::SCALE as usize;
+        self.accounts
+            .values()
+            .map(|account| {
+                let [available, held, total] = [account.available, account.held, account.total]
+                    .map(|amount| amount.round_with(CsvOptions::SCALE, Rounding::HalfEven));
+
+                format!(
+                    "{},{},{available:.places$},{held:.places$},{total:.places$},{}\n",
+                    account.client,
+                    account.currency.as_deref().unwrap_or_default(),
+                    account.locked
+                )
+            })
+            .flat_map(String::into_bytes)
+            .fold(OFFSET_BASIS, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+            })
+    }
+
+    ///
+    /// Returns a mutable account for a given client id in the base currency
+    /// If the account does not exist, it will be created and returned
+    ///
+    pub fn get_mut(&mut self, client: u16) -> &mut Account {
+        self.get_mut_in(client, None)
+    }
+
+    ///
+    /// Returns a mutable account for a given client id in the given currency, or the base currency if none is given
+    /// If the account does not exist, it will be created and returned
+    ///
+    pub fn get_mut_in(&mut self, client: u16, currency: Option<&str>) -> &mut Account {
+        let currency = self.resolve_currency(currency).map(ToString::to_string);
+        self.dirty.insert(client);
+
+        let (clients, sequences, next_sequence) = (
+            &mut self.clients,
+            &mut self.sequences,
+            &mut self.next_sequence,
+        );
+        self.accounts
+            .entry((client, currency.clone()))
+            .or_insert_with_key(|key| {
+                clients.insert(client);
+                sequences.insert(key.clone(), *next_sequence);
+                *next_sequence += 1;
+                Account::new_with_currency(client, currency)
+            })
+    }
+
+    ///
+    /// Records that the transaction at `index` was processed for the account of a client in the given currency,
+    /// given the balances of the account before it, or `None` if the account didn't exist yet,
+    /// and returns the account if it exists
+    ///
+    /// Accounts are only created by the transactions that change them, so a new account takes the index as both
+    /// its first and last tx index
+    ///
+    pub(crate) fn record_activity(
+        &mut self,
+        client: u16,
+        currency: Option<&str>,
+        index: usize,
+        before: Option<Balances>,
+    ) -> Option<&Account> {
+        let currency = self.resolve_currency(currency).map(ToString::to_string);
+        let account = self.accounts.get_mut(&(client, currency))?;
+
+        match before {
+            None => {
+                account.first_tx_index = index;
+                account.last_tx_index = index;
+            }
+            Some(before) if account.balances() != before => account.last_tx_index = index,
+            Some(_) => {}
+        }
+
+        Some(account)
+    }
+
+    ///
+    /// Removes the account of a client in the given currency if the transaction at `index` created it
+    /// and it's empty again, e.g. once that transaction is undone, as if the account never existed
+    ///
+    pub(crate) fn remove_created_by(&mut self, client: u16, currency: Option<&str>, index: usize) {
+        let key = (
+            client,
+            self.resolve_currency(currency).map(ToString::to_string),
+        );
+        let created = self
+            .accounts
+            .get(&key)
+            .is_some_and(|account| account.first_tx_index == index && account.is_empty());
+        if !created {
+            return;
+        }
+
+        self.accounts.remove(&key);
+        if self
+            .sequences
+            .remove(&key)
+            .is_some_and(|sequence| sequence + 1 == self.next_sequence)
+        {
+            self.next_sequence -= 1;
+        }
+        if !self.accounts.keys().any(|(other, _)| *other == client) {
+            self.clients.remove(&client);
+        }
+    }
+
+    ///
+    /// Writes to stdout the state of all accounts in a CSV format
+    /// Since the accounts are stored in a `BTreeMap`, the output is sorted by the client id (and currency)
+    ///
+    /// The output is flushed periodically, so if stdout is closed early (e.g. piped to `head`)
+    /// the records written so far are complete and the failure is returned as an error
+    ///
+    /// # Errors
+    ///
+    /// If the csv writer fails to serialize the account to a csv record or to write to stdout
+    ///
+    pub fn print_state(&self) -> Result<()> {
+        let lock = std::io::stdout().lock();
+
+        self.write_csv(lock)
+    }
+
+    ///
+    /// Writes the state of all accounts in a CSV format to the given writer, sorted by the client id
+    ///
+    /// # Errors
+    ///
+    /// If the csv writer fails to serialize the account to a csv record or to flush the writer
+    ///
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<()> {
+        self.write_csv_with(&CsvOptions::default(), writer)
+    }
+
+    ///
+    /// Writes the state of all accounts in a CSV format to the given writer, sorted by the given key
+    ///
+    /// # Errors
+    ///
+    /// If the csv writer fails to serialize the account to a csv record or to flush the writer
+    ///
+    pub fn write_csv_sorted_by<W: Write>(&self, key: SortKey, writer: W) -> Result<()> {
+        self.write_csv_with(&CsvOptions::default().sorted_by(key), writer)
+    }
+
+    ///
+    /// Writes the state of all accounts in a CSV format to the given writer, in the order their clients first appeared
+    /// (the order the accounts were created) instead of by client id
+    ///
+    /// # Errors
+    ///
+    /// If the csv writer fails to serialize the account to a csv record or to flush the writer
+    ///
+    pub fn write_csv_insertion_order<W: Write>(&self, writer: W) -> Result<()> {
+        self.write_csv_sorted_by(SortKey::InsertionOrder, writer)
+    }
+
+    ///
+    /// Writes the state of all accounts in a CSV format to the given writer using the given options
+    ///
+    /// # Errors
+    ///
+    /// If the csv writer fails to serialize the account to a csv record or to flush the writer
+    ///
+    pub fn write_csv_with<W: Write>(&self, options: &CsvOptions, writer: W) -> Result<()> {
+        self.write_csv_filtered(options, writer, |account| {
+            !options.omit_empty || !account.is_empty()
+        })
+    }
+
+    ///
+    /// Returns the ids of the clients whose accounts changed since the last `write_csv_dirty`, in ascending order
+    ///
+    pub fn changed_clients(&self) -> Vec<u16> {
+        self.dirty.iter().copied().collect()
+    }
+
+    ///
+    /// Writes in a CSV format only the accounts of clients that changed since the last call, sorted by the client id
+    /// The set of changed clients is reset once the accounts are written
+    ///
+    /// # Errors
+    ///
+    /// If the csv writer fails to serialize the account to a csv record or to flush the writer,
+    /// in which case the changed clients are kept
+    ///
+    pub fn write_csv_dirty<W: Write>(&mut self, writer: W) -> Result<()> {
+        self.write_csv_filtered(&CsvOptions::default(), writer, |account| {
+            self.dirty.contains(&account.client)
+        })?;
+
+        self.dirty.clear();
+
+        Ok(())
+    }
+
+    ///
+    /// Writes the accounts matching the filter in a CSV format to the given writer using the given options
+    /// The currency column is decided on all accounts, so filtered outputs keep the same columns
+    ///
+    fn write_csv_filtered<W: Write>(
+        &self,
+        options: &CsvOptions,
+        writer: W,
+        filter: impl Fn(&Account) -> bool,
+    ) -> Result<()> {
+        let with_currency = self.iter().any(|account| account.currency.is_some());
+        let accounts = self.sorted(options.sort_key, filter);
+
+        let mut csv_writer = csv::WriterBuilder::default()
+            .delimiter(b',')
+            .has_headers(true)
+            .from_writer(writer);
+
+        for (written, account) in accounts.into_iter().enumerate() {
+            Self::write_record(
+                &mut csv_writer,
+                account.client,
+                &AccountRecord::new(account, *options, with_currency),
+                options,
+            )?;
+
+            if options.flush_interval > 0 && (written + 1) % options.flush_interval == 0 {
+                csv_writer.flush()?;
+            }
+        }
+
+        csv_writer.flush()?;
+
+        Ok(())
+    }
+
+    ///
+    /// Returns the accounts matching the filter in the order of the sort key
+    ///
+    fn sorted(&self, sort_key: SortKey, filter: impl Fn(&Account) -> bool) -> Vec<&Account> {
+        // The accounts are already ordered by client id, and a stable sort keeps that order for ties
+        let mut accounts = self
+            .accounts
+            .values()
+            .filter(|account| filter(account))
+            .collect::<Vec<_>>();
+        match sort_key {
+            SortKey::Client => {}
+            SortKey::TotalAsc => accounts.sort_by_key(|account| account.total),
+            SortKey::TotalDesc => accounts.sort_by_key(|account| Reverse(account.total)),
+            SortKey::HeldDesc => accounts.sort_by_key(|account| Reverse(account.held)),
+            SortKey::InsertionOrder => accounts
+                .sort_by_key(|account| self.sequences[&(account.client, account.currency.clone())]),
+        }
+
+        accounts
+    }
+
+    ///
+    /// Serializes the csv record of the account of a client to the csv writer
+    ///
+    /// Without strict serialization, a record that fails to be serialized is skipped and passed to the skipped account hook.
+    /// The record is serialized on its own first, so a failure never leaves a partial row in the output
+    ///
+    /// # Errors
+    ///
+    /// If the record fails to be serialized with strict serialization, or fails to be written
+    ///
+    fn write_record<W: Write>(
+        csv_writer: &mut csv::Writer<W>,
+        client: u16,
+        record: &impl Serialize,
+        options: &CsvOptions,
+    ) -> Result<()> {
+        if !options.strict_serialize {
+            let mut scratch = csv::WriterBuilder::default()
+                .has_headers(false)
+                .from_writer(std::io::sink());
+
+            if let Err(source) = scratch.serialize(record) {
+                if let Some(ErrorHook(hook)) = options.skipped_account_hook {
+                    hook(&PaymentsError::Serialize { client, source });
+                }
+                return Ok(());
+            }
+        }
+
+        csv_writer
+            .serialize(record)
+            .map_err(|source| PaymentsError::Serialize { client, source })
+    }
+
+    ///
+    /// Writes to stdout the state of all accounts as an aligned text table, sorted by the client id
+    /// This is meant for interactive inspection, use `print_state` for machine consumption
+    ///
+    /// # Errors
+    ///
+    /// If writing to stdout fails
+    ///
+    pub fn print_table(&self) -> Result<()> {
+        let lock = std::io::stdout().lock();
+
+        self.write_table(lock)
+    }
+
+    ///
+    /// Version of the binary format written by `Accounts::write_binary`
+    ///
+    /// The binary format isn't self-describing, so it is bumped whenever a field of the accounts is added,
+    /// removed or changed and readers reject versions they don't know instead of misreading them
+    ///
+    pub const BINARY_FORMAT_VERSION: u8 = 4;
+
+    ///
+    /// Writes the state of all accounts in a compact binary format to the given writer, for machine-to-machine transfer
+    ///
+    /// The output is a [`Accounts::BINARY_FORMAT_VERSION`] byte followed by the bincode encoded accounts,
+    /// in client id (and currency) order, and can be read back with `Accounts::read_binary`
+    ///
+    /// # Errors
+    ///
+    /// If the accounts fail to be serialized or written to the writer
+    ///
+    pub fn write_binary<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(&[Self::BINARY_FORMAT_VERSION])?;
+        bincode::serialize_into(&mut writer, &self.snapshot()).map_err(PaymentsError::Binary)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    ///
+    /// Reads accounts written by `Accounts::write_binary`, no client is dirty after reading
+    ///
+    /// # Errors
+    ///
+    /// If the version byte is not [`Accounts::BINARY_FORMAT_VERSION`], or the accounts fail to be read or deserialized
+    ///
+    pub fn read_binary<R: Read>(mut reader: R) -> Result<Self> {
+        let mut version = [0; 1];
+        reader.read_exact(&mut version)?;
+        if version[0] != Self::BINARY_FORMAT_VERSION {
+            return Err(PaymentsError::UnsupportedBinaryVersion(version[0]));
+        }
+
+        let snapshot = bincode::deserialize_from(reader).map_err(PaymentsError::Binary)?;
+        Ok(Self::from_snapshot(snapshot))
+    }
+
+    ///
+    /// Writes the state of all accounts as an aligned text table to the given writer, sorted by the client id
+    ///
+    /// Amounts are right-aligned with 4 decimal places and locked accounts are marked in the status column.
+    /// The currency column is only written when at least one account has a currency
+    ///
+    /// # Errors
+    ///
+    /// If writing to the writer fails
+    ///
+    pub fn write_table<W: Write>(&self, writer: W) -> Result<()> {
+        self.write_table_with(&CsvOptions::default(), writer)
+    }
+
+    ///
+    /// Writes the state of all accounts as an aligned text table like `Accounts::write_table`, using the order,
+    /// empty account filter, rounding and overdrawn flag of the given options. Overdrawn accounts are marked
+    /// in the status column, the other columns of the options are only written to csv
+    ///
+    /// # Errors
+    ///
+    /// If writing to the writer fails
+    ///
+    pub fn write_table_with<W: Write>(&self, options: &CsvOptions, mut writer: W) -> Result<()> {
+        let with_currency = self.iter().any(|account| account.currency.is_some());
+        let amount = |amount: Amount| {
+            format!(
+                "{:.*}",
+                CsvOptions::SCALE as usize,
+                amount.round_with(CsvOptions::SCALE, options.rounding)
+            )
+        };
+
+        let mut rows = vec![vec![
+            "client".to_string(),
+            "currency".to_string(),
+            "available".to_string(),
+            "held".to_string(),
+            "total".to_string(),
+            "status".to_string(),
+        ]];
+        let accounts = self.sorted(options.sort_key, |account| {
+            !options.omit_empty || !account.is_empty()
+        });
+        for account in accounts {
+            let status = [
+                account.locked.then_some("LOCKED"),
+                (options.overdrawn && account.is_overdrawn()).then_some("OVERDRAWN"),
+            ];
+            rows.push(vec![
+                account.client.to_string(),
+                account.currency.clone().unwrap_or_default(),
+                amount(account.available),
+                amount(account.held),
+                amount(account.total),
+                status.into_iter().flatten().collect::<Vec<_>>().join(" "),
+            ]);
+        }
+
+        if !with_currency {
+            for row in &mut rows {
+                row.remove(1);
+            }
+        }
+
+        let columns = rows[0].len();
+        let widths = (0..columns)
+            .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        // Text columns (currency and status) are left-aligned, numbers are right-aligned
+        let left_aligned = |column: usize| column == columns - 1 || (with_currency && column == 1);
+
+        let separator = widths.iter().map(|width| "-".repeat(*width)).collect();
+        rows.insert(1, separator);
+
+        for row in rows {
+            let line = row
+                .iter()
+                .enumerate()
+                .map(|(column, cell)| {
+                    let width = widths[column];
+                    if left_aligned(column) {
+                        format!("{cell:<width$}")
+                    } else {
+                        format!("{cell:>width$}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+
+            writeln!(writer, "{}", line.trim_end())?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    #[test]
+    fn new_account() {
+        let account = Account::new(1);
+
+        assert_eq!(account.client, 1);
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, 0.into());
+        assert!(!account.locked);
+    }
+
+    ///
+    /// Asserts the available, held and total funds of an account
+    ///
+    fn assert_balances(account: &Account, available: i64, held: i64, total: i64) {
+        assert_eq!(account.available, Amount::from(available));
+        assert_eq!(account.held, Amount::from(held));
+        assert_eq!(account.total, Amount::from(total));
+    }
+
+    #[test]
+    fn account_deposit() {
+        let mut account = Account::new(1);
+
+        account
+            .deposit(Amount::from(10))
+            .expect("Failed to deposit");
+
+        assert_balances(&account, 10, 0, 10);
+    }
+
+    #[test]
+    fn account_deposit_rejects_overflow() {
+        let mut account = Account::new(1);
+        account.deposit(Amount::MAX).expect("Failed to deposit");
+
+        assert_eq!(
+            account.deposit(Amount::from(1)),
+            Err(RejectReason::Overflow)
+        );
+        assert_eq!(account.available, Amount::MAX);
+        assert_eq!(account.total, Amount::MAX);
+    }
+
+    #[test]
+    fn account_withdraw() {
+        let mut account = Account::new(1);
+        account
+            .deposit(Amount::from(10))
+            .expect("Failed to deposit");
+
+        account
+            .withdraw(Amount::from(4))
+            .expect("Failed to withdraw");
+
+        assert_balances(&account, 6, 0, 6);
+    }
+
+    #[test]
+    fn account_withdraw_insufficient_funds() {
+        let mut account = Account::new(1);
+        account
+            .deposit(Amount::from(10))
+            .expect("Failed to deposit");
+        account.hold(Amount::from(5)).expect("Failed to hold");
+
+        assert_eq!(
+            account.withdraw(Amount::from(6)),
+            Err(RejectReason::InsufficientFunds)
+        );
+        assert_balances(&account, 5, 5, 10);
+    }
+
+    #[test]
+    fn account_hold() {
+        let mut account = Account::new(1);
+        account
+            .deposit(Amount::from(10))
+            .expect("Failed to deposit");
+
+        account.hold(Amount::from(4)).expect("Failed to hold");
+
+        assert_balances(&account, 6, 4, 10);
+    }
+
+    #[test]
+    fn account_hold_withdrawn_funds_goes_negative() {
+        let mut account = Account::new(1);
+        account
+            .deposit(Amount::from(10))
+            .expect("Failed to deposit");
+        account
+            .withdraw(Amount::from(10))
+            .expect("Failed to withdraw");
+
+        account.hold(Amount::from(10)).expect("Failed to hold");
+
+        assert_balances(&account, -10, 10, 0);
+    }
+
+    #[test]
+    fn account_release() {
+        let mut account = Account::new(1);
+        account
+            .deposit(Amount::from(10))
+            .expect("Failed to deposit");
+        account.hold(Amount::from(4)).expect("Failed to hold");
+
+        account.release(Amount::from(3)).expect("Failed to release");
+
+        assert_balances(&account, 9, 1, 10);
+    }
+
+    #[test]
+    fn account_chargeback() {
+        let mut account = Account::new(1);
+        account
+            .deposit(Amount::from(10))
+            .expect("Failed to deposit");
+        account.hold(Amount::from(4)).expect("Failed to hold");
+
+        account.chargeback(Amount::from(4));
+
+        assert_balances(&account, 6, 0, 6);
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn new_accounts_is_empty() {
+        let accounts = Accounts::new();
+
+        assert!(accounts.accounts.is_empty());
+    }
+
+    #[test]
+    fn get_mut_account() {
+        let mut accounts = Accounts::new();
+
+        let account = accounts.get_mut(1);
+
+        assert_eq!(account.client, 1);
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, 0.into());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn get_mut_account_twice() {
+        let mut accounts = Accounts::new();
+
+        let account = accounts.get_mut(1);
+        account.available = Amount::from(100);
+
+        let account = accounts.get_mut(1);
+
+        assert_eq!(account.client, 1);
+        assert_eq!(account.available, Amount::from(100));
+        assert_eq!(account.held, 0.into());
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn get_mut_account_twice_different() {
+        let mut accounts = Accounts::new();
+
+        let account = accounts.get_mut(1);
+        account.available = Amount::from(100);
+
+        let account = accounts.get_mut(2);
+
+        assert_eq!(account.client, 2);
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, 0.into());
         assert!(!account.locked);
     }
+
+    #[test]
+    fn disputed_total_is_held() {
+        let mut account = Account::new(1);
+        account.held = Amount::from(5);
+
+        assert_eq!(account.disputed_total(), Amount::from(5));
+    }
+
+    #[test]
+    fn total_held_sums_all_accounts() {
+        let mut accounts = Accounts::new();
+
+        accounts.get_mut(1).held = Amount::from(5);
+        accounts.get_mut(2).held = Amount::from(3);
+        accounts.get_mut(3);
+
+        assert_eq!(accounts.total_held(), Amount::from(8));
+    }
+
+    #[test]
+    fn checksum_ignores_creation_order_and_trailing_zeros() {
+        let mut accounts = Accounts::new();
+        accounts
+            .get_mut(1)
+            .deposit(Amount::new(250, 2))
+            .expect("Failed to deposit");
+        accounts
+            .get_mut(2)
+            .deposit(Amount::from(3))
+            .expect("Failed to deposit");
+
+        let mut reordered = Accounts::new();
+        reordered
+            .get_mut(2)
+            .deposit(Amount::new(30, 1))
+            .expect("Failed to deposit");
+        reordered
+            .get_mut(1)
+            .deposit(Amount::new(25, 1))
+            .expect("Failed to deposit");
+
+        assert_eq!(accounts.checksum(), reordered.checksum());
+    }
+
+    #[test]
+    fn checksum_changes_with_state() {
+        let mut accounts = Accounts::new();
+        accounts
+            .get_mut(1)
+            .deposit(Amount::from(2))
+            .expect("Failed to deposit");
+        let checksum = accounts.checksum();
+
+        accounts
+            .get_mut(1)
+            .hold(Amount::from(1))
+            .expect("Failed to hold");
+        assert_ne!(accounts.checksum(), checksum);
+        let held = accounts.checksum();
+
+        accounts.get_mut(1).locked = true;
+        assert_ne!(accounts.checksum(), held);
+        assert_ne!(Accounts::new().checksum(), checksum);
+    }
+
+    #[test]
+    fn client_count_counts_distinct_clients() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1);
+        accounts.get_mut_in(1, Some("EUR"));
+        accounts.get_mut_in(3, Some("USD"));
+
+        assert_eq!(accounts.len(), 3);
+        assert_eq!(accounts.client_count(), 2);
+        assert!(accounts.contains_client(1));
+        assert!(!accounts.contains_client(2));
+        assert!(accounts.contains_client(3));
+    }
+
+    fn sort_fixture() -> Accounts {
+        let mut accounts = Accounts::new();
+
+        for (client, total, held) in [(1, 5, 0), (2, 10, 1), (3, 5, 3), (4, 1, 0)] {
+            let account = accounts.get_mut(client);
+            account.total = Amount::from(total);
+            account.available = Amount::from(total - held);
+            account.held = Amount::from(held);
+        }
+
+        accounts
+    }
+
+    fn written_clients(accounts: &Accounts, key: SortKey) -> Vec<String> {
+        let mut output = vec![];
+        accounts
+            .write_csv_sorted_by(key, &mut output)
+            .expect("Failed to write accounts");
+
+        String::from_utf8(output)
+            .expect("Output is not valid utf8")
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap_or_default().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn write_csv_sorted_by_client() {
+        let accounts = sort_fixture();
+
+        assert_eq!(
+            written_clients(&accounts, SortKey::Client),
+            ["1", "2", "3", "4"]
+        );
+    }
+
+    #[test]
+    fn write_csv_sorted_by_total() {
+        let accounts = sort_fixture();
+
+        assert_eq!(
+            written_clients(&accounts, SortKey::TotalAsc),
+            ["4", "1", "3", "2"]
+        );
+        assert_eq!(
+            written_clients(&accounts, SortKey::TotalDesc),
+            ["2", "1", "3", "4"]
+        );
+    }
+
+    #[test]
+    fn write_csv_sorted_by_held() {
+        let accounts = sort_fixture();
+
+        assert_eq!(
+            written_clients(&accounts, SortKey::HeldDesc),
+            ["3", "2", "1", "4"]
+        );
+    }
+
+    #[test]
+    fn insertion_order_survives_merge_and_binary_round_trip() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(4);
+        accounts.get_mut(2);
+
+        let mut other = Accounts::new();
+        other.get_mut(3);
+        other.get_mut(1);
+        accounts.merge(other).expect("Failed to merge accounts");
+
+        assert_eq!(
+            written_clients(&accounts, SortKey::InsertionOrder),
+            ["4", "2", "3", "1"]
+        );
+
+        let mut output = vec![];
+        accounts
+            .write_binary(&mut output)
+            .expect("Failed to write accounts");
+        let mut restored =
+            Accounts::read_binary(output.as_slice()).expect("Failed to read accounts");
+        restored.get_mut(0);
+
+        assert_eq!(
+            written_clients(&restored, SortKey::InsertionOrder),
+            ["4", "2", "3", "1", "0"]
+        );
+    }
+
+    #[test]
+    fn write_csv_writes_header_and_records() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::from(2);
+
+        let mut output = vec![];
+        accounts
+            .write_csv(&mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked\n1,2,0,0,false\n"
+        );
+    }
+
+    ///
+    /// Writer that records the length of the output at every flush that follows new output
+    ///
+    /// The csv writer also flushes when dropped, which is ignored since nothing new was written
+    ///
+    #[derive(Default)]
+    struct FlushRecorder {
+        output: Vec<u8>,
+        flushed_at: Vec<usize>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            if self.flushed_at.last() != Some(&self.output.len()) {
+                self.flushed_at.push(self.output.len());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_csv_flushes_periodically() {
+        let mut accounts = Accounts::new();
+        for client in 1..=5 {
+            accounts.get_mut(client);
+        }
+
+        let mut recorder = FlushRecorder::default();
+        accounts
+            .write_csv_with(&CsvOptions::new().with_flush_interval(2), &mut recorder)
+            .expect("Failed to write accounts");
+
+        // Header and two records, header and four records, then the final flush
+        let header = "client,available,held,total,locked\n".len();
+        let record = "1,0,0,0,false\n".len();
+        assert_eq!(
+            recorder.flushed_at,
+            [
+                header + 2 * record,
+                header + 4 * record,
+                header + 5 * record
+            ]
+        );
+    }
+
+    #[test]
+    fn write_csv_without_flush_interval_flushes_once() {
+        let mut accounts = Accounts::new();
+        for client in 1..=5 {
+            accounts.get_mut(client);
+        }
+
+        let mut recorder = FlushRecorder::default();
+        accounts
+            .write_csv_with(&CsvOptions::new().with_flush_interval(0), &mut recorder)
+            .expect("Failed to write accounts");
+
+        assert_eq!(recorder.flushed_at, [recorder.output.len()]);
+    }
+
+    #[test]
+    fn write_csv_dirty_writes_changed_accounts_once() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::from(1);
+        accounts.get_mut(2).available = Amount::from(2);
+        assert_eq!(accounts.changed_clients(), [1, 2]);
+
+        accounts
+            .write_csv_dirty(std::io::sink())
+            .expect("Failed to write accounts");
+        assert!(accounts.changed_clients().is_empty());
+
+        accounts.get_mut(2).available = Amount::from(3);
+
+        let mut output = vec![];
+        accounts
+            .write_csv_dirty(&mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked\n2,3,0,0,false\n"
+        );
+        assert!(accounts.changed_clients().is_empty());
+    }
+
+    #[test]
+    fn write_table_aligns_columns() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::new(15, 1);
+        accounts.get_mut(1).total = Amount::new(15, 1);
+        accounts.get_mut(12).held = Amount::from(100);
+        accounts.get_mut(12).total = Amount::from(100);
+        accounts.get_mut(12).locked = true;
+
+        let mut output = vec![];
+        accounts
+            .write_table(&mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "\
+client  available      held     total  status
+------  ---------  --------  --------  ------
+     1     1.5000    0.0000    1.5000
+    12     0.0000  100.0000  100.0000  LOCKED
+"
+        );
+    }
+
+    #[test]
+    fn write_table_with_omits_empty_and_marks_overdrawn_accounts() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::from(-2);
+        accounts.get_mut(1).total = Amount::from(-2);
+        accounts.get_mut(1).locked = true;
+        accounts.get_mut(2);
+
+        let mut output = vec![];
+        accounts
+            .write_table_with(
+                &CsvOptions::new().with_omit_empty(true).with_overdrawn(true),
+                &mut output,
+            )
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "\
+client  available    held    total  status
+------  ---------  ------  -------  ----------------
+     1    -2.0000  0.0000  -2.0000  LOCKED OVERDRAWN
+"
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "fixed-point"))]
+    fn write_table_with_rounds_with_strategy() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::new(100_005, 5);
+
+        let mut output = vec![];
+        accounts
+            .write_table_with(
+                &CsvOptions::new().with_rounding(Rounding::Truncate),
+                &mut output,
+            )
+            .expect("Failed to write accounts");
+
+        let output = String::from_utf8(output).expect("Output is not valid utf8");
+        assert!(
+            output.ends_with("     1     1.0000  0.0000  0.0000\n"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn get_mut_in_keeps_currencies_apart() {
+        let mut accounts = Accounts::new();
+
+        accounts.get_mut_in(1, Some("EUR")).available = Amount::from(5);
+        accounts.get_mut_in(1, Some("USD")).available = Amount::from(3);
+
+        let eur = accounts
+            .get_in(1, Some("EUR"))
+            .expect("Failed to get account");
+        assert_eq!(eur.available, Amount::from(5));
+        assert_eq!(eur.currency.as_deref(), Some("EUR"));
+
+        let usd = accounts
+            .get_in(1, Some("USD"))
+            .expect("Failed to get account");
+        assert_eq!(usd.available, Amount::from(3));
+
+        assert!(accounts.get(1).is_none());
+    }
+
+    #[test]
+    fn base_currency_is_used_without_currency() {
+        let mut accounts = Accounts::new().with_base_currency("EUR");
+
+        accounts.get_mut(1).available = Amount::from(5);
+
+        let account = accounts
+            .get_in(1, Some("EUR"))
+            .expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.currency.as_deref(), Some("EUR"));
+    }
+
+    #[test]
+    fn write_csv_with_currency_column() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::from(1);
+        accounts.get_mut_in(1, Some("EUR")).available = Amount::from(2);
+
+        let mut output = vec![];
+        accounts
+            .write_csv(&mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,currency,available,held,total,locked\n1,,1,0,0,false\n1,EUR,2,0,0,false\n"
+        );
+    }
+
+    #[test]
+    fn merge_disjoint_accounts() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::from(1);
+
+        let mut other = Accounts::new();
+        other.get_mut(2).available = Amount::from(2);
+        other.get_mut(3).available = Amount::from(3);
+
+        accounts.merge(other).expect("Failed to merge accounts");
+
+        assert_eq!(accounts.iter().count(), 3);
+        assert_eq!(
+            accounts.get(3).map(|account| account.available),
+            Some(Amount::from(3))
+        );
+    }
+
+    #[test]
+    fn client_count_follows_merge_and_clear() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1);
+        accounts.get_mut_in(1, Some("EUR"));
+        assert_eq!(accounts.client_count(), 1);
+
+        let mut other = Accounts::new();
+        other.get_mut(2);
+        other.get_mut_in(3, Some("EUR"));
+        accounts.merge(other).expect("Failed to merge accounts");
+        assert_eq!(accounts.client_count(), 3);
+        assert!(accounts.contains_client(3));
+
+        accounts.clear();
+        assert_eq!(accounts.client_count(), 0);
+        assert!(!accounts.contains_client(1));
+    }
+
+    #[test]
+    fn merge_overlapping_accounts_fails() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::from(1);
+
+        let mut other = Accounts::new();
+        other.get_mut(0);
+        other.get_mut(2);
+        other.get_mut_in(1, Some("EUR")).available = Amount::from(5);
+
+        let error = accounts
+            .merge(other)
+            .expect_err("Expected an error for overlapping clients");
+
+        assert!(matches!(error, PaymentsError::OverlappingClient(1)));
+        assert_eq!(accounts.iter().count(), 1);
+        assert_eq!(
+            accounts.get(1).map(|account| account.available),
+            Some(Amount::from(1))
+        );
+    }
+
+    #[test]
+    fn utilization_is_the_held_fraction_of_total() {
+        let mut account = Account::new(1);
+        assert_eq!(account.utilization(), None);
+
+        account.deposit(Amount::from(8)).expect("Failed to deposit");
+        assert_eq!(account.utilization(), Some(Decimal::ZERO));
+
+        account.hold(Amount::from(2)).expect("Failed to hold");
+        assert_eq!(account.utilization(), Some(Decimal::new(25, 2)));
+
+        account.hold(Amount::from(6)).expect("Failed to hold");
+        assert_eq!(account.utilization(), Some(Decimal::ONE));
+
+        // A negative total has no meaningful fraction
+        account.chargeback(Amount::from(9));
+        assert_eq!(account.utilization(), None);
+    }
+
+    #[test]
+    fn is_overdrawn_with_negative_available() {
+        let mut account = Account::new(1);
+        assert!(!account.is_overdrawn());
+
+        account.available = Amount::from(-10);
+        account.held = Amount::from(10);
+        assert!(account.is_overdrawn());
+    }
+
+    #[test]
+    fn write_csv_omits_empty_unlocked_accounts() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::from(2);
+        accounts.get_mut(1).total = Amount::from(2);
+        accounts.get_mut(2);
+        accounts.get_mut(3).locked = true;
+
+        let mut output = vec![];
+        accounts
+            .write_csv_with(&CsvOptions::new().with_omit_empty(true), &mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked\n1,2,0,2,false\n3,0,0,0,true\n"
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "fixed-point"))]
+    fn write_csv_rounds_with_strategy() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::new(12_345, 5);
+        accounts.get_mut(2).available = Amount::new(12_347, 5);
+
+        let written = |rounding| {
+            let mut output = vec![];
+            accounts
+                .write_csv_with(&CsvOptions::new().with_rounding(rounding), &mut output)
+                .expect("Failed to write accounts");
+
+            String::from_utf8(output)
+                .expect("Output is not valid utf8")
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').nth(1).unwrap_or_default().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(written(Rounding::HalfUp), ["0.1235", "0.1235"]);
+        assert_eq!(written(Rounding::HalfEven), ["0.1234", "0.1235"]);
+        assert_eq!(written(Rounding::Truncate), ["0.1234", "0.1234"]);
+        assert_eq!(
+            CsvOptions::default(),
+            CsvOptions::new().with_rounding(Rounding::HalfEven)
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "fixed-point"))]
+    fn write_csv_with_fixed_scale_pads_rounded_amounts() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::new(123_456, 5);
+        accounts.get_mut(1).held = Amount::new(5, 1);
+        accounts.get_mut(1).total = Amount::new(173_456, 5);
+
+        let mut output = vec![];
+        accounts
+            .write_csv_with(&CsvOptions::new().with_fixed_scale(true), &mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked\n1,1.2346,0.5000,1.7346,false\n"
+        );
+    }
+
+    #[test]
+    fn write_csv_with_overdrawn_column() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::from(2);
+        accounts.get_mut(2).available = Amount::from(-1);
+
+        let mut output = vec![];
+        accounts
+            .write_csv_with(&CsvOptions::new().with_overdrawn(true), &mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked,overdrawn\n1,2,0,0,false,false\n2,-1,0,0,false,true\n"
+        );
+    }
+
+    #[test]
+    fn activity_adds_tx_index_columns() {
+        let mut accounts = Accounts::new();
+        let account = accounts.get_mut(1);
+        account.available = Amount::from(2);
+        account.first_tx_index = 0;
+        account.last_tx_index = 3;
+
+        let mut output = vec![];
+        accounts
+            .write_csv_with(&CsvOptions::new().with_activity(true), &mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked,first_tx_index,last_tx_index\n1,2,0,0,false,0,3\n"
+        );
+    }
+
+    #[test]
+    fn locked_by_adds_column_empty_for_unlocked_accounts() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::from(2);
+        let account = accounts.get_mut(2);
+        account.locked = true;
+        account.locked_by = Some(7);
+
+        let mut output = vec![];
+        accounts
+            .write_csv_with(&CsvOptions::new().with_locked_by(true), &mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked,locked_by\n1,2,0,0,false,\n2,0,0,0,true,7\n"
+        );
+    }
+
+    #[test]
+    fn rejected_withdrawals_add_a_column() {
+        let mut accounts = Accounts::new();
+        accounts
+            .get_mut(1)
+            .deposit(Amount::from(2))
+            .expect("Failed to deposit");
+        accounts.get_mut(1).rejected_withdrawals = 3;
+
+        let mut output = Vec::new();
+        accounts
+            .write_csv_with(
+                &CsvOptions::new().with_rejected_withdrawals(true),
+                &mut output,
+            )
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked,rejected_withdrawals\n1,2,0,2,false,3\n"
+        );
+    }
+
+    #[test]
+    fn lifetime_totals_add_columns() {
+        let mut accounts = Accounts::new();
+        let account = accounts.get_mut(1);
+        account.deposit(Amount::from(2)).expect("Failed to deposit");
+        account.total_deposited = Amount::from(5);
+        account.total_withdrawn = Amount::from(3);
+
+        let mut output = vec![];
+        accounts
+            .write_csv_with(&CsvOptions::new().with_lifetime_totals(true), &mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked,total_deposited,total_withdrawn\n1,2,0,2,false,5,3\n"
+        );
+    }
+
+    ///
+    /// A csv record that fails to be serialized for client 2, to inject a serialization failure
+    ///
+    struct FailingRecord(u16);
+
+    impl Serialize for FailingRecord {
+        fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            use serde::ser::{Error, SerializeStruct};
+
+            if self.0 == 2 {
+                return Err(S::Error::custom("injected failure"));
+            }
+
+            let mut record = serializer.serialize_struct("FailingRecord", 1)?;
+            record.serialize_field("client", &self.0)?;
+            record.end()
+        }
+    }
+
+    fn write_failing_records(options: &CsvOptions) -> (Result<()>, String) {
+        let mut output = vec![];
+        let mut csv_writer = csv::Writer::from_writer(&mut output);
+
+        let result = (1..=3).try_for_each(|client| {
+            Accounts::write_record(&mut csv_writer, client, &FailingRecord(client), options)
+        });
+        drop(csv_writer);
+
+        (
+            result,
+            String::from_utf8(output).expect("Output is not valid utf8"),
+        )
+    }
+
+    #[test]
+    fn strict_serialize_fails_on_the_failing_account() {
+        let (result, _) = write_failing_records(&CsvOptions::new());
+
+        assert!(matches!(
+            result,
+            Err(PaymentsError::Serialize { client: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn lenient_serialize_skips_the_failing_account() {
+        static SKIPPED_CLIENT: AtomicU16 = AtomicU16::new(0);
+
+        let options = CsvOptions::new()
+            .with_strict_serialize(false)
+            .with_skipped_account_hook(|error| {
+                if let PaymentsError::Serialize { client, .. } = error {
+                    SKIPPED_CLIENT.store(*client, Ordering::Relaxed);
+                }
+            });
+        let (result, output) = write_failing_records(&options);
+
+        assert!(result.is_ok());
+        assert_eq!(output, "client\n1\n3\n");
+        assert_eq!(SKIPPED_CLIENT.load(Ordering::Relaxed), 2);
+        assert!(CsvOptions::default().strict_serialize);
+    }
+
+    #[test]
+    fn write_binary_round_trips() {
+        let mut accounts = Accounts::new();
+        accounts.get_mut(1).available = Amount::new(15, 1);
+        let account = accounts.get_mut_in(2, Some("EUR"));
+        account.held = Amount::from(3);
+        account.locked = true;
+        account.locked_by = Some(4);
+
+        let mut output = vec![];
+        accounts
+            .write_binary(&mut output)
+            .expect("Failed to write accounts");
+        assert_eq!(output[0], Accounts::BINARY_FORMAT_VERSION);
+
+        let read = Accounts::read_binary(output.as_slice()).expect("Failed to read accounts");
+        assert_eq!(
+            read.iter().collect::<Vec<_>>(),
+            accounts.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn read_binary_rejects_unknown_version() {
+        let result = Accounts::read_binary([Accounts::BINARY_FORMAT_VERSION + 1, 0].as_slice());
+
+        assert!(matches!(
+            result,
+            Err(PaymentsError::UnsupportedBinaryVersion(version)) if version == Accounts::BINARY_FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn filter_by_total_selects_accounts_in_range() {
+        let mut accounts = Accounts::new();
+        for (client, total) in [(3, 50), (1, 200), (2, 10), (4, 100)] {
+            accounts.get_mut(client).total = Amount::from(total);
+        }
+
+        let clients = |accounts: Vec<&Account>| {
+            accounts
+                .iter()
+                .map(|account| account.client)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            clients(accounts.filter_by_total(Amount::from(50), Amount::MAX)),
+            [1, 3, 4]
+        );
+        assert_eq!(
+            clients(accounts.filter_by_total(Amount::from(50), Amount::from(100))),
+            [3, 4]
+        );
+        assert_eq!(
+            clients(accounts.filter(|account| account.client % 2 == 0)),
+            [2, 4]
+        );
+    }
 }
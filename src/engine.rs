@@ -1,18 +1,91 @@
-use crate::account::Accounts;
-use crate::transaction::{Transactions, Type};
+use crate::account::{Account, Accounts, Balances};
+use crate::error::PaymentsError;
+use crate::money::{Amount, Money, Rounding};
+use crate::policy::{LockedPolicy, WithdrawalPolicy};
+use crate::report::{
+    BalanceEvent, BalanceField, HeldDiscrepancy, LedgerEntry, PartialFill, ProcessReport,
+    RejectReason, Rejection,
+};
+use crate::snapshot::EngineSnapshot;
+use crate::transaction::{Transaction, Transactions, Type};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{Read, Write};
 
 ///
 /// This struct is responsible for managing accounts and processing incoming transactions
+///
 /// It keeps track of the last processed transaction index so we don't have to reprocess all the transactions
 /// if we process multiple transactions files
 ///
+/// Disputes move funds differently depending on the type of the referenced transaction:
+///
+/// | Referenced | Dispute                       | Resolve                      | Chargeback                                 |
+/// |------------|-------------------------------|------------------------------|--------------------------------------------|
+/// | Deposit    | available -= a, held += a     | held -= a, available += a    | held -= a, total -= a, account locked      |
+/// | Withdrawal | held += a, total += a         | held -= a, total -= a        | held -= a, available += a, account locked  |
+///
+/// A disputed withdrawal provisionally credits the withdrawn funds while it is investigated,
+/// a resolve confirms the withdrawal and a chargeback gives the funds back to the client for good
+///
+#[allow(clippy::struct_excessive_bools)]
 pub struct Engine {
     accounts: Accounts,
     transactions: Transactions,
     last_processed_transaction_index: usize,
+    processed_per_type: [usize; Type::ALL.len()],
+    locked_policy: LockedPolicy,
+    withdrawal_policy: WithdrawalPolicy,
+    max_scale: u32,
+    max_transaction_amount: Option<Amount>,
+    strict_disputes: bool,
+    allow_dispute_resolution_when_locked: bool,
+    allow_direct_chargeback: bool,
+    max_open_disputes_per_client: Option<usize>,
+    max_clients: Option<usize>,
+    reject_zero_client: bool,
+    open_disputes: HashMap<u16, usize>,
+    withdrawal_fee_bps: u32,
+    fee_account: u16,
+    transaction_counts: HashMap<u16, u64>,
+    applied_transactions: Option<HashSet<u32>>,
+    client_filter: Option<Box<dyn Fn(u16) -> bool>>,
+    progress_hook: Option<ProgressHook>,
+    low_balance_hook: Option<LowBalanceHook>,
+    event_log: Option<Vec<BalanceEvent>>,
+    report: ProcessReport,
+}
+
+///
+/// A callback invoked every `every` processed transactions with the number of transactions processed so far
+///
+struct ProgressHook {
+    every: usize,
+    hook: Box<dyn FnMut(usize)>,
+}
+
+///
+/// A callback invoked with the client and the new available funds when a withdrawal leaves them below `threshold`
+///
+struct LowBalanceHook {
+    threshold: Amount,
+    hook: Box<dyn FnMut(u16, Amount)>,
 }
 
 impl Engine {
+    ///
+    /// The default maximum number of decimal places of deposit and withdrawal amounts
+    ///
+    pub const DEFAULT_MAX_SCALE: u32 = 4;
+
+    ///
+    /// Version of the snapshot format written by `Engine::save_snapshot`
+    ///
+    /// Like the binary accounts, the snapshot isn't self-describing, so it is bumped whenever a field of the snapshot
+    /// is added, removed or changed and snapshots of versions the engine doesn't know are rejected
+    ///
+    pub const SNAPSHOT_FORMAT_VERSION: u8 = 1;
+
     ///
     /// Creates a new Engine instance with a collection of accounts
     /// and an empty collection of transactions
@@ -22,335 +95,4819 @@ impl Engine {
             accounts,
             transactions: Transactions::default(),
             last_processed_transaction_index: 0,
+            processed_per_type: [0; Type::ALL.len()],
+            locked_policy: LockedPolicy::default(),
+            withdrawal_policy: WithdrawalPolicy::default(),
+            max_scale: Self::DEFAULT_MAX_SCALE,
+            max_transaction_amount: None,
+            strict_disputes: false,
+            allow_dispute_resolution_when_locked: false,
+            allow_direct_chargeback: false,
+            max_open_disputes_per_client: None,
+            max_clients: None,
+            reject_zero_client: false,
+            open_disputes: HashMap::new(),
+            withdrawal_fee_bps: 0,
+            fee_account: 0,
+            transaction_counts: HashMap::new(),
+            applied_transactions: None,
+            client_filter: None,
+            progress_hook: None,
+            low_balance_hook: None,
+            event_log: None,
+            report: ProcessReport::default(),
+        }
+    }
+
+    ///
+    /// Creates a new Engine instance like `Engine::new`, with room for `expected_transactions` transactions
+    /// so the stored transactions and their tx id index don't reallocate while processing them
+    ///
+    pub fn with_capacity(accounts: Accounts, expected_transactions: usize) -> Self {
+        Self {
+            transactions: Transactions::with_capacity(expected_transactions),
+            ..Self::new(accounts)
+        }
+    }
+
+    ///
+    /// Enables deduplication of deposits and withdrawals by tx id across `process` calls
+    /// A deposit or withdrawal whose tx id was already applied is skipped
+    ///
+    #[must_use]
+    pub fn with_dedup(mut self) -> Self {
+        self.applied_transactions = Some(HashSet::new());
+        self
+    }
+
+    ///
+    /// Sets the policy that decides which transactions are still applied to locked accounts
+    ///
+    #[must_use]
+    pub const fn with_locked_policy(mut self, locked_policy: LockedPolicy) -> Self {
+        self.locked_policy = locked_policy;
+        self
+    }
+
+    ///
+    /// Sets the policy that decides how withdrawals larger than the available funds are handled
+    ///
+    #[must_use]
+    pub const fn with_withdrawal_policy(mut self, withdrawal_policy: WithdrawalPolicy) -> Self {
+        self.withdrawal_policy = withdrawal_policy;
+        self
+    }
+
+    ///
+    /// Sets the maximum number of decimal places of deposit and withdrawal amounts, 4 by default
+    /// Amounts with more decimal places are rejected, trailing zeros are not counted
+    ///
+    #[must_use]
+    pub const fn with_max_scale(mut self, max_scale: u32) -> Self {
+        self.max_scale = max_scale;
+        self
+    }
+
+    ///
+    /// Rejects deposits and withdrawals with an amount above the given limit with `RejectReason::ExceedsLimit`
+    /// By default there is no limit
+    ///
+    #[must_use]
+    pub const fn with_max_transaction_amount(mut self, max_transaction_amount: Amount) -> Self {
+        self.max_transaction_amount = Some(max_transaction_amount);
+        self
+    }
+
+    ///
+    /// Limits how many disputes a client can have open at the same time,
+    /// a dispute above the limit is rejected with `RejectReason::TooManyDisputes`
+    /// A dispute stops counting once it is fully resolved or charged back, by default there is no limit
+    ///
+    #[must_use]
+    pub const fn with_max_open_disputes_per_client(mut self, max_open_disputes: usize) -> Self {
+        self.max_open_disputes_per_client = Some(max_open_disputes);
+        self
+    }
+
+    ///
+    /// Limits how many distinct clients can have an account, a transaction of a new client above the limit
+    /// is rejected with `RejectReason::TooManyClients` instead of creating its account
+    /// The transactions of existing clients are unaffected, as is the fee account credited by withdrawal fees
+    /// By default there is no limit
+    ///
+    /// With a `SharedEngine` every shard has its own engine, so the limit applies per shard
+    ///
+    #[must_use]
+    pub const fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = Some(max_clients);
+        self
+    }
+
+    ///
+    /// Rejects the transactions of client id `0` with `RejectReason::ZeroClient`, for upstream systems that use it
+    /// as a sentinel for a missing client, as well as transfers to client `0`
+    /// By default client `0` is a client like any other
+    ///
+    #[must_use]
+    pub const fn with_reject_zero_client(mut self) -> Self {
+        self.reject_zero_client = true;
+        self
+    }
+
+    ///
+    /// Charges a fee of `withdrawal_fee_bps` basis points (hundredths of a percent) on every withdrawal
+    /// and credits it to the account of the `fee_account` client, in the currency of the withdrawal
+    ///
+    /// The fee is charged on top of the withdrawn amount, e.g. withdrawing 100 at 50 bps takes 100.5 from
+    /// the available funds, which must cover both. A partially filled withdrawal pays the fee on what it withdrew
+    /// Fees are rounded half-even to the engine's max scale, by default no fee is charged
+    ///
+    #[must_use]
+    pub const fn with_withdrawal_fee(mut self, withdrawal_fee_bps: u32, fee_account: u16) -> Self {
+        self.withdrawal_fee_bps = withdrawal_fee_bps;
+        self.fee_account = fee_account;
+        self
+    }
+
+    ///
+    /// Applies resolves and chargebacks of already held funds on locked accounts regardless of the locked policy,
+    /// so open disputes can be wound down after a chargeback locked the account
+    /// New disputes are still subject to the locked policy
+    ///
+    #[must_use]
+    pub const fn with_dispute_resolution_when_locked(mut self) -> Self {
+        self.allow_dispute_resolution_when_locked = true;
+        self
+    }
+
+    ///
+    /// Lets a chargeback of a transaction that isn't disputed charge it back directly, as if it was disputed right before
+    /// A direct chargeback of a deposit takes the funds out of the available funds, which may leave them negative
+    /// By default a chargeback without a prior dispute is ignored, or rejected with strict disputes enabled
+    ///
+    #[must_use]
+    pub const fn with_direct_chargeback(mut self) -> Self {
+        self.allow_direct_chargeback = true;
+        self
+    }
+
+    ///
+    /// Records every change of the available, held or total funds of an account in an event log, see `Engine::event_log`
+    /// The log is off by default since it grows with every processed transaction
+    ///
+    #[must_use]
+    pub fn with_event_log(mut self) -> Self {
+        self.event_log = Some(vec![]);
+        self
+    }
+
+    ///
+    /// Rejects resolves and chargebacks referencing a transaction that isn't disputed with `RejectReason::NotDisputed`,
+    /// or with `RejectReason::ChargedBack` if the transaction was already charged back
+    /// By default they are silently ignored
+    ///
+    #[must_use]
+    pub const fn with_strict_disputes(mut self) -> Self {
+        self.strict_disputes = true;
+        self
+    }
+
+    ///
+    /// Only processes the transactions of clients for which the filter returns true
+    /// Transactions of other clients are skipped entirely, no account is created for them
+    ///
+    #[must_use]
+    pub fn with_client_filter(mut self, filter: impl Fn(u16) -> bool + 'static) -> Self {
+        self.client_filter = Some(Box::new(filter));
+        self
+    }
+
+    ///
+    /// Sets a hook invoked every `every` processed transactions with the number of transactions processed so far,
+    /// e.g. to render a progress bar or log periodically while processing large inputs
+    /// The count includes the transactions of previous `process` calls, an `every` of zero is treated as one
+    ///
+    /// Like the client filter, the hook isn't saved in snapshots
+    ///
+    pub fn set_progress_hook(&mut self, every: usize, hook: impl FnMut(usize) + 'static) {
+        self.progress_hook = Some(ProgressHook {
+            every: every.max(1),
+            hook: Box::new(hook),
+        });
+    }
+
+    ///
+    /// Sets a hook invoked with the client and its new available funds whenever a withdrawal
+    /// leaves the available funds below `threshold`, e.g. to alert on accounts running low
+    /// Withdrawals that don't change the balance (rejected, or partially filled with nothing available) don't fire it
+    ///
+    /// Like the progress hook, the hook isn't saved in snapshots
+    ///
+    pub fn set_low_balance_hook(
+        &mut self,
+        threshold: Amount,
+        hook: impl FnMut(u16, Amount) + 'static,
+    ) {
+        self.low_balance_hook = Some(LowBalanceHook {
+            threshold,
+            hook: Box::new(hook),
+        });
+    }
+
+    pub const fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    ///
+    /// Consumes the engine and returns its accounts, e.g. to merge the accounts of several engines
+    ///
+    pub fn into_accounts(self) -> Accounts {
+        self.accounts
+    }
+
+    ///
+    /// Consumes the engine and returns its accounts and its process report
+    ///
+    pub fn into_parts(self) -> (Accounts, ProcessReport) {
+        (self.accounts, self.report)
+    }
+
+    ///
+    /// Returns the number of accounts, see `Accounts::len`
+    ///
+    pub fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    ///
+    /// Returns the number of locked accounts, see `Accounts::locked_count`
+    ///
+    pub fn locked_count(&self) -> usize {
+        self.accounts.locked_count()
+    }
+
+    ///
+    /// Returns the accounts mutably, e.g. to write only the accounts that changed with `Accounts::write_csv_dirty`
+    ///
+    pub const fn accounts_mut(&mut self) -> &mut Accounts {
+        &mut self.accounts
+    }
+
+    ///
+    /// Returns the ids of the clients whose accounts changed since the accounts were last written with
+    /// `Accounts::write_csv_dirty`, in ascending order
+    ///
+    pub fn changed_clients(&self) -> Vec<u16> {
+        self.accounts.changed_clients()
+    }
+
+    ///
+    /// Returns the stored transactions, including the ones still waiting to be processed, for auditing
+    ///
+    pub const fn transactions(&self) -> &Transactions {
+        &self.transactions
+    }
+
+    ///
+    /// Returns the report of all transactions rejected or partially filled so far
+    ///
+    pub const fn report(&self) -> &ProcessReport {
+        &self.report
+    }
+
+    ///
+    /// Returns the balance changes recorded in processing order, empty unless the event log is enabled
+    ///
+    /// Replaying the events of an account from zero reconstructs its available, held and total funds,
+    /// fees credited to the fee account are recorded with the withdrawal that charged them
+    /// and the events of a transaction undone with `Engine::undo_last` are removed
+    ///
+    /// The only balance changes not recorded are those of `Engine::resolve_all_disputes`,
+    /// which aren't caused by a transaction
+    ///
+    pub fn event_log(&self) -> &[BalanceEvent] {
+        self.event_log.as_deref().unwrap_or_default()
+    }
+
+    ///
+    /// Returns the number of transactions applied to a client's account
+    /// Only deposits and withdrawals that actually changed the balance are counted, skipped transactions
+    /// (locked account, insufficient funds, ...), zero amounts, transfers and dispute related transactions are not
+    ///
+    pub fn transaction_count(&self, client: u16) -> u64 {
+        self.transaction_counts.get(&client).copied().unwrap_or(0)
+    }
+
+    ///
+    /// Returns all the transactions of a client that are currently flagged as disputed
+    /// The transactions are returned in the order they were processed
+    ///
+    pub fn disputed_transactions(&self, client: u16) -> Vec<&Transaction> {
+        self.transactions
+            .iter()
+            .filter(|transaction| transaction.client == client && transaction.disputed)
+            .collect()
+    }
+
+    ///
+    /// Returns the current dispute state of the transaction with the given id,
+    /// or `None` if no deposit or withdrawal with that id was processed
+    ///
+    pub fn is_disputed(&self, tx: u32) -> Option<bool> {
+        self.transactions.is_disputed(tx)
+    }
+
+    ///
+    /// Returns the engine metrics in the Prometheus text exposition format, so an embedding service can expose them
+    ///
+    /// - `toy_payments_transactions_processed_total` (counter): transactions processed, including rejected ones
+    /// - `toy_payments_transactions_total{type="..."}` (counter): transactions processed per transaction type
+    /// - `toy_payments_transactions_rejected_total` (counter): transactions rejected
+    /// - `toy_payments_accounts_locked` (gauge): accounts currently locked
+    /// - `toy_payments_held_funds{currency="..."}` (gauge): funds currently held per currency,
+    ///   an empty label being the unspecified currency
+    ///
+    pub fn metrics_text(&self) -> String {
+        let locked = self
+            .accounts
+            .iter()
+            .filter(|account| account.locked)
+            .count();
+        let mut held = BTreeMap::new();
+        for account in self.accounts.iter() {
+            *held
+                .entry(account.currency.as_deref().unwrap_or_default())
+                .or_insert(Amount::ZERO) += account.held;
+        }
+
+        let mut metrics = String::new();
+        Self::write_metric(
+            &mut metrics,
+            "transactions_processed_total",
+            "counter",
+            "Transactions processed by the engine, including rejected ones",
+            &[(None, self.last_processed_transaction_index.to_string())],
+        );
+        Self::write_metric(
+            &mut metrics,
+            "transactions_total",
+            "counter",
+            "Transactions processed by the engine per type",
+            &Type::ALL.map(|r#type| {
+                let count = self.processed_per_type[r#type as usize];
+                (Some(("type", r#type.as_str())), count.to_string())
+            }),
+        );
+        Self::write_metric(
+            &mut metrics,
+            "transactions_rejected_total",
+            "counter",
+            "Transactions rejected by the engine",
+            &[(None, self.report.rejected.len().to_string())],
+        );
+        Self::write_metric(
+            &mut metrics,
+            "accounts_locked",
+            "gauge",
+            "Accounts currently locked by a chargeback",
+            &[(None, locked.to_string())],
+        );
+        Self::write_metric(
+            &mut metrics,
+            "held_funds",
+            "gauge",
+            "Funds currently held by open disputes across all accounts, per currency",
+            &held
+                .into_iter()
+                .map(|(currency, amount)| (Some(("currency", currency)), amount.to_string()))
+                .collect::<Vec<_>>(),
+        );
+
+        metrics
+    }
+
+    ///
+    /// Appends a metric with its help and type lines, and one sample per optional label name and value
+    ///
+    fn write_metric(
+        metrics: &mut String,
+        name: &str,
+        kind: &str,
+        help: &str,
+        samples: &[(Option<(&str, &str)>, String)],
+    ) {
+        // Writing to a String can't fail
+        let _ = writeln!(metrics, "# HELP toy_payments_{name} {help}");
+        let _ = writeln!(metrics, "# TYPE toy_payments_{name} {kind}");
+        for (label, value) in samples {
+            let _ = match label {
+                Some((label, label_value)) => writeln!(
+                    metrics,
+                    "toy_payments_{name}{{{label}=\"{label_value}\"}} {value}"
+                ),
+                None => writeln!(metrics, "toy_payments_{name} {value}"),
+            };
+        }
+    }
+
+    ///
+    /// Writes the full state of the engine to the given writer, so processing can be resumed later
+    /// with `Engine::load_snapshot`
+    ///
+    /// The client filter can't be serialized, it has to be set again on the restored engine
+    /// The snapshot is a [`Engine::SNAPSHOT_FORMAT_VERSION`] byte followed by the bincode encoded state
+    ///
+    /// # Errors
+    ///
+    /// If the snapshot fails to be serialized or written to the writer
+    ///
+    pub fn save_snapshot<W: Write>(&self, mut writer: W) -> crate::Result<()> {
+        let snapshot = EngineSnapshot {
+            accounts: self.accounts.snapshot(),
+            transactions: self.transactions.snapshot(),
+            last_processed_transaction_index: self.last_processed_transaction_index,
+            locked_policy: self.locked_policy,
+            withdrawal_policy: self.withdrawal_policy,
+            max_scale: self.max_scale,
+            max_transaction_amount: self.max_transaction_amount,
+            strict_disputes: self.strict_disputes,
+            allow_dispute_resolution_when_locked: self.allow_dispute_resolution_when_locked,
+            allow_direct_chargeback: self.allow_direct_chargeback,
+            max_open_disputes_per_client: self.max_open_disputes_per_client,
+            max_clients: self.max_clients,
+            reject_zero_client: self.reject_zero_client,
+            open_disputes: self.open_disputes.clone(),
+            withdrawal_fee_bps: self.withdrawal_fee_bps,
+            fee_account: self.fee_account,
+            transaction_counts: self.transaction_counts.clone(),
+            applied_transactions: self.applied_transactions.clone(),
+            rejected: self.report.rejected.clone(),
+            partial_fills: self.report.partial_fills.clone(),
+            held_discrepancies: self.report.held_discrepancies.clone(),
+            dropped_due_to_lock: self.report.dropped_due_to_lock.clone(),
+            event_log: self.event_log.clone(),
+        };
+
+        writer.write_all(&[Self::SNAPSHOT_FORMAT_VERSION])?;
+        bincode::serialize_into(writer, &snapshot).map_err(PaymentsError::Snapshot)
+    }
+
+    ///
+    /// Restores an engine from a snapshot written by `Engine::save_snapshot`
+    /// New transactions processed by the restored engine continue where the saved engine stopped
+    ///
+    /// # Errors
+    ///
+    /// If the version byte is not [`Engine::SNAPSHOT_FORMAT_VERSION`], or the snapshot fails to be read
+    /// from the reader or is not a valid snapshot
+    ///
+    pub fn load_snapshot<R: Read>(mut reader: R) -> crate::Result<Self> {
+        let mut version = [0; 1];
+        reader.read_exact(&mut version)?;
+        if version[0] != Self::SNAPSHOT_FORMAT_VERSION {
+            return Err(PaymentsError::UnsupportedSnapshotVersion(version[0]));
+        }
+
+        let snapshot: EngineSnapshot =
+            bincode::deserialize_from(reader).map_err(PaymentsError::Snapshot)?;
+
+        // The counters per type aren't part of the snapshot, they are recounted once from the processed transactions
+        let transactions = Transactions::from_snapshot(snapshot.transactions);
+        let mut processed_per_type = [0; Type::ALL.len()];
+        for transaction in transactions
+            .iter()
+            .take(snapshot.last_processed_transaction_index)
+        {
+            processed_per_type[transaction.r#type as usize] += 1;
+        }
+
+        Ok(Self {
+            accounts: Accounts::from_snapshot(snapshot.accounts),
+            transactions,
+            last_processed_transaction_index: snapshot.last_processed_transaction_index,
+            processed_per_type,
+            locked_policy: snapshot.locked_policy,
+            withdrawal_policy: snapshot.withdrawal_policy,
+            max_scale: snapshot.max_scale,
+            max_transaction_amount: snapshot.max_transaction_amount,
+            strict_disputes: snapshot.strict_disputes,
+            allow_dispute_resolution_when_locked: snapshot.allow_dispute_resolution_when_locked,
+            allow_direct_chargeback: snapshot.allow_direct_chargeback,
+            max_open_disputes_per_client: snapshot.max_open_disputes_per_client,
+            max_clients: snapshot.max_clients,
+            reject_zero_client: snapshot.reject_zero_client,
+            open_disputes: snapshot.open_disputes,
+            withdrawal_fee_bps: snapshot.withdrawal_fee_bps,
+            fee_account: snapshot.fee_account,
+            transaction_counts: snapshot.transaction_counts,
+            applied_transactions: snapshot.applied_transactions,
+            client_filter: None,
+            progress_hook: None,
+            low_balance_hook: None,
+            event_log: snapshot.event_log,
+            report: ProcessReport {
+                rejected: snapshot.rejected,
+                partial_fills: snapshot.partial_fills,
+                held_discrepancies: snapshot.held_discrepancies,
+                dropped_due_to_lock: snapshot.dropped_due_to_lock,
+            },
+        })
+    }
+
+    ///
+    /// Clears the accounts, the stored transactions and everything recorded while processing them (dispute state,
+    /// transaction counts, applied tx ids, event log and report), so the engine can process an independent input
+    /// as if it was new
+    ///
+    /// The configuration, client filter and hooks are kept, and the collections are cleared rather than dropped
+    /// so their allocations are reused
+    ///
+    pub fn reset(&mut self) {
+        self.accounts.clear();
+        self.transactions.clear();
+        self.last_processed_transaction_index = 0;
+        self.processed_per_type = [0; Type::ALL.len()];
+        self.open_disputes.clear();
+        self.transaction_counts.clear();
+        if let Some(applied_transactions) = &mut self.applied_transactions {
+            applied_transactions.clear();
+        }
+        if let Some(event_log) = &mut self.event_log {
+            event_log.clear();
+        }
+        self.report.rejected.clear();
+        self.report.partial_fills.clear();
+        self.report.held_discrepancies.clear();
+        self.report.dropped_due_to_lock.clear();
+    }
+
+    ///
+    /// Processes a new collection of transactions.
+    ///
+    /// Transactions are applied strictly in the order of the collection, which is the file order
+    /// unless it was sorted with `Transactions::sort_by_time`, there is no reordering or tie-break by tx id
+    /// The order only matters between transactions of the same client (e.g. a resolve before its dispute is ignored),
+    /// the transactions of different clients never affect each other
+    ///
+    pub fn process(&mut self, trxs: Transactions) {
+        self.transactions.extend(trxs);
+        self.process_until(self.transactions.len());
+    }
+
+    ///
+    /// Processes a new collection of transactions like `Engine::process`, but stops after `max_count` transactions
+    /// and returns how many were processed
+    ///
+    /// The remaining transactions stay stored, so a later call (e.g. with an empty collection) resumes
+    /// with the first transaction that wasn't processed yet
+    ///
+    pub fn process_limited(&mut self, trxs: Transactions, max_count: usize) -> usize {
+        self.transactions.extend(trxs);
+
+        let start = self.last_processed_transaction_index;
+        let end = self.transactions.len().min(start.saturating_add(max_count));
+        self.process_until(end);
+
+        end.saturating_sub(start)
+    }
+
+    ///
+    /// Processes a single transaction, which is appended to the stored transactions
+    /// This is useful to step the engine one transaction at a time and inspect the accounts in between
+    ///
+    pub fn process_one(&mut self, transaction: Transaction) {
+        self.transactions.push(transaction);
+        self.process_until(self.transactions.len());
+    }
+
+    ///
+    /// Processes the transactions of an iterator one by one, e.g. a generated stream, without collecting them first
+    /// Each transaction is appended to the stored transactions and indexed as it arrives
+    ///
+    pub fn process_iter(&mut self, iter: impl IntoIterator<Item = Transaction>) {
+        for transaction in iter {
+            self.process_one(transaction);
+        }
+    }
+
+    ///
+    /// Processes the transactions of an async stream in the order they arrive, e.g. a channel fed by network I/O
+    /// The engine stays synchronous, each transaction is processed like `Engine::process_one` as soon as it is received
+    /// The engine isn't `Send` because of its hooks, so neither is the returned future and it has to be awaited on the thread that owns the engine
+    ///
+    #[cfg(feature = "async")]
+    #[allow(clippy::future_not_send)]
+    pub async fn process_stream(&mut self, stream: impl futures_util::Stream<Item = Transaction>) {
+        use futures_util::StreamExt;
+
+        let mut stream = std::pin::pin!(stream);
+        while let Some(transaction) = stream.next().await {
+            self.process_one(transaction);
+        }
+    }
+
+    ///
+    /// Processes a new collection of transactions in batches of `chunk_size` transactions.
+    /// The callback is invoked with the accounts state after each batch, which allows snapshotting partial state
+    /// A `chunk_size` of zero is treated as one
+    ///
+    pub fn process_chunked(
+        &mut self,
+        trxs: Transactions,
+        chunk_size: usize,
+        mut on_chunk: impl FnMut(&Accounts),
+    ) {
+        self.transactions.extend(trxs);
+
+        let chunk_size = chunk_size.max(1);
+        while self.last_processed_transaction_index < self.transactions.len() {
+            let end = self
+                .transactions
+                .len()
+                .min(self.last_processed_transaction_index + chunk_size);
+
+            self.process_until(end);
+            on_chunk(&self.accounts);
         }
     }
 
-    pub const fn accounts(&self) -> &Accounts {
-        &self.accounts
+    ///
+    /// Recomputes the account of a client from scratch by replaying only its processed transactions,
+    /// and the disputes, resolves and chargebacks referencing them, on a fresh engine with the same configuration
+    ///
+    /// This is meant for dispute investigations, the engine state isn't changed. The account is the one in the
+    /// base currency, with the tx indexes of the engine's transaction store. Withdrawal fees credited to the client
+    /// by other clients' withdrawals are not replayed, so the fee account is only recomputed from its own history
+    ///
+    pub fn recompute_client(&self, client: u16) -> Account {
+        let (indexes, history) = self.client_history(client);
+        let mut engine = self.replay_engine();
+        engine.process(Transactions::from(history));
+
+        // Translate the tx indexes of the replay back to the engine's transaction store
+        let mut account = engine
+            .accounts
+            .get(client)
+            .cloned()
+            .unwrap_or_else(|| Account::new(client));
+        account.first_tx_index = indexes
+            .get(account.first_tx_index)
+            .copied()
+            .unwrap_or_default();
+        account.last_tx_index = indexes
+            .get(account.last_tx_index)
+            .copied()
+            .unwrap_or_default();
+
+        account
+    }
+
+    ///
+    /// Returns the processed transactions of a client, and the disputes, resolves and chargebacks referencing them,
+    /// each annotated with the balances of the client's account right after it was applied, e.g. for statements
+    ///
+    /// The running balances are computed by replaying the history like `Engine::recompute_client`
+    ///
+    pub fn client_ledger(&self, client: u16) -> Vec<LedgerEntry<'_>> {
+        let (indexes, history) = self.client_history(client);
+        let mut engine = self.replay_engine();
+
+        indexes
+            .into_iter()
+            .zip(history)
+            .filter_map(|(index, transaction)| {
+                engine.process_one(transaction);
+
+                let (available, held, total, locked) = engine
+                    .accounts
+                    .get(client)
+                    .map(Account::balances)
+                    .unwrap_or_default();
+
+                Some(LedgerEntry {
+                    index,
+                    transaction: self.transactions.get(index)?,
+                    available,
+                    held,
+                    total,
+                    locked,
+                })
+            })
+            .collect()
+    }
+
+    ///
+    /// Returns the processed transactions of a client, and the disputes, resolves and chargebacks referencing them,
+    /// with their indexes in the engine's transaction store
+    /// The transactions are copied without their dispute state, which is rebuilt when they are replayed
+    ///
+    fn client_history(&self, client: u16) -> (Vec<usize>, Vec<Transaction>) {
+        let belongs_to_client = |transaction: &Transaction| {
+            let references_client = || {
+                self.transactions
+                    .get_tx(transaction.tx)
+                    .is_some_and(|referenced| referenced.client == client)
+            };
+
+            match transaction.r#type {
+                Type::Deposit | Type::Withdrawal => transaction.client == client,
+                Type::Transfer => {
+                    transaction.client == client || transaction.dest_client == Some(client)
+                }
+                Type::Dispute | Type::Resolve | Type::Chargeback => {
+                    transaction.client == client || references_client()
+                }
+            }
+        };
+        let rejected_indexes = self
+            .report
+            .rejected
+            .iter()
+            .map(|rejection| rejection.index)
+            .collect::<HashSet<_>>();
+
+        self.transactions
+            .iter()
+            .take(self.last_processed_transaction_index)
+            .enumerate()
+            .filter(|(_, transaction)| belongs_to_client(transaction))
+            .map(|(index, transaction)| {
+                let mut transaction = Transaction {
+                    disputed: false,
+                    ..transaction.clone()
+                };
+
+                // The funds of the sender aren't replayed, so a received transfer is replayed as a deposit
+                // unless it was rejected, a rejected one is rejected again for lack of funds
+                let rejected = rejected_indexes.contains(&index);
+                if transaction.r#type == Type::Transfer && transaction.client != client && !rejected
+                {
+                    transaction = Transaction {
+                        r#type: Type::Deposit,
+                        client,
+                        dest_client: None,
+                        ..transaction
+                    };
+                }
+
+                (index, transaction)
+            })
+            .unzip()
+    }
+
+    ///
+    /// Creates an empty engine with the same configuration, to replay part of the stored transactions
+    /// Hooks, the client filter and the event log are not copied
+    ///
+    fn replay_engine(&self) -> Self {
+        let mut accounts = Accounts::new();
+        if let Some(base_currency) = self.accounts.base_currency() {
+            accounts = accounts.with_base_currency(base_currency);
+        }
+
+        Self {
+            locked_policy: self.locked_policy,
+            withdrawal_policy: self.withdrawal_policy,
+            max_scale: self.max_scale,
+            max_transaction_amount: self.max_transaction_amount,
+            strict_disputes: self.strict_disputes,
+            allow_dispute_resolution_when_locked: self.allow_dispute_resolution_when_locked,
+            allow_direct_chargeback: self.allow_direct_chargeback,
+            max_open_disputes_per_client: self.max_open_disputes_per_client,
+            max_clients: self.max_clients,
+            reject_zero_client: self.reject_zero_client,
+            withdrawal_fee_bps: self.withdrawal_fee_bps,
+            fee_account: self.fee_account,
+            applied_transactions: self.applied_transactions.as_ref().map(|_| HashSet::new()),
+            ..Self::new(accounts)
+        }
+    }
+
+    ///
+    /// Resolves every open dispute at once, e.g. to reconcile at the end of a batch, and returns how many were resolved
+    ///
+    /// Each dispute is resolved like a full resolve of its transaction on the account of the client that disputed it,
+    /// which holds the funds: a disputed deposit gives the held funds back and a disputed withdrawal takes them back out
+    /// Locked accounts are resolved too, since the disputes are settled by the engine rather than by the client
+    ///
+    /// The released funds aren't recorded in the event log, there is no transaction to attribute them to
+    ///
+    pub fn resolve_all_disputes(&mut self) -> usize {
+        let mut resolved = 0;
+        let disputed = self
+            .transactions
+            .iter()
+            .filter(|transaction| transaction.disputed)
+            .map(|transaction| transaction.tx)
+            .collect::<Vec<_>>();
+
+        for &tx_id in &disputed {
+            let (Some(transaction), Some(held), Some(client)) = (
+                self.transactions.get_tx(tx_id),
+                self.transactions.held_amount(tx_id),
+                self.transactions.disputing_client(tx_id),
+            ) else {
+                continue;
+            };
+
+            let account = self
+                .accounts
+                .get_mut_in(client, transaction.currency.as_deref());
+
+            // The held funds of an account never go negative, even if they don't cover the dispute
+            let released = held.min(account.held.max(Amount::ZERO));
+            if self.transactions.is_withdrawal(tx_id) {
+                account.release_withdrawal(released);
+            } else if account.release(released).is_err() {
+                continue;
+            }
+
+            self.transactions.release_held(tx_id, held);
+            if let Some(open_disputes) = self.open_disputes.get_mut(&client) {
+                *open_disputes = open_disputes.saturating_sub(1);
+            }
+            resolved += 1;
+        }
+
+        resolved
+    }
+
+    ///
+    /// Undoes the last processed transaction, removing it from the stored transactions and reversing its effect
+    /// Can be called repeatedly to step back one transaction at a time
+    /// Stored transactions that weren't processed yet (e.g. past the limit of `Engine::process_limited`) are kept
+    ///
+    /// Only deposits and withdrawals are reversible: an applied deposit is subtracted again, an applied withdrawal
+    /// is added back (only the withdrawn amount of a partial fill), and a rejected or skipped one just has its
+    /// rejection or partial fill removed from the report, a withdrawal rejected for insufficient funds also
+    /// no longer counts in the rejected withdrawals of its account
+    ///
+    /// Disputes, resolves and chargebacks aren't reversible since they move funds between the
+    /// disputed transaction and the account, and a chargeback locks the account, neither are transfers
+    /// since they change two accounts
+    ///
+    /// The balance events of the undone transaction are removed from the event log as well,
+    /// and so are the accounts it created (the account of a new client or the fee account)
+    ///
+    /// # Errors
+    ///
+    /// Returns `PaymentsError::NothingToUndo` if no transaction was processed,
+    /// or `PaymentsError::NotReversible` if the last transaction is a dispute, resolve, chargeback or transfer
+    ///
+    pub fn undo_last(&mut self) -> crate::Result<()> {
+        let Some(index) = self.last_processed_transaction_index.checked_sub(1) else {
+            return Err(PaymentsError::NothingToUndo);
+        };
+        let Some(transaction) = self.transactions.get(index) else {
+            return Err(PaymentsError::NothingToUndo);
+        };
+
+        let r#type = transaction.r#type;
+        if !matches!(r#type, Type::Deposit | Type::Withdrawal) {
+            return Err(PaymentsError::NotReversible { index, r#type });
+        }
+
+        // A deposit or withdrawal is applied unless it was rejected or skipped without a rejection,
+        // which only happens for excluded clients, transactions flagged as disputed and missing amounts
+        let rejected = self
+            .report
+            .rejected
+            .last()
+            .is_some_and(|rejection| rejection.index == index);
+        let excluded = self
+            .client_filter
+            .as_ref()
+            .is_some_and(|filter| !filter(transaction.client));
+
+        if rejected {
+            match self.report.rejected.pop().map(|rejection| rejection.reason) {
+                Some(RejectReason::AccountLocked) => {
+                    self.report.dropped_due_to_lock.pop();
+                }
+                // Only count the rejected withdrawal back if its account exists, without creating it
+                Some(RejectReason::InsufficientFunds) => {
+                    let currency = transaction.currency.as_deref();
+                    if self.accounts.get_in(transaction.client, currency).is_some() {
+                        let account = self.accounts.get_mut_in(transaction.client, currency);
+                        account.rejected_withdrawals =
+                            account.rejected_withdrawals.saturating_sub(1);
+                    }
+                }
+                _ => {}
+            }
+        } else if let Some(amount) = transaction
+            .amount
+            .filter(|_| !excluded && !transaction.disputed)
+        {
+            let client = transaction.client;
+            let account = self
+                .accounts
+                .get_mut_in(client, transaction.currency.as_deref());
+
+            let applied = if r#type == Type::Deposit {
+                account.reverse_deposit(amount);
+                account.total_deposited -= amount;
+                amount
+            } else {
+                // A partially filled withdrawal only withdrew part of the requested amount
+                let withdrawn = self
+                    .report
+                    .partial_fills
+                    .last()
+                    .filter(|partial_fill| partial_fill.index == index)
+                    .map_or(amount, |partial_fill| partial_fill.withdrawn);
+                account.total_withdrawn -= withdrawn;
+
+                // Give the fee back too, it was charged on the withdrawn amount
+                let fee = Self::withdrawal_fee(self.withdrawal_fee_bps, self.max_scale, withdrawn);
+                account.reverse_withdrawal(withdrawn + fee);
+                if fee > Amount::ZERO {
+                    self.accounts
+                        .get_mut_in(self.fee_account, transaction.currency.as_deref())
+                        .reverse_deposit(fee);
+                    self.accounts.remove_created_by(
+                        self.fee_account,
+                        transaction.currency.as_deref(),
+                        index,
+                    );
+                }
+                withdrawn
+            };
+
+            // An account created by the undone transaction is removed again
+            self.accounts
+                .remove_created_by(client, transaction.currency.as_deref(), index);
+
+            // A withdrawal that withdrew nothing wasn't counted
+            if let Some(count) = self
+                .transaction_counts
+                .get_mut(&client)
+                .filter(|_| applied != Amount::ZERO)
+            {
+                *count -= 1;
+            }
+            if let Some(applied_transactions) = &mut self.applied_transactions {
+                applied_transactions.remove(&transaction.tx);
+            }
+            if self
+                .report
+                .partial_fills
+                .last()
+                .is_some_and(|partial_fill| partial_fill.index == index)
+            {
+                self.report.partial_fills.pop();
+            }
+        }
+
+        // Drop the processed transaction, the transactions stored after it that weren't processed yet move up
+        // and are processed next
+        self.transactions.remove(index);
+        if let Some(event_log) = &mut self.event_log {
+            event_log.retain(|event| event.index != index);
+        }
+        self.last_processed_transaction_index = index;
+        self.processed_per_type[r#type as usize] -= 1;
+
+        Ok(())
+    }
+
+    ///
+    /// Processes all stored transactions from the last processed transaction index up to `end` (exclusive)
+    ///
+    fn process_until(&mut self, end: usize) {
+        for index in self.last_processed_transaction_index..end {
+            if let Some(transaction) = self.transactions.get(index) {
+                self.processed_per_type[transaction.r#type as usize] += 1;
+                let client = transaction.client;
+                let tx = transaction.tx;
+                let referenced = self.transactions.referenced_type(transaction);
+
+                // Remember the balances of the account to know if the transaction changed it,
+                // and those of the destination account of a transfer to another client
+                let currency = transaction.currency.clone();
+                let before = self
+                    .accounts
+                    .get_in(client, currency.as_deref())
+                    .map(Account::balances);
+                let destination = transaction.dest_client.filter(|destination| {
+                    transaction.r#type == Type::Transfer && *destination != client
+                });
+                let destination_before = destination.and_then(|destination| {
+                    self.accounts
+                        .get_in(destination, currency.as_deref())
+                        .map(Account::balances)
+                });
+
+                // Process current transaction and record it in the report if it was rejected
+                if let Err(reason) = self.process_transaction(index, client) {
+                    if reason == RejectReason::AccountLocked {
+                        self.report.dropped_due_to_lock.push((client, tx));
+                    }
+                    self.report.rejected.push(Rejection {
+                        index,
+                        client,
+                        tx,
+                        reason,
+                        referenced,
+                    });
+                }
+
+                self.record_changes(index, client, currency.as_deref(), before);
+                if let Some(destination) = destination {
+                    self.record_changes(
+                        index,
+                        destination,
+                        currency.as_deref(),
+                        destination_before,
+                    );
+                }
+            }
+
+            // Report the progress only when a hook is set
+            if let Some(progress) = self.progress_hook.as_mut() {
+                let processed = index + 1;
+                if processed % progress.every == 0 {
+                    (progress.hook)(processed);
+                }
+            }
+        }
+
+        // Update the last processed transaction index so we don't have to reprocess all transactions from the start the next time
+        self.last_processed_transaction_index = end;
+    }
+
+    ///
+    /// Records the activity of the transaction at `index` on the account of a client and the balance fields
+    /// it changed in the event log, given the balances of the account before it, or `None` if the account didn't exist yet
+    ///
+    fn record_changes(
+        &mut self,
+        index: usize,
+        client: u16,
+        currency: Option<&str>,
+        before: Option<Balances>,
+    ) {
+        let Some(account) = self
+            .accounts
+            .record_activity(client, currency, index, before)
+        else {
+            return;
+        };
+        let (Some(event_log), Some(transaction)) =
+            (self.event_log.as_mut(), self.transactions.get(index))
+        else {
+            return;
+        };
+
+        let referenced = self.transactions.referenced_type(transaction);
+        let (available, held, total, _) = before.unwrap_or_default();
+        let changes = [
+            (BalanceField::Available, available, account.available),
+            (BalanceField::Held, held, account.held),
+            (BalanceField::Total, total, account.total),
+        ];
+
+        for (field, old, new) in changes {
+            if old != new {
+                event_log.push(BalanceEvent {
+                    index,
+                    client,
+                    currency: account.currency.clone(),
+                    field,
+                    old,
+                    new,
+                    reason: transaction.r#type,
+                    referenced,
+                });
+            }
+        }
+    }
+
+    ///
+    /// Returns false if the transaction is a dispute, resolve or chargeback referencing
+    /// a transaction in a different currency
+    ///
+    fn references_same_currency(&self, transaction: &Transaction) -> bool {
+        if !matches!(
+            transaction.r#type,
+            Type::Dispute | Type::Resolve | Type::Chargeback
+        ) {
+            return true;
+        }
+
+        self.transactions.get_tx(transaction.tx).is_none_or(|tx| {
+            self.accounts.resolve_currency(tx.currency.as_deref())
+                == self
+                    .accounts
+                    .resolve_currency(transaction.currency.as_deref())
+        })
+    }
+
+    ///
+    /// Validates a deposit, withdrawal or transfer before it is applied, other transaction types are always valid
+    ///
+    /// # Errors
+    ///
+    /// Returns `RejectReason::Duplicate` if the tx id was already applied, `RejectReason::TooManyDecimals` if the amount
+    /// has more decimal places than `max_scale`, or `RejectReason::ExceedsLimit` if it's above the transaction limit
+    ///
+    fn validate_balance_change(
+        transaction: &Transaction,
+        applied_transactions: Option<&HashSet<u32>>,
+        max_scale: u32,
+        max_transaction_amount: Option<Amount>,
+    ) -> Result<(), RejectReason> {
+        if !matches!(
+            transaction.r#type,
+            Type::Deposit | Type::Withdrawal | Type::Transfer
+        ) {
+            return Ok(());
+        }
+
+        // Check if deduplication is enabled and the deposit/withdrawal was already applied, if so, skip the transaction
+        if applied_transactions.is_some_and(|applied| applied.contains(&transaction.tx)) {
+            return Err(RejectReason::Duplicate);
+        }
+
+        // Check the amount doesn't have more decimal places than allowed, so no dust accumulates
+        if transaction
+            .amount
+            .is_some_and(|amount| amount.scale() > max_scale)
+        {
+            return Err(RejectReason::TooManyDecimals);
+        }
+
+        // Check the amount doesn't exceed the configured limit, the limit itself is allowed
+        if transaction
+            .amount
+            .zip(max_transaction_amount)
+            .is_some_and(|(amount, limit)| amount > limit)
+        {
+            return Err(RejectReason::ExceedsLimit);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Disputes the transaction with the given id, holding its funds on the account
+    /// A disputed deposit holds funds that were available,
+    /// a disputed withdrawal provisionally credits the withdrawn funds as held
+    ///
+    /// Only applied transactions can be disputed and a dispute holds the amount the transaction actually applied,
+    /// a dispute of a transaction that was never applied is ignored
+    ///
+    /// A dispute may carry the amount it expects the referenced transaction to have, as a safety check
+    /// against disputing the wrong transaction
+    ///
+    /// # Errors
+    ///
+    /// Returns `RejectReason::ChargedBack` if the transaction was already charged back, since the funds are gone,
+    /// `RejectReason::AmountMismatch` if the expected amount doesn't match the amount of the transaction,
+    /// or `RejectReason::TooManyDisputes` if the client already has the maximum number of open disputes
+    ///
+    fn open_dispute(
+        &mut self,
+        client: u16,
+        tx_id: u32,
+        expected: Option<Amount>,
+    ) -> Result<(), RejectReason> {
+        // Check if the transaction is already disputed, if so, skip the transaction
+        if self.transactions.is_tx_disputed(tx_id) {
+            return Ok(());
+        }
+
+        if self.transactions.is_tx_charged_back(tx_id) {
+            return Err(RejectReason::ChargedBack);
+        }
+
+        // Retrieve the referenced transaction and the amount it applied, transfers can't be disputed
+        // and neither can transactions that were never applied (e.g. a withdrawal rejected for insufficient funds)
+        let (Some(referenced), Some(amount)) = (
+            self.transactions
+                .get_tx(tx_id)
+                .filter(|tx| tx.r#type != Type::Transfer),
+            self.transactions.applied_amount(tx_id),
+        ) else {
+            return Ok(());
+        };
+
+        if expected.is_some_and(|expected| Some(expected) != referenced.amount) {
+            return Err(RejectReason::AmountMismatch);
+        }
+
+        let open_disputes = self.open_disputes.entry(client).or_default();
+        if self
+            .max_open_disputes_per_client
+            .is_some_and(|max| *open_disputes >= max)
+        {
+            return Err(RejectReason::TooManyDisputes);
+        }
+
+        // The referenced transaction is in the currency of the dispute, it was checked before
+        let account = self
+            .accounts
+            .get_mut_in(client, referenced.currency.as_deref());
+        if self.transactions.is_withdrawal(tx_id) {
+            account.hold_withdrawal(amount);
+        } else {
+            account.hold(amount)?;
+        }
+        *open_disputes += 1;
+        self.transactions.set_disputed(tx_id, Some(client));
+
+        Ok(())
+    }
+
+    ///
+    /// Settles the dispute referenced by the resolve or chargeback at the given index
+    ///
+    /// The settled amount is clamped to the funds the account holds, so its held funds never go negative,
+    /// a resolve or chargeback that settles more than the account holds is reported as a discrepancy
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the resolve or chargeback is rejected, see `Engine::settled_amount`
+    ///
+    fn settle_dispute(&mut self, index: usize, client: u16) -> Result<(), RejectReason> {
+        if self.allow_direct_chargeback {
+            self.open_implicit_dispute(index, client)?;
+        }
+
+        let Some(transaction) = self.transactions.get(index) else {
+            return Ok(());
+        };
+
+        let tx_id = transaction.tx;
+        let is_chargeback = transaction.r#type == Type::Chargeback;
+        let disputing_client = self.transactions.disputing_client(tx_id);
+
+        // A resolve may carry an amount to only release part of the held amount, the remainder stays held,
+        // a chargeback may carry an amount to only charge back part of it, the remainder is settled as if it was resolved
+        let Some((held, settled)) =
+            Self::settled_amount(&self.transactions, transaction, self.strict_disputes)?
+        else {
+            return Ok(());
+        };
+
+        let account = self
+            .accounts
+            .get_mut_in(client, transaction.currency.as_deref());
+
+        // A chargeback is terminal and settles the whole held amount, a resolve only the released amount
+        let requested = if is_chargeback { held } else { settled };
+        let available_held = account.held.max(Amount::ZERO);
+        let discrepancy = (requested > available_held).then_some(HeldDiscrepancy {
+            index,
+            client,
+            tx: tx_id,
+            settled: requested,
+            held: account.held,
+        });
+        let requested = requested.min(available_held);
+
+        if is_chargeback {
+            let charged_back = settled.min(requested);
+
+            // A charged back deposit reverses the deposited funds, a charged back withdrawal credits them permanently
+            if self.transactions.is_withdrawal(tx_id) {
+                account.release_withdrawal(requested - charged_back);
+                account.chargeback_withdrawal(charged_back);
+            } else {
+                account.release(requested - charged_back)?;
+                account.chargeback(charged_back);
+            }
+
+            // Only the chargeback that locked the account is recorded, later ones find it already locked
+            account.locked_by.get_or_insert(tx_id);
+
+            // The chargeback is terminal, the transaction can't be disputed again
+            self.transactions.set_charged_back(tx_id);
+        } else {
+            // A resolved deposit dispute gives the held funds back, a resolved withdrawal dispute takes them back out
+            if self.transactions.is_withdrawal(tx_id) {
+                account.release_withdrawal(requested);
+            } else {
+                account.release(requested)?;
+            }
+            self.transactions.release_held(tx_id, settled);
+        }
+        self.report.held_discrepancies.extend(discrepancy);
+
+        // The dispute no longer counts as open for the client that opened it once nothing is held anymore
+        if !self.transactions.is_tx_disputed(tx_id) {
+            if let Some(open_disputes) = disputing_client
+                .and_then(|disputing_client| self.open_disputes.get_mut(&disputing_client))
+            {
+                *open_disputes = open_disputes.saturating_sub(1);
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Disputes the transaction referenced by the chargeback at the given index if it isn't disputed yet,
+    /// so a direct chargeback is settled like any other chargeback
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the implicit dispute is rejected, see `Engine::open_dispute`
+    ///
+    fn open_implicit_dispute(&mut self, index: usize, client: u16) -> Result<(), RejectReason> {
+        let Some(transaction) = self
+            .transactions
+            .get(index)
+            .filter(|transaction| transaction.r#type == Type::Chargeback)
+        else {
+            return Ok(());
+        };
+
+        let tx_id = transaction.tx;
+        if self.transactions.is_tx_disputed(tx_id) || self.transactions.is_tx_charged_back(tx_id) {
+            return Ok(());
+        }
+
+        self.open_dispute(client, tx_id, None)
+    }
+
+    ///
+    /// Returns the amount still held by the transaction referenced by a resolve or chargeback, and the amount to settle,
+    /// which is the whole held amount unless the resolve or chargeback carries a partial amount
+    /// A reference to a transaction that isn't disputed is skipped, or rejected with strict disputes enabled
+    /// A charged back transaction is no longer disputed, so a later resolve or chargeback never settles its funds again
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the resolve or chargeback is rejected, if the referenced transaction isn't disputed
+    /// in strict mode or the partial amount is negative or larger than the held amount
+    ///
+    fn settled_amount(
+        transactions: &Transactions,
+        transaction: &Transaction,
+        strict_disputes: bool,
+    ) -> Result<Option<(Amount, Amount)>, RejectReason> {
+        let Some(held) = transactions.held_amount(transaction.tx) else {
+            if strict_disputes {
+                // A charged back transaction is in its terminal state rather than never disputed
+                if transactions.is_tx_charged_back(transaction.tx) {
+                    return Err(RejectReason::ChargedBack);
+                }
+
+                return Err(RejectReason::NotDisputed);
+            }
+
+            return Ok(None);
+        };
+
+        let settled = transaction.amount.unwrap_or(held);
+        if settled.is_negative() || settled > held {
+            return Err(RejectReason::InvalidAmount);
+        }
+
+        Ok(Some((held, settled)))
+    }
+
+    ///
+    /// Returns the fee charged on a withdrawal of the given amount, rounded half-even to the max scale
+    /// The fee is zero when no withdrawal fee is configured
+    ///
+    fn withdrawal_fee(withdrawal_fee_bps: u32, max_scale: u32, amount: Amount) -> Amount {
+        amount
+            .basis_points(withdrawal_fee_bps)
+            .round_with(max_scale, Rounding::HalfEven)
+    }
+
+    ///
+    /// Invokes the low balance hook if the available funds of the client are below its threshold
+    ///
+    fn notify_low_balance(&mut self, client: u16, available: Amount) {
+        if let Some(low_balance) = self.low_balance_hook.as_mut() {
+            if available < low_balance.threshold {
+                (low_balance.hook)(client, available);
+            }
+        }
+    }
+
+    ///
+    /// Records the amount a deposit, withdrawal or transfer applied so it can be disputed, and marks it as applied
+    /// when deduplication is enabled
+    /// Only deposits and withdrawals that changed the balance of the client count in `Engine::transaction_count`
+    ///
+    fn record_applied(&mut self, index: usize, client: u16, tx: u32, amount: Amount) {
+        let counted = amount != Amount::ZERO
+            && self
+                .transactions
+                .get(index)
+                .is_some_and(|transaction| transaction.r#type != Type::Transfer);
+        if counted {
+            *self.transaction_counts.entry(client).or_insert(0) += 1;
+        }
+        self.transactions.set_applied(index, amount);
+        if let Some(applied_transactions) = &mut self.applied_transactions {
+            applied_transactions.insert(tx);
+        }
+    }
+
+    ///
+    /// Records a partially filled withdrawal in the report, the stored withdrawal keeps its requested amount
+    /// and the withdrawn amount is its applied amount, so a later dispute holds that amount
+    ///
+    fn record_partial_fill(&mut self, partial_fill: PartialFill) {
+        self.report.partial_fills.push(partial_fill);
+    }
+
+    ///
+    /// Processes a single transaction
+    ///
+    /// Transactions that are ignored (e.g. a dispute referencing an unknown transaction) are not rejections,
+    /// only transactions that could not be applied return an error with the reason
+    ///
+    fn process_transaction(
+        &mut self,
+        current_transaction_index: usize,
+        client: u16,
+    ) -> Result<(), RejectReason> {
+        let Some(transaction) = self.transactions.get(current_transaction_index) else {
+            return Ok(());
+        };
+
+        // Skip the transactions of clients excluded by the filter before their account is looked up
+        if self
+            .client_filter
+            .as_ref()
+            .is_some_and(|filter| !filter(client))
+        {
+            return Ok(());
+        }
+
+        // Disputes, resolves and chargebacks only match a referenced transaction in the same currency
+        if !self.references_same_currency(transaction) {
+            return Ok(());
+        }
+
+        // Reject the zero client and new clients once the client limit is reached, before their account is created
+        self.check_client(client)?;
+
+        // Check if the account is locked, if so, skip the transaction unless the locked policy allows it
+        // or it winds down an open dispute and dispute resolution is allowed on locked accounts
+        // The account itself is only created once the transaction changes it
+        let locked = self
+            .accounts
+            .get_in(client, transaction.currency.as_deref())
+            .is_some_and(|account| account.locked);
+        let winds_down_dispute = self.allow_dispute_resolution_when_locked
+            && matches!(transaction.r#type, Type::Resolve | Type::Chargeback);
+        if locked && !winds_down_dispute && !self.locked_policy.allows(transaction.r#type) {
+            return Err(RejectReason::AccountLocked);
+        }
+
+        // Check if a deposit/withdrawal can be applied at all
+        Self::validate_balance_change(
+            transaction,
+            self.applied_transactions.as_ref(),
+            self.max_scale,
+            self.max_transaction_amount,
+        )?;
+
+        match transaction.r#type {
+            Type::Deposit => {
+                // Check if the transaction is disputed, if so, skip the transaction
+                if !transaction.disputed {
+                    if let Some(amount) = transaction.amount {
+                        let account = self
+                            .accounts
+                            .get_mut_in(client, transaction.currency.as_deref());
+                        account.deposit(amount)?;
+                        account.total_deposited += amount;
+                        let tx_id = transaction.tx;
+                        self.record_applied(current_transaction_index, client, tx_id, amount);
+                    }
+                }
+            }
+            Type::Withdrawal => self.withdraw(current_transaction_index, client)?,
+            Type::Dispute => {
+                let (tx_id, expected) = (transaction.tx, transaction.amount);
+                self.open_dispute(client, tx_id, expected)?;
+            }
+            Type::Resolve | Type::Chargeback => {
+                self.settle_dispute(current_transaction_index, client)?;
+            }
+            Type::Transfer => self.transfer(current_transaction_index, client)?,
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Withdraws the amount of the withdrawal at the given index from the available funds of the client
+    ///
+    /// Withdrawals only draw from the available funds, held funds can never be withdrawn
+    /// With `WithdrawalPolicy::PartialFill` a withdrawal larger than the available funds withdraws them all
+    ///
+    /// # Errors
+    ///
+    /// Returns `RejectReason::InsufficientFunds` if the available funds don't cover the withdrawal and its fee,
+    /// the rejection is counted on the account of the client if it exists, or `RejectReason::Overflow` if a balance
+    /// of the client or the fee account would overflow, in which case neither account changes
+    ///
+    fn withdraw(&mut self, index: usize, client: u16) -> Result<(), RejectReason> {
+        // Check if the transaction is disputed, if so, skip the transaction
+        let Some(transaction) = self
+            .transactions
+            .get(index)
+            .filter(|transaction| !transaction.disputed)
+        else {
+            return Ok(());
+        };
+        let Some(requested) = transaction.amount else {
+            return Ok(());
+        };
+        let (tx_id, currency) = (transaction.tx, transaction.currency.clone());
+
+        // Check how much of the available funds can be withdrawn, held funds are never considered
+        // and the available funds have to cover the fee as well
+        let available = self
+            .accounts
+            .get_in(client, currency.as_deref())
+            .map_or(Amount::ZERO, |account| account.available);
+        let fee = Self::withdrawal_fee(self.withdrawal_fee_bps, self.max_scale, requested);
+        let Some(amount) = self
+            .withdrawal_policy
+            .withdrawable(available - fee, requested)
+        else {
+            // Only count the rejection on an existing account, a withdrawal doesn't create the account it's rejected for
+            if self.accounts.get_in(client, currency.as_deref()).is_some() {
+                self.accounts
+                    .get_mut_in(client, currency.as_deref())
+                    .rejected_withdrawals += 1;
+            }
+            return Err(RejectReason::InsufficientFunds);
+        };
+
+        let fee = Self::withdrawal_fee(self.withdrawal_fee_bps, self.max_scale, amount);
+        let debited = amount.checked_add(fee).ok_or(RejectReason::Overflow)?;
+        self.accounts
+            .get_mut_in(client, currency.as_deref())
+            .withdraw(debited)?;
+        if fee > Amount::ZERO {
+            // The activity of the client is recorded by `Engine::process_until`, the fee account only when it's another one
+            let fee_before = self
+                .accounts
+                .get_in(self.fee_account, currency.as_deref())
+                .map(Account::balances);
+            let deposited = self
+                .accounts
+                .get_mut_in(self.fee_account, currency.as_deref())
+                .deposit(fee);
+            if let Err(reason) = deposited {
+                // The fee can't be credited, so the client gets the debited funds back
+                self.accounts
+                    .get_mut_in(client, currency.as_deref())
+                    .reverse_withdrawal(debited);
+                return Err(reason);
+            }
+            if self.fee_account != client {
+                self.record_changes(index, self.fee_account, currency.as_deref(), fee_before);
+            }
+        }
+        let account = self.accounts.get_mut_in(client, currency.as_deref());
+        account.total_withdrawn += amount;
+        let available = account.available;
+
+        self.record_applied(index, client, tx_id, amount);
+        if amount != Amount::ZERO {
+            self.notify_low_balance(client, available);
+        }
+
+        if amount < requested {
+            self.record_partial_fill(PartialFill {
+                index,
+                client,
+                tx: tx_id,
+                requested,
+                withdrawn: amount,
+            });
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Checks that the client may have an account, before it's created
+    ///
+    /// # Errors
+    ///
+    /// Returns `RejectReason::ZeroClient` for client `0` if the engine rejects it, or `RejectReason::TooManyClients`
+    /// if the client doesn't have an account yet and the engine already has the maximum number of clients
+    ///
+    fn check_client(&self, client: u16) -> Result<(), RejectReason> {
+        if self.reject_zero_client && client == 0 {
+            return Err(RejectReason::ZeroClient);
+        }
+
+        let exceeds_max_clients = self.max_clients.is_some_and(|max| {
+            !self.accounts.contains_client(client) && self.accounts.client_count() >= max
+        });
+        if exceeds_max_clients {
+            return Err(RejectReason::TooManyClients);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Moves the amount of the transfer at the given index from the available funds of the client
+    /// to the available funds of the destination client, in the currency of the transfer
+    ///
+    /// A transfer is atomic: every check is done before any funds move, and the withdrawn funds are given back if the
+    /// destination can't be credited, so a rejected transfer changes neither account
+    /// The account of the client was already checked to be unlocked, the withdrawal policy and withdrawal fees
+    /// don't apply to transfers and neither do the lifetime deposited and withdrawn totals
+    ///
+    /// # Errors
+    ///
+    /// Returns `RejectReason::InvalidAmount` for a negative amount, `RejectReason::DestinationExcluded` if the client
+    /// filter excludes the destination, `RejectReason::DestinationLocked` if the destination account is locked,
+    /// `RejectReason::TooManyClients` if the destination is a new client above the client limit
+    /// or `RejectReason::InsufficientFunds` if the client doesn't have enough available funds,
+    /// or `RejectReason::Overflow` if a balance of either account would overflow
+    ///
+    fn transfer(&mut self, index: usize, client: u16) -> Result<(), RejectReason> {
+        let Some(transaction) = self.transactions.get(index) else {
+            return Ok(());
+        };
+        let (Some(amount), Some(destination)) = (transaction.amount, transaction.dest_client)
+        else {
+            return Ok(());
+        };
+
+        if amount.is_negative() {
+            return Err(RejectReason::InvalidAmount);
+        }
+
+        let (tx_id, currency) = (transaction.tx, transaction.currency.as_deref());
+        if self
+            .client_filter
+            .as_ref()
+            .is_some_and(|filter| !filter(destination))
+        {
+            return Err(RejectReason::DestinationExcluded);
+        }
+        if self
+            .accounts
+            .get_in(destination, currency)
+            .is_some_and(|account| account.locked)
+        {
+            return Err(RejectReason::DestinationLocked);
+        }
+        self.check_client(destination)?;
+        if self
+            .accounts
+            .get_in(client, currency)
+            .map_or(Amount::ZERO, |account| account.available)
+            < amount
+        {
+            return Err(RejectReason::InsufficientFunds);
+        }
+
+        self.accounts
+            .get_mut_in(client, currency)
+            .withdraw(amount)?;
+        if let Err(reason) = self
+            .accounts
+            .get_mut_in(destination, currency)
+            .deposit(amount)
+        {
+            // The destination can't be credited, so the client gets the funds back
+            self.accounts
+                .get_mut_in(client, currency)
+                .reverse_withdrawal(amount);
+            return Err(reason);
+        }
+        self.record_applied(index, client, tx_id, amount);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::{Account, Accounts};
+    use crate::engine::Engine;
+    use crate::error::PaymentsError;
+    use crate::money::Amount;
+    use crate::policy::{LockedPolicy, WithdrawalPolicy};
+    use crate::report::{
+        BalanceEvent, BalanceField, HeldDiscrepancy, PartialFill, RejectReason, Rejection,
+    };
+    use crate::transaction::{transaction, Transaction, Transactions, Type};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    ///
+    /// Locks client 1 through a chargeback on tx 1 while tx 2 stays disputed (held = 5)
+    ///
+    fn locked_engine(locked_policy: LockedPolicy) -> Engine {
+        let mut engine = Engine::new(Accounts::new()).with_locked_policy(locked_policy);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Dispute, 1, 2, None),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert!(account.locked);
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::from(5));
+
+        engine
+    }
+
+    #[test]
+    fn deposits_on_locked_account_are_listed_as_dropped() {
+        let mut engine = locked_engine(LockedPolicy::RejectAll);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 3, Some(Amount::from(3))),
+            transaction(Type::Deposit, 2, 4, Some(Amount::from(1))),
+            transaction(Type::Deposit, 1, 5, Some(Amount::from(2))),
+        ]));
+
+        assert_eq!(engine.report().dropped_due_to_lock, [(1, 3), (1, 5)]);
+
+        engine.undo_last().expect("Failed to undo deposit");
+        assert_eq!(engine.report().dropped_due_to_lock, [(1, 3)]);
+    }
+
+    fn deposit_and_resolve_on_locked(engine: &mut Engine) {
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 3, Some(Amount::from(3))),
+            transaction(Type::Resolve, 1, 2, None),
+        ]));
+    }
+
+    #[test]
+    fn single_transaction_deposit_succeeds() {
+        let mut engine = Engine::new(Accounts::new());
+        let transaction = Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transactions = Transactions::from(vec![transaction]);
+        engine.process(transactions);
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::new(5, 1));
+    }
+
+    #[test]
+    fn single_transaction_withdrawal_without_funds_creates_no_account() {
+        let mut engine = Engine::new(Accounts::new());
+        let transaction = Transaction {
+            r#type: Type::Withdrawal,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transactions = Transactions::from(vec![transaction]);
+        engine.process(transactions);
+
+        // The rejected withdrawal doesn't change the account, so it is never created
+        assert!(engine.accounts().get(1).is_none());
+        assert_eq!(engine.report().rejected.len(), 1);
+    }
+
+    #[test]
+    fn double_transaction_succeeds() {
+        let mut engine = Engine::new(Accounts::new());
+        let transaction1 = Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+        let transaction2 = Transaction {
+            r#type: Type::Withdrawal,
+            client: 1,
+            tx: 2,
+            amount: Some(Amount::new(3, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        engine.process(Transactions::from(vec![transaction1, transaction2]));
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::new(2, 1));
+    }
+
+    #[test]
+    fn dispute_transaction_succeeds() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let transaction1 = Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction2 = Transaction {
+            r#type: Type::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        engine.process(Transactions::from(vec![transaction1, transaction2]));
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::new(5, 1));
+    }
+
+    #[test]
+    fn resolve_transaction_succeeds() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let transaction1 = Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction2 = Transaction {
+            r#type: Type::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction3 = Transaction {
+            r#type: Type::Resolve,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        engine.process(Transactions::from(vec![
+            transaction1,
+            transaction2,
+            transaction3,
+        ]));
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::new(5, 1));
+        assert_eq!(account.held, 0.into());
+    }
+
+    #[test]
+    fn chargeback_transaction_succeeds() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let transaction1 = Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction2 = Transaction {
+            r#type: Type::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction3 = Transaction {
+            r#type: Type::Chargeback,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        engine.process(Transactions::from(vec![
+            transaction1,
+            transaction2,
+            transaction3,
+        ]));
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, 0.into());
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn locked_account_withdraw_fails() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let transaction1 = Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction2 = Transaction {
+            r#type: Type::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        engine.process(Transactions::from(vec![transaction1, transaction2]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::new(5, 1));
+        assert!(!account.locked);
+
+        let transaction3 = Transaction {
+            r#type: Type::Chargeback,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction4 = Transaction {
+            r#type: Type::Withdrawal,
+            client: 1,
+            tx: 2,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        engine.process(Transactions::from(vec![transaction3, transaction4]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, 0.into());
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn disputed_transactions_lists_only_disputed() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let transaction1 = Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction2 = Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 2,
+            amount: Some(Amount::new(15, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction3 = Transaction {
+            r#type: Type::Dispute,
+            client: 1,
+            tx: 2,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        engine.process(Transactions::from(vec![
+            transaction1,
+            transaction2,
+            transaction3,
+        ]));
+
+        let disputed = engine.disputed_transactions(1);
+        assert_eq!(disputed.len(), 1);
+        assert_eq!(disputed[0].tx, 2);
+        assert!(engine.disputed_transactions(2).is_empty());
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.disputed_total(), Amount::new(15, 1));
+    }
+
+    #[test]
+    fn resolve_without_dispute_is_ignored() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let transaction1 = Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 1,
+            amount: Some(Amount::new(5, 1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        let transaction2 = Transaction {
+            r#type: Type::Resolve,
+            client: 1,
+            tx: 1,
+            amount: None,
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+
+        engine.process(Transactions::from(vec![transaction1, transaction2]));
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::new(5, 1));
+        assert_eq!(account.held, 0.into());
+    }
+
+    #[test]
+    fn locked_policy_reject_all_skips_deposit_and_resolve() {
+        let mut engine = locked_engine(LockedPolicy::RejectAll);
+        deposit_and_resolve_on_locked(&mut engine);
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::from(5));
+        assert_eq!(account.total, Amount::from(5));
+    }
+
+    #[test]
+    fn locked_policy_allow_resolve_only_applies_resolve() {
+        let mut engine = locked_engine(LockedPolicy::AllowResolveOnly);
+        deposit_and_resolve_on_locked(&mut engine);
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(5));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn locked_policy_allow_deposits_applies_deposit() {
+        let mut engine = locked_engine(LockedPolicy::AllowDeposits);
+        deposit_and_resolve_on_locked(&mut engine);
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(3));
+        assert_eq!(account.held, Amount::from(5));
+        assert_eq!(account.total, Amount::from(8));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn dispute_resolution_when_locked_winds_down_open_disputes() {
+        let mut engine =
+            locked_engine(LockedPolicy::RejectAll).with_dispute_resolution_when_locked();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 3, Some(Amount::from(3))),
+            transaction(Type::Dispute, 1, 3, None),
+            transaction(Type::Resolve, 1, 2, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(5));
+        assert!(account.locked);
+        assert_eq!(engine.is_disputed(2), Some(false));
+
+        // The deposit and the new dispute are still rejected by the locked policy
+        let reasons = engine
+            .report()
+            .rejected
+            .iter()
+            .map(|rejection| (rejection.tx, rejection.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reasons,
+            [
+                (3, RejectReason::AccountLocked),
+                (3, RejectReason::AccountLocked)
+            ]
+        );
+    }
+
+    #[test]
+    fn dispute_resolution_when_locked_applies_chargeback() {
+        let mut engine =
+            locked_engine(LockedPolicy::RejectAll).with_dispute_resolution_when_locked();
+
+        engine.process_one(transaction(Type::Chargeback, 1, 2, None));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, 0.into());
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    fn resolve_on_locked_account_is_rejected_by_default() {
+        let mut engine = locked_engine(LockedPolicy::RejectAll);
+
+        engine.process_one(transaction(Type::Resolve, 1, 2, None));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.held, Amount::from(5));
+        assert_eq!(
+            engine
+                .report()
+                .rejected
+                .last()
+                .map(|rejection| rejection.reason),
+            Some(RejectReason::AccountLocked)
+        );
+    }
+
+    fn snapshot_first_half() -> Vec<Transaction> {
+        vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 2, 3, Some(Amount::from(8))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Deposit, 3, 4, Some(Amount::from(7))),
+            transaction(Type::Dispute, 3, 4, None),
+            transaction(Type::Chargeback, 3, 4, None),
+        ]
+    }
+
+    fn snapshot_second_half() -> Vec<Transaction> {
+        vec![
+            transaction(Type::Resolve, 1, 1, None),
+            transaction(Type::Withdrawal, 1, 5, Some(Amount::from(4))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 3, 4, None),
+            transaction(Type::Deposit, 3, 6, Some(Amount::from(1))),
+        ]
+    }
+
+    fn accounts_csv(engine: &Engine) -> String {
+        let mut output = vec![];
+        engine
+            .accounts()
+            .write_csv(&mut output)
+            .expect("Failed to write accounts");
+
+        String::from_utf8(output).expect("Output is not valid utf8")
+    }
+
+    #[test]
+    fn snapshot_restore_matches_single_run() {
+        let mut single_run = Engine::new(Accounts::new()).with_dedup();
+        single_run.process(Transactions::from(snapshot_first_half()));
+        single_run.process(Transactions::from(snapshot_second_half()));
+
+        let mut first_run = Engine::new(Accounts::new()).with_dedup();
+        first_run.process(Transactions::from(snapshot_first_half()));
+
+        let mut snapshot = vec![];
+        first_run
+            .save_snapshot(&mut snapshot)
+            .expect("Failed to save snapshot");
+        let mut restored =
+            Engine::load_snapshot(snapshot.as_slice()).expect("Failed to load snapshot");
+        restored.process(Transactions::from(snapshot_second_half()));
+
+        assert_eq!(accounts_csv(&restored), accounts_csv(&single_run));
+        assert_eq!(restored.report().rejected, single_run.report().rejected);
+        assert_eq!(
+            restored.transaction_count(1),
+            single_run.transaction_count(1)
+        );
+        assert!(restored.disputed_transactions(1).is_empty());
+    }
+
+    #[test]
+    fn process_dir_matches_processing_files_sequentially() {
+        let mut sequential = Engine::new(Accounts::new());
+        for file in ["01_first.csv", "02_second.csv"] {
+            let path = Path::new("tests/resources/inputs/batch").join(file);
+            sequential.process(Transactions::from_csv(&path).expect("Failed to read transactions"));
+        }
+
+        let mut batch = Engine::new(Accounts::new());
+        batch.process(
+            Transactions::from_dir(Path::new("tests/resources/inputs/batch"))
+                .expect("Failed to read transactions from directory"),
+        );
+
+        assert_eq!(accounts_csv(&batch), accounts_csv(&sequential));
+        assert_eq!(batch.report().rejected, sequential.report().rejected);
+    }
+
+    #[test]
+    fn load_snapshot_invalid_data_fails() {
+        let result = Engine::load_snapshot([1_u8, 2, 3].as_slice());
+
+        assert!(matches!(result, Err(PaymentsError::Snapshot(_))));
+    }
+
+    #[test]
+    fn load_snapshot_unknown_version_fails() {
+        let mut output = vec![];
+        Engine::new(Accounts::new())
+            .save_snapshot(&mut output)
+            .expect("Failed to save snapshot");
+        assert_eq!(output[0], Engine::SNAPSHOT_FORMAT_VERSION);
+
+        output[0] = Engine::SNAPSHOT_FORMAT_VERSION + 1;
+        let result = Engine::load_snapshot(output.as_slice());
+
+        assert!(matches!(
+            result,
+            Err(PaymentsError::UnsupportedSnapshotVersion(version)) if version == Engine::SNAPSHOT_FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn process_chunked_invokes_callback_per_chunk() {
+        let mut engine = Engine::new(Accounts::new());
+        let mut snapshots = vec![];
+
+        engine.process_chunked(
+            Transactions::from(vec![
+                transaction(Type::Deposit, 1, 1, Some(Amount::from(1))),
+                transaction(Type::Deposit, 1, 2, Some(Amount::from(2))),
+                transaction(Type::Deposit, 1, 3, Some(Amount::from(3))),
+                transaction(Type::Deposit, 1, 4, Some(Amount::from(4))),
+                transaction(Type::Deposit, 1, 5, Some(Amount::from(5))),
+            ]),
+            2,
+            |accounts| {
+                let account = accounts.get(1).expect("Failed to get account");
+                snapshots.push(account.available);
+            },
+        );
+
+        assert_eq!(
+            snapshots,
+            vec![Amount::from(3), Amount::from(10), Amount::from(15)]
+        );
+    }
+
+    #[test]
+    fn process_chunked_continues_after_process() {
+        let mut engine = Engine::new(Accounts::new());
+        engine.process(Transactions::from(vec![transaction(
+            Type::Deposit,
+            1,
+            1,
+            Some(Amount::from(1)),
+        )]));
+
+        let mut chunks = 0;
+        engine.process_chunked(
+            Transactions::from(vec![
+                transaction(Type::Deposit, 1, 2, Some(Amount::from(2))),
+                transaction(Type::Withdrawal, 1, 3, Some(Amount::from(1))),
+            ]),
+            0,
+            |_| chunks += 1,
+        );
+
+        assert_eq!(chunks, 2);
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(2));
+    }
+
+    #[test]
+    fn transaction_count_counts_only_applied_deposits_and_withdrawals() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(2))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(100))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Deposit, 2, 4, Some(Amount::from(1))),
+        ]));
+
+        assert_eq!(engine.transaction_count(1), 2);
+        assert_eq!(engine.transaction_count(2), 1);
+        assert_eq!(engine.transaction_count(3), 0);
+    }
+
+    #[test]
+    fn transaction_count_ignores_transfers() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(5))),
+            transfer(1, 2, Amount::from(2), 2),
+        ]));
+
+        assert_eq!(engine.transaction_count(1), 1);
+        assert_eq!(engine.transaction_count(2), 0);
+    }
+
+    #[test]
+    fn transaction_count_ignores_zero_amounts() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(5))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::ZERO)),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::ZERO)),
+        ]));
+
+        assert_eq!(engine.transaction_count(1), 1);
+
+        engine.undo_last().expect("Failed to undo the withdrawal");
+        assert_eq!(engine.transaction_count(1), 1);
+    }
+
+    #[test]
+    fn full_chargeback_without_amount() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(5));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn partial_chargeback_releases_remainder() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, Some(Amount::from(4))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(11));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(11));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn partial_chargeback_larger_than_disputed_is_rejected() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, Some(Amount::from(11))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::from(10));
+        assert_eq!(account.total, Amount::from(10));
+        assert!(!account.locked);
+    }
+
+    fn overdrawing_withdrawal(withdrawal_policy: WithdrawalPolicy) -> Engine {
+        let mut engine = Engine::new(Accounts::new()).with_withdrawal_policy(withdrawal_policy);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(60))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(100))),
+        ]));
+
+        engine
+    }
+
+    #[test]
+    fn withdrawal_policy_reject_on_insufficient() {
+        let engine = overdrawing_withdrawal(WithdrawalPolicy::RejectOnInsufficient);
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(60));
+        assert_eq!(account.total, Amount::from(60));
+        assert_eq!(engine.report().rejected.len(), 1);
+        assert!(engine.report().partial_fills.is_empty());
+    }
+
+    #[test]
+    fn withdrawal_policy_partial_fill() {
+        let mut engine = overdrawing_withdrawal(WithdrawalPolicy::PartialFill);
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.total, 0.into());
+        assert!(engine.report().is_clean());
+        assert_eq!(
+            engine.report().partial_fills,
+            vec![PartialFill {
+                index: 1,
+                client: 1,
+                tx: 2,
+                requested: Amount::from(100),
+                withdrawn: Amount::from(60),
+            }]
+        );
+
+        // A dispute of the partially filled withdrawal holds what was actually withdrawn
+        engine.process(Transactions::from(vec![transaction(
+            Type::Dispute,
+            1,
+            2,
+            None,
+        )]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.held, Amount::from(60));
+
+        // The stored withdrawal keeps the amount that was requested
+        let withdrawal = engine
+            .transactions()
+            .get(1)
+            .expect("Failed to get withdrawal");
+        assert_eq!(withdrawal.amount, Some(Amount::from(100)));
+    }
+
+    #[test]
+    fn withdrawal_policy_partial_fill_without_funds_is_rejected() {
+        let mut engine =
+            Engine::new(Accounts::new()).with_withdrawal_policy(WithdrawalPolicy::PartialFill);
+
+        engine.process(Transactions::from(vec![transaction(
+            Type::Withdrawal,
+            1,
+            1,
+            Some(Amount::from(10)),
+        )]));
+
+        assert_eq!(
+            engine.report().rejected[0].reason,
+            RejectReason::InsufficientFunds
+        );
+        assert!(engine.report().partial_fills.is_empty());
+    }
+
+    #[test]
+    fn amount_with_four_decimals_is_accepted() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::new(12_345, 4))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::new(1, 4))),
+            transaction(Type::Deposit, 1, 3, Some(Amount::new(10_000, 4))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::new(22_344, 4));
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    #[cfg(not(feature = "fixed-point"))]
+    fn amount_with_five_decimals_is_rejected() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(1))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::new(123_456, 5))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::new(1, 5))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(1));
+        assert_eq!(
+            engine.report().rejected,
+            vec![
+                Rejection {
+                    index: 1,
+                    client: 1,
+                    tx: 2,
+                    reason: RejectReason::TooManyDecimals,
+                    referenced: None,
+                },
+                Rejection {
+                    index: 2,
+                    client: 1,
+                    tx: 3,
+                    reason: RejectReason::TooManyDecimals,
+                    referenced: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn max_scale_is_configurable() {
+        let mut engine = Engine::new(Accounts::new()).with_max_scale(2);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::new(101, 2))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::new(1001, 3))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::new(101, 2));
+        assert_eq!(engine.report().rejected.len(), 1);
+    }
+
+    #[test]
+    fn deposit_at_or_under_limit_is_applied() {
+        let mut engine =
+            Engine::new(Accounts::new()).with_max_transaction_amount(Amount::from(10_000));
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::new(999_999, 2))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(10_000))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::new(1_999_999, 2));
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    fn transaction_over_limit_is_rejected() {
+        let mut engine =
+            Engine::new(Accounts::new()).with_max_transaction_amount(Amount::from(10_000));
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(5))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::new(1_000_001, 2))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::new(1_000_001, 2))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.total, Amount::from(5));
+        assert_eq!(
+            engine.report().rejected,
+            [
+                Rejection {
+                    index: 1,
+                    client: 1,
+                    tx: 2,
+                    reason: RejectReason::ExceedsLimit,
+                    referenced: None,
+                },
+                Rejection {
+                    index: 2,
+                    client: 1,
+                    tx: 3,
+                    reason: RejectReason::ExceedsLimit,
+                    referenced: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn no_limit_by_default() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![transaction(
+            Type::Deposit,
+            1,
+            1,
+            Some(Amount::from(1_000_000_000)),
+        )]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(1_000_000_000));
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    fn reused_tx_id_in_later_file_keeps_dispute_on_original() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+        ]));
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(3))),
+            transaction(Type::Resolve, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(13));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(engine.is_disputed(1), Some(false));
+        assert!(engine.disputed_transactions(1).is_empty());
+    }
+
+    #[test]
+    fn resolve_without_dispute_is_ignored_by_default() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Resolve, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    fn resolve_without_dispute_is_rejected_with_strict_disputes() {
+        let mut engine = Engine::new(Accounts::new()).with_strict_disputes();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Resolve, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(
+            engine.report().rejected,
+            vec![Rejection {
+                index: 1,
+                client: 1,
+                tx: 1,
+                reason: RejectReason::NotDisputed,
+                referenced: Some(Type::Deposit),
+            }]
+        );
+    }
+
+    ///
+    /// Charges back deposit 1 of client 1 and then tries to resolve and charge it back again,
+    /// dispute resolution is allowed on locked accounts so the resolve and chargeback reach the dispute state
+    ///
+    fn settle_after_chargeback(engine: Engine) -> Engine {
+        let mut engine = engine.with_dispute_resolution_when_locked();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(5));
+        assert_eq!(account.locked_by, Some(1));
+
+        engine
+    }
+
+    #[test]
+    fn resolve_after_chargeback_is_a_no_op() {
+        let engine = settle_after_chargeback(Engine::new(Accounts::new()));
+
+        assert!(engine.report().rejected.is_empty());
+        assert!(engine.report().held_discrepancies.is_empty());
+    }
+
+    #[test]
+    fn resolve_after_chargeback_is_rejected_with_strict_disputes() {
+        let engine = settle_after_chargeback(Engine::new(Accounts::new()).with_strict_disputes());
+
+        assert_eq!(
+            engine
+                .report()
+                .rejected
+                .iter()
+                .map(|rejection| (rejection.index, rejection.reason))
+                .collect::<Vec<_>>(),
+            [
+                (4, RejectReason::ChargedBack),
+                (5, RejectReason::ChargedBack)
+            ]
+        );
+    }
+
+    #[test]
+    fn chargeback_without_dispute_is_rejected_with_strict_disputes() {
+        let mut engine = Engine::new(Accounts::new()).with_strict_disputes();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Chargeback, 1, 1, None),
+            transaction(Type::Chargeback, 1, 2, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert!(!account.locked);
+        assert_eq!(
+            engine
+                .report()
+                .rejected
+                .iter()
+                .map(|rejection| rejection.reason)
+                .collect::<Vec<_>>(),
+            [RejectReason::NotDisputed, RejectReason::NotDisputed]
+        );
+    }
+
+    #[test]
+    fn client_filter_skips_other_clients() {
+        let mut engine = Engine::new(Accounts::new()).with_client_filter(|client| client == 2);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 3, 3, Some(Amount::from(1))),
+            transaction(Type::Dispute, 1, 1, None),
+        ]));
+
+        assert_eq!(
+            accounts_csv(&engine),
+            "client,available,held,total,locked\n2,5,0,5,false\n"
+        );
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    fn is_disputed_follows_dispute_and_resolve() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+        ]));
+        assert_eq!(engine.is_disputed(1), Some(true));
+        assert_eq!(engine.is_disputed(2), None);
+
+        engine.process(Transactions::from(vec![transaction(
+            Type::Resolve,
+            1,
+            1,
+            None,
+        )]));
+        assert_eq!(engine.is_disputed(1), Some(false));
+    }
+
+    #[test]
+    fn partial_resolve_keeps_remainder_held() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, Some(Amount::from(3))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(3));
+        assert_eq!(account.held, Amount::from(7));
+        assert_eq!(account.total, Amount::from(10));
+        assert_eq!(engine.disputed_transactions(1).len(), 1);
+
+        // A resolve without an amount releases what is still held
+        engine.process(Transactions::from(vec![transaction(
+            Type::Resolve,
+            1,
+            1,
+            None,
+        )]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.held, 0.into());
+        assert!(engine.disputed_transactions(1).is_empty());
+    }
+
+    #[test]
+    fn partial_resolve_then_partial_chargeback() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, Some(Amount::from(3))),
+            transaction(Type::Chargeback, 1, 1, Some(Amount::from(5))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(5));
+        assert!(account.locked);
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    fn partial_resolve_larger_than_held_is_rejected() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, Some(Amount::from(6))),
+            transaction(Type::Resolve, 1, 1, Some(Amount::from(5))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(6));
+        assert_eq!(account.held, Amount::from(4));
+        assert_eq!(
+            engine.report().rejected,
+            vec![Rejection {
+                index: 3,
+                client: 1,
+                tx: 1,
+                reason: RejectReason::InvalidAmount,
+                referenced: Some(Type::Deposit),
+            }]
+        );
+    }
+
+    #[test]
+    fn dedup_skips_already_applied_deposit() {
+        let mut engine = Engine::new(Accounts::new()).with_dedup();
+
+        engine.process(Transactions::from(vec![transaction(
+            Type::Deposit,
+            1,
+            1,
+            Some(Amount::from(10)),
+        )]));
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(1))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(11));
+        assert_eq!(engine.transaction_count(1), 2);
+    }
+
+    #[test]
+    fn without_dedup_duplicate_deposit_is_applied() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(20));
+    }
+
+    #[test]
+    fn report_records_rejected_transactions() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, None),
+            transaction(Type::Deposit, 1, 3, Some(Amount::from(1))),
+        ]));
+
+        let report = engine.report();
+        assert_eq!(
+            report.rejected,
+            vec![
+                Rejection {
+                    index: 1,
+                    client: 1,
+                    tx: 2,
+                    reason: RejectReason::InsufficientFunds,
+                    referenced: None,
+                },
+                Rejection {
+                    index: 4,
+                    client: 1,
+                    tx: 3,
+                    reason: RejectReason::AccountLocked,
+                    referenced: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn report_is_clean_without_rejections() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![transaction(
+            Type::Deposit,
+            1,
+            1,
+            Some(Amount::from(5)),
+        )]));
+
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    fn dispute_resolve_dispute_chargeback_locks_once() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, None),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(5));
+        assert!(account.locked);
+        assert!(engine.disputed_transactions(1).is_empty());
+    }
+
+    #[test]
+    fn dispute_after_chargeback_is_rejected() {
+        let mut engine =
+            Engine::new(Accounts::new()).with_locked_policy(LockedPolicy::AllowDeposits);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        // Reopen the account so the dispute reaches the state machine instead of the lock check
+        engine.accounts.get_mut(1).locked = false;
+        engine.process(Transactions::from(vec![transaction(
+            Type::Dispute,
+            1,
+            1,
+            None,
+        )]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, 0.into());
+        assert_eq!(
+            engine
+                .report()
+                .rejected
+                .last()
+                .map(|rejection| rejection.reason),
+            Some(RejectReason::ChargedBack)
+        );
+    }
+
+    #[test]
+    fn held_funds_cannot_be_withdrawn() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(100))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(50))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::from(100));
+        assert_eq!(account.total, Amount::from(100));
+        assert_eq!(
+            engine
+                .report()
+                .rejected
+                .last()
+                .map(|rejection| rejection.reason),
+            Some(RejectReason::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn currencies_are_kept_in_separate_accounts() {
+        let mut engine = Engine::new(Accounts::new().with_base_currency("USD"));
+
+        let mut eur_deposit = transaction(Type::Deposit, 1, 1, Some(Amount::from(10)));
+        eur_deposit.currency = Some("EUR".to_string());
+        let mut eur_withdrawal = transaction(Type::Withdrawal, 1, 3, Some(Amount::from(8)));
+        eur_withdrawal.currency = Some("EUR".to_string());
+
+        engine.process(Transactions::from(vec![
+            eur_deposit,
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            eur_withdrawal,
+        ]));
+
+        let eur = engine
+            .accounts()
+            .get_in(1, Some("EUR"))
+            .expect("Failed to get account");
+        assert_eq!(eur.available, Amount::from(2));
+
+        let usd = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(usd.available, Amount::from(5));
+        assert_eq!(usd.currency.as_deref(), Some("USD"));
+    }
+
+    #[test]
+    fn dispute_in_other_currency_is_ignored() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let mut eur_deposit = transaction(Type::Deposit, 1, 1, Some(Amount::from(10)));
+        eur_deposit.currency = Some("EUR".to_string());
+        let mut usd_dispute = transaction(Type::Dispute, 1, 1, None);
+        usd_dispute.currency = Some("USD".to_string());
+        let mut eur_dispute = transaction(Type::Dispute, 1, 1, None);
+        eur_dispute.currency = Some("EUR".to_string());
+
+        engine.process(Transactions::from(vec![eur_deposit, usd_dispute]));
+
+        let eur = engine
+            .accounts()
+            .get_in(1, Some("EUR"))
+            .expect("Failed to get account");
+        assert_eq!(eur.held, 0.into());
+        assert!(engine.accounts().get_in(1, Some("USD")).is_none());
+
+        engine.process(Transactions::from(vec![eur_dispute]));
+
+        let eur = engine
+            .accounts()
+            .get_in(1, Some("EUR"))
+            .expect("Failed to get account");
+        assert_eq!(eur.available, 0.into());
+        assert_eq!(eur.held, Amount::from(10));
+    }
+
+    #[test]
+    fn process_one_steps_the_engine() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process_one(transaction(Type::Deposit, 1, 1, Some(Amount::from(5))));
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+
+        engine.process_one(transaction(Type::Dispute, 1, 1, None));
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::from(5));
+    }
+
+    #[test]
+    fn client_ledger_tracks_running_balances() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(7))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(4))),
+            transaction(Type::Dispute, 1, 1, None),
+        ]));
+
+        let ledger = engine
+            .client_ledger(1)
+            .iter()
+            .map(|entry| {
+                (
+                    entry.index,
+                    entry.transaction.tx,
+                    entry.available,
+                    entry.held,
+                    entry.total,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            ledger,
+            [
+                (0, 1, Amount::from(10), 0.into(), Amount::from(10)),
+                (2, 3, Amount::from(6), 0.into(), Amount::from(6)),
+                (3, 1, Amount::from(-4), Amount::from(10), Amount::from(6)),
+            ]
+        );
+    }
+
+    #[test]
+    fn recompute_client_matches_the_live_account() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(7))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(4))),
+            transaction(Type::Deposit, 1, 4, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 4, None),
+            transaction(Type::Dispute, 2, 2, None),
+            transaction(Type::Chargeback, 2, 2, None),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, None),
+        ]));
+
+        for client in [1, 2] {
+            let live = engine
+                .accounts()
+                .get(client)
+                .expect("Failed to get account");
+            assert_eq!(engine.recompute_client(client), *live);
+        }
+
+        // Recomputing doesn't touch the engine state
+        assert_eq!(engine.is_disputed(4), Some(true));
+        assert_eq!(engine.recompute_client(3), Account::new(3));
+    }
+
+    #[test]
+    fn process_limited_resumes_where_it_stopped() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let processed = engine.process_limited(
+            Transactions::from(vec![
+                transaction(Type::Deposit, 1, 1, Some(Amount::from(1))),
+                transaction(Type::Deposit, 1, 2, Some(Amount::from(2))),
+                transaction(Type::Deposit, 1, 3, Some(Amount::from(3))),
+                transaction(Type::Deposit, 1, 4, Some(Amount::from(4))),
+                transaction(Type::Deposit, 1, 5, Some(Amount::from(5))),
+            ]),
+            3,
+        );
+        assert_eq!(processed, 3);
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(6));
+
+        assert_eq!(engine.process_limited(Transactions::default(), 10), 2);
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(15));
+
+        assert_eq!(engine.process_limited(Transactions::default(), 10), 0);
+    }
+
+    #[test]
+    fn process_iter_processes_an_iterator_adapter() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process_iter(
+            (1..=4).map(|tx| transaction(Type::Deposit, 1, tx, Some(Amount::from(i64::from(tx))))),
+        );
+        engine.process_iter(std::iter::once(transaction(Type::Dispute, 1, 4, None)));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(6));
+        assert_eq!(account.held, Amount::from(4));
+        assert_eq!(engine.transactions().len(), 5);
+    }
+
+    #[test]
+    fn chargeback_records_the_transaction_that_locked_the_account() {
+        let mut engine = Engine::new(Accounts::new()).with_dispute_resolution_when_locked();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Deposit, 2, 3, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 2, None),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Chargeback, 1, 2, None),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert!(account.locked);
+        assert_eq!(account.locked_by, Some(2));
+
+        let account = engine.accounts().get(2).expect("Failed to get account");
+        assert_eq!(account.locked_by, None);
+    }
+
+    #[test]
+    fn changed_clients_only_lists_clients_touched_since_last_output() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(20))),
+        ]));
+        assert_eq!(engine.changed_clients(), [1, 2]);
+
+        engine
+            .accounts_mut()
+            .write_csv_dirty(std::io::sink())
+            .expect("Failed to write accounts");
+
+        engine.process(Transactions::from(vec![transaction(
+            Type::Withdrawal,
+            1,
+            3,
+            Some(Amount::from(4)),
+        )]));
+        assert_eq!(engine.changed_clients(), [1]);
+
+        let mut output = vec![];
+        engine
+            .accounts_mut()
+            .write_csv_dirty(&mut output)
+            .expect("Failed to write accounts");
+
+        let output = String::from_utf8(output).expect("Output is not valid utf8");
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n1,6,0,6,false\n"
+        );
+    }
+
+    ///
+    /// Deposits 10 on client 1, withdraws 4 (tx 2) and disputes the withdrawal
+    ///
+    fn disputed_withdrawal_engine() -> Engine {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(4))),
+            transaction(Type::Dispute, 1, 2, None),
+        ]));
+
+        engine
+    }
+
+    #[test]
+    fn dispute_on_withdrawal_holds_provisional_credit() {
+        let engine = disputed_withdrawal_engine();
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(6));
+        assert_eq!(account.held, Amount::from(4));
+        assert_eq!(account.total, Amount::from(10));
+        assert_eq!(engine.is_disputed(2), Some(true));
+    }
+
+    #[test]
+    fn resolve_on_withdrawal_removes_provisional_credit() {
+        let mut engine = disputed_withdrawal_engine();
+
+        engine.process_one(transaction(Type::Resolve, 1, 2, None));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(6));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(6));
+        assert!(!account.locked);
+        assert_eq!(engine.is_disputed(2), Some(false));
+    }
+
+    #[test]
+    fn chargeback_on_withdrawal_credits_funds() {
+        let mut engine = disputed_withdrawal_engine();
+
+        engine.process_one(transaction(Type::Chargeback, 1, 2, None));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(10));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn partial_chargeback_on_withdrawal_resolves_remainder() {
+        let mut engine = disputed_withdrawal_engine();
+
+        engine.process_one(transaction(Type::Chargeback, 1, 2, Some(Amount::from(1))));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(7));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(7));
+    }
+
+    #[test]
+    fn deposit_dispute_matrix() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Dispute, 1, 2, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::from(15));
+        assert_eq!(account.total, Amount::from(15));
+
+        engine.process_one(transaction(Type::Resolve, 1, 2, None));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.held, Amount::from(10));
+        assert_eq!(account.total, Amount::from(15));
+
+        engine.process_one(transaction(Type::Chargeback, 1, 1, None));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(5));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn progress_hook_fires_every_n_transactions() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let calls = Rc::new(RefCell::new(vec![]));
+        let recorded = Rc::clone(&calls);
+        engine.set_progress_hook(3, move |processed| recorded.borrow_mut().push(processed));
+
+        engine.process(Transactions::from(
+            (1..=7)
+                .map(|tx| transaction(Type::Deposit, 1, tx, Some(Amount::from(1))))
+                .collect::<Vec<_>>(),
+        ));
+        assert_eq!(*calls.borrow(), [3, 6]);
+
+        // The count carries over to later calls
+        engine.process_one(transaction(Type::Deposit, 1, 8, Some(Amount::from(1))));
+        engine.process_one(transaction(Type::Deposit, 1, 9, Some(Amount::from(1))));
+        assert_eq!(*calls.borrow(), [3, 6, 9]);
+    }
+
+    #[test]
+    fn metrics_text_has_prometheus_shape() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 2, 3, Some(Amount::from(50))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Dispute, 2, 2, None),
+            transaction(Type::Chargeback, 2, 2, None),
+        ]));
+
+        let metrics = engine.metrics_text();
+
+        // Every line is either a comment or a `name{labels} value` sample with a numeric value
+        let mut samples = HashMap::new();
+        for line in metrics.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                assert!(
+                    comment.starts_with("HELP toy_payments_")
+                        || comment.starts_with("TYPE toy_payments_")
+                );
+                continue;
+            }
+
+            let (name, value) = line.rsplit_once(' ').expect("Sample has no value");
+            assert!(name.starts_with("toy_payments_"));
+            assert!(value.parse::<f64>().is_ok(), "{line}");
+            samples.insert(name.to_string(), value.to_string());
+        }
+
+        assert_eq!(samples["toy_payments_transactions_processed_total"], "6");
+        assert_eq!(
+            samples["toy_payments_transactions_total{type=\"deposit\"}"],
+            "2"
+        );
+        assert_eq!(
+            samples["toy_payments_transactions_total{type=\"withdrawal\"}"],
+            "1"
+        );
+        assert_eq!(
+            samples["toy_payments_transactions_total{type=\"dispute\"}"],
+            "2"
+        );
+        assert_eq!(
+            samples["toy_payments_transactions_total{type=\"resolve\"}"],
+            "0"
+        );
+        assert_eq!(
+            samples["toy_payments_transactions_total{type=\"chargeback\"}"],
+            "1"
+        );
+        assert_eq!(samples["toy_payments_transactions_rejected_total"], "1");
+        assert_eq!(samples["toy_payments_accounts_locked"], "1");
+        assert_eq!(samples["toy_payments_held_funds{currency=\"\"}"], "10");
+    }
+
+    #[test]
+    fn metrics_text_splits_held_funds_per_currency_and_follows_undo() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            Transaction {
+                currency: Some("EUR".to_string()),
+                ..transaction(Type::Deposit, 1, 1, Some(Amount::from(10)))
+            },
+            Transaction {
+                currency: Some("USD".to_string()),
+                ..transaction(Type::Deposit, 1, 2, Some(Amount::from(3)))
+            },
+            Transaction {
+                currency: Some("EUR".to_string()),
+                ..transaction(Type::Dispute, 1, 1, None)
+            },
+            Transaction {
+                currency: Some("USD".to_string()),
+                ..transaction(Type::Dispute, 1, 2, None)
+            },
+            transaction(Type::Deposit, 1, 3, Some(Amount::from(1))),
+        ]));
+        engine.undo_last().expect("Failed to undo deposit");
+
+        let metrics = engine.metrics_text();
+        assert!(metrics.contains("toy_payments_held_funds{currency=\"EUR\"} 10\n"));
+        assert!(metrics.contains("toy_payments_held_funds{currency=\"USD\"} 3\n"));
+        assert!(metrics.contains("toy_payments_transactions_processed_total 4\n"));
+        assert!(metrics.contains("toy_payments_transactions_total{type=\"deposit\"} 2\n"));
+    }
+
+    #[test]
+    fn undo_last_reverses_deposit() {
+        let mut engine = Engine::new(Accounts::new()).with_dedup();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+        ]));
+        engine.undo_last().expect("Failed to undo deposit");
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.total, Amount::from(10));
+        assert_eq!(account.total_deposited, Amount::from(10));
+        assert_eq!(engine.transaction_count(1), 1);
+        assert_eq!(engine.is_disputed(2), None);
+
+        // The undone tx id can be applied again
+        engine.process_one(transaction(Type::Deposit, 1, 2, Some(Amount::from(3))));
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(13));
+    }
+
+    #[test]
+    fn undo_last_removes_the_accounts_the_transaction_created() {
+        let deposits = || {
+            vec![
+                transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+                transaction(Type::Deposit, 2, 2, Some(Amount::from(4))),
+            ]
+        };
+        let mut fresh = Engine::new(Accounts::new()).with_withdrawal_fee(100, 999);
+        fresh.process(Transactions::from(deposits()));
+
+        let mut engine = Engine::new(Accounts::new()).with_withdrawal_fee(100, 999);
+        let mut transactions = deposits();
+        transactions.push(transaction(Type::Deposit, 3, 3, Some(Amount::from(5))));
+        transactions.push(transaction(Type::Withdrawal, 3, 4, Some(Amount::from(2))));
+        engine.process(Transactions::from(transactions));
+
+        // Undoing the withdrawal removes the fee account its fee created, undoing the deposit removes client 3
+        engine.undo_last().expect("Failed to undo withdrawal");
+        engine.undo_last().expect("Failed to undo deposit");
+
+        assert_eq!(
+            engine.accounts().iter().collect::<Vec<_>>(),
+            fresh.accounts().iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            engine.accounts().client_count(),
+            fresh.accounts().client_count()
+        );
+
+        // The next client gets its account like on the fresh engine
+        engine.process_one(transaction(Type::Deposit, 4, 3, Some(Amount::from(1))));
+        fresh.process_one(transaction(Type::Deposit, 4, 3, Some(Amount::from(1))));
+        assert_eq!(
+            engine.accounts().iter().collect::<Vec<_>>(),
+            fresh.accounts().iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn undo_last_reverses_withdrawal() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(4))),
+        ]));
+        engine.undo_last().expect("Failed to undo withdrawal");
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.total, Amount::from(10));
+        assert_eq!(account.total_withdrawn, 0.into());
+
+        // The deposit created the account, so undoing it removes the account
+        engine.undo_last().expect("Failed to undo deposit");
+        assert!(engine.accounts().get(1).is_none());
+
+        assert!(matches!(
+            engine.undo_last(),
+            Err(PaymentsError::NothingToUndo)
+        ));
+    }
+
+    #[test]
+    fn undo_last_reverses_partially_filled_withdrawal() {
+        let mut engine =
+            Engine::new(Accounts::new()).with_withdrawal_policy(WithdrawalPolicy::PartialFill);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(40))),
+        ]));
+        engine.undo_last().expect("Failed to undo withdrawal");
+
+        // Only the withdrawn amount is given back, not the requested one
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.total_withdrawn, 0.into());
+        assert!(engine.report().partial_fills.is_empty());
+    }
+
+    #[test]
+    fn undo_last_keeps_transactions_that_were_not_processed() {
+        let mut engine = Engine::new(Accounts::new());
+
+        let processed = engine.process_limited(
+            Transactions::from(vec![
+                transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+                transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+                transaction(Type::Withdrawal, 1, 3, Some(Amount::from(4))),
+            ]),
+            2,
+        );
+        assert_eq!(processed, 2);
+        engine.undo_last().expect("Failed to undo deposit");
+        assert_eq!(engine.transactions().len(), 2);
+
+        // The withdrawal that wasn't processed yet is still applied next
+        assert_eq!(engine.process_limited(Transactions::default(), 10), 1);
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(6));
+        assert_eq!(account.total_withdrawn, Amount::from(4));
+    }
+
+    #[test]
+    fn undo_last_of_rejected_withdrawal_removes_rejection() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(40))),
+        ]));
+        engine.undo_last().expect("Failed to undo withdrawal");
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    fn undo_last_of_dispute_fails() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+        ]));
+
+        assert!(matches!(
+            engine.undo_last(),
+            Err(PaymentsError::NotReversible {
+                index: 1,
+                r#type: Type::Dispute
+            })
+        ));
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.held, Amount::from(10));
+    }
+
+    #[test]
+    fn transactions_can_be_audited_by_type() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(2))),
+            transaction(Type::Dispute, 2, 2, None),
+            transaction(Type::Resolve, 2, 2, None),
+        ]));
+
+        let counts = Type::ALL.map(|r#type| {
+            engine
+                .transactions()
+                .iter()
+                .filter(|transaction| transaction.r#type == r#type)
+                .count()
+        });
+
+        assert_eq!(counts, [2, 1, 1, 1, 0, 0]);
+        assert_eq!(engine.transactions().iter().count(), 5);
+    }
+
+    #[test]
+    fn dispute_after_withdrawal_overdraws_account() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(-10));
+        assert_eq!(account.held, Amount::from(10));
+        assert_eq!(account.total, 0.into());
+        assert!(account.is_overdrawn());
+    }
+
+    #[test]
+    fn chargeback_of_one_of_two_disputes_keeps_the_other_held() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Dispute, 1, 2, None),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::from(5));
+        assert_eq!(account.total, Amount::from(5));
+        assert!(account.locked);
+        assert!(engine.report().held_discrepancies.is_empty());
+    }
+
+    #[test]
+    fn chargeback_of_funds_held_elsewhere_is_clamped() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 2, 1, None),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, account.available + account.held);
+        assert_eq!(
+            engine.report().held_discrepancies,
+            vec![HeldDiscrepancy {
+                index: 2,
+                client: 1,
+                tx: 1,
+                settled: Amount::from(10),
+                held: 0.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn account_can_be_compared_as_a_whole() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(3))),
+            transaction(Type::Dispute, 1, 2, None),
+        ]));
+
+        let expected = Account {
+            client: 1,
+            currency: None,
+            available: Amount::from(7),
+            held: Amount::from(5),
+            total: Amount::from(12),
+            locked: false,
+            locked_by: None,
+            total_deposited: Amount::from(15),
+            total_withdrawn: Amount::from(3),
+            rejected_withdrawals: 0,
+            first_tx_index: 0,
+            last_tx_index: 3,
+        };
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(*account, expected);
+        assert_eq!(account.clone(), expected);
+    }
+
+    #[test]
+    fn reordering_transactions_of_different_clients_keeps_the_result() {
+        let client1 = [
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, None),
+        ];
+        let client2 = [
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 2, 3, Some(Amount::from(2))),
+        ];
+
+        let mut interleaved = Engine::new(Accounts::new());
+        interleaved.process(Transactions::from(vec![
+            client2[0].clone(),
+            client1[0].clone(),
+            client1[1].clone(),
+            client2[1].clone(),
+            client1[2].clone(),
+        ]));
+
+        let mut grouped = Engine::new(Accounts::new());
+        grouped.process(Transactions::from(
+            client1.into_iter().chain(client2).collect::<Vec<_>>(),
+        ));
+
+        // Only the balances are compared, the tx indexes of the accounts follow the input order
+        for client in [1, 2] {
+            assert_eq!(
+                interleaved.accounts().get(client).map(Account::balances),
+                grouped.accounts().get(client).map(Account::balances)
+            );
+        }
+    }
+
+    #[test]
+    fn reordering_dispute_and_resolve_of_the_same_client_changes_the_result() {
+        let deposit = transaction(Type::Deposit, 1, 1, Some(Amount::from(10)));
+        let dispute = transaction(Type::Dispute, 1, 1, None);
+        let resolve = transaction(Type::Resolve, 1, 1, None);
+
+        let mut in_order = Engine::new(Accounts::new());
+        in_order.process(Transactions::from(vec![
+            deposit.clone(),
+            dispute.clone(),
+            resolve.clone(),
+        ]));
+
+        // The resolve comes before the dispute, so it is ignored and the dispute stays open
+        let mut reordered = Engine::new(Accounts::new());
+        reordered.process(Transactions::from(vec![deposit, resolve, dispute]));
+
+        let account = in_order.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.held, 0.into());
+
+        let account = reordered.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert_eq!(account.held, Amount::from(10));
+    }
+
+    #[test]
+    fn extended_transactions_are_processed_after_the_existing_ones() {
+        let mut first = Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, None),
+        ]);
+        first.extend(Transactions::from(vec![transaction(
+            Type::Chargeback,
+            1,
+            1,
+            None,
+        )]));
+
+        let mut engine = Engine::new(Accounts::new());
+        engine.process(first);
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.total, 0.into());
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn dispute_above_the_open_disputes_limit_is_rejected() {
+        let mut engine = Engine::new(Accounts::new()).with_max_open_disputes_per_client(2);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(1))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(2))),
+            transaction(Type::Deposit, 1, 3, Some(Amount::from(3))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Dispute, 1, 2, None),
+            transaction(Type::Dispute, 1, 3, None),
+        ]));
+
+        assert_eq!(
+            engine.report().rejected,
+            vec![Rejection {
+                index: 5,
+                client: 1,
+                tx: 3,
+                reason: RejectReason::TooManyDisputes,
+                referenced: Some(Type::Deposit),
+            }]
+        );
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.held, Amount::from(3));
+        assert_eq!(engine.is_disputed(3), Some(false));
+    }
+
+    #[test]
+    fn settled_dispute_frees_room_under_the_open_disputes_limit() {
+        let mut engine = Engine::new(Accounts::new()).with_max_open_disputes_per_client(1);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(1))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(2))),
+            transaction(Type::Deposit, 1, 3, Some(Amount::from(3))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, None),
+            transaction(Type::Dispute, 1, 2, None),
+            transaction(Type::Chargeback, 1, 2, None),
+        ]));
+
+        assert!(engine.report().is_clean());
+        assert!(
+            engine
+                .accounts()
+                .get(1)
+                .expect("Failed to get account")
+                .locked
+        );
+
+        // A partial resolve keeps the dispute open, so it still counts towards the limit
+        let mut engine = Engine::new(Accounts::new()).with_max_open_disputes_per_client(1);
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(1))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(2))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, Some(Amount::new(5, 1))),
+            transaction(Type::Dispute, 1, 2, None),
+        ]));
+
+        assert_eq!(
+            engine
+                .report()
+                .rejected
+                .iter()
+                .map(|rejection| rejection.reason)
+                .collect::<Vec<_>>(),
+            vec![RejectReason::TooManyDisputes]
+        );
+    }
+
+    #[test]
+    fn chargeback_locks_one_of_several_accounts() {
+        let mut engine = Engine::new(Accounts::new());
+        assert_eq!(engine.account_count(), 0);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Deposit, 3, 3, Some(Amount::from(2))),
+            transaction(Type::Dispute, 2, 2, None),
+            transaction(Type::Chargeback, 2, 2, None),
+        ]));
+
+        assert_eq!(engine.account_count(), 3);
+        assert_eq!(engine.locked_count(), 1);
+        assert_eq!(engine.accounts().locked_count(), 1);
+    }
+
+    #[test]
+    fn low_balance_hook_fires_when_a_withdrawal_goes_below_the_threshold() {
+        let mut engine =
+            Engine::new(Accounts::new()).with_withdrawal_policy(WithdrawalPolicy::PartialFill);
+
+        let recorded = Rc::new(RefCell::new(vec![]));
+        let alerts = Rc::clone(&recorded);
+        engine.set_low_balance_hook(Amount::from(5), move |client, available| {
+            alerts.borrow_mut().push((client, available));
+        });
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(4))),
+            transaction(Type::Deposit, 2, 3, Some(Amount::from(3))),
+            transaction(Type::Withdrawal, 1, 4, Some(Amount::from(2))),
+            transaction(Type::Withdrawal, 1, 5, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 6, Some(Amount::from(1))),
+        ]));
+
+        // The first withdrawal stays above the threshold, the deposit below it is not a withdrawal
+        // and the last withdrawal has nothing left to withdraw so the balance doesn't change
+        assert_eq!(
+            *recorded.borrow(),
+            [(1, Amount::from(4)), (1, Amount::ZERO)]
+        );
+    }
+
+    #[test]
+    fn resolve_all_disputes_releases_the_held_funds_of_every_account() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Deposit, 2, 3, Some(Amount::from(8))),
+            transaction(Type::Withdrawal, 2, 4, Some(Amount::from(3))),
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Dispute, 1, 2, None),
+            transaction(Type::Resolve, 1, 2, Some(Amount::from(2))),
+            transaction(Type::Dispute, 2, 4, None),
+        ]));
+
+        assert_eq!(engine.resolve_all_disputes(), 3);
+        assert_eq!(engine.resolve_all_disputes(), 0);
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.available, Amount::from(15));
+        assert_eq!(account.total, Amount::from(15));
+
+        let account = engine.accounts().get(2).expect("Failed to get account");
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(account.total, Amount::from(5));
+
+        assert!(engine.disputed_transactions(1).is_empty());
+        assert!(engine.disputed_transactions(2).is_empty());
+    }
+
+    #[test]
+    fn resolve_all_disputes_counts_only_the_resolved_disputes() {
+        let mut engine = Engine::new(Accounts::new());
+
+        // The flagged duplicate doesn't hold any funds, so there's nothing to resolve for it
+        let mut flagged_duplicate = transaction(Type::Deposit, 1, 1, Some(Amount::from(5)));
+        flagged_duplicate.disputed = true;
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            flagged_duplicate,
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(4))),
+            transaction(Type::Dispute, 1, 2, None),
+        ]));
+
+        assert_eq!(engine.resolve_all_disputes(), 1);
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.available, Amount::from(14));
+    }
+
+    #[test]
+    fn resolve_all_disputes_frees_the_dispute_slot_of_the_disputing_client() {
+        let mut engine = Engine::new(Accounts::new()).with_max_open_disputes_per_client(1);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 2, 1, None),
+        ]));
+
+        assert_eq!(engine.resolve_all_disputes(), 1);
+
+        // The held funds were released on the account of the disputing client,
+        // which can open a dispute again
+        let account = engine.accounts().get(2).expect("Failed to get account");
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(5));
+
+        engine.process_one(transaction(Type::Dispute, 2, 2, None));
+        assert!(engine.report().is_clean());
+        assert_eq!(engine.is_disputed(2), Some(true));
+    }
+
+    #[test]
+    fn chargeback_without_dispute_is_ignored_by_default() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Chargeback, 1, 1, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.total, Amount::from(10));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn direct_chargeback_of_deposit_takes_available_funds() {
+        let mut engine = Engine::new(Accounts::new()).with_direct_chargeback();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(12))),
+            transaction(Type::Chargeback, 1, 2, None),
+        ]));
+
+        // The charged back funds were already withdrawn, so the available funds go negative
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(-2));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(-2));
+        assert!(account.locked);
+        assert_eq!(account.locked_by, Some(2));
+        assert_eq!(engine.is_disputed(2), Some(false));
+    }
+
+    #[test]
+    fn direct_chargeback_of_withdrawal_credits_available_funds() {
+        let mut engine = Engine::new(Accounts::new()).with_direct_chargeback();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(4))),
+            transaction(Type::Chargeback, 1, 2, None),
+            transaction(Type::Chargeback, 1, 2, None),
+        ]));
+
+        // The second chargeback finds the transaction already charged back
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.total, Amount::from(10));
+        assert!(account.locked);
+    }
+
+    #[test]
+    fn dispute_of_rejected_withdrawal_is_ignored() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(
+            Transactions::from_csv_str(
+                "type,client,tx,amount\ndeposit,1,1,10\nwithdrawal,1,2,100\ndispute,1,2,\nchargeback,1,2,\n",
+            )
+            .expect("Failed to read transactions"),
+        );
+
+        // The withdrawal was never applied, so there are no withdrawn funds to credit back
+        assert_eq!(
+            accounts_csv(&engine),
+            "client,available,held,total,locked\n1,10,0,10,false\n"
+        );
+        assert_eq!(engine.is_disputed(2), Some(false));
+    }
+
+    #[test]
+    fn direct_chargeback_of_rejected_withdrawal_is_ignored() {
+        let mut engine = Engine::new(Accounts::new()).with_direct_chargeback();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(100))),
+            transaction(Type::Chargeback, 1, 2, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.total, Amount::from(10));
+        assert!(!account.locked);
+    }
+
+    #[test]
+    fn lifetime_totals_survive_a_chargeback() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(3))),
+            transaction(Type::Withdrawal, 1, 4, Some(Amount::from(100))),
+            transaction(Type::Dispute, 1, 2, None),
+            transaction(Type::Chargeback, 1, 2, None),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.total, Amount::from(7));
+        assert_eq!(account.total_deposited, Amount::from(15));
+        assert_eq!(account.total_withdrawn, Amount::from(3));
+    }
+
+    #[test]
+    fn dispute_with_matching_amount_is_applied() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::new(105, 1))),
+            transaction(Type::Dispute, 1, 1, Some(Amount::new(1050, 2))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.held, Amount::new(105, 1));
+        assert_eq!(account.available, 0.into());
+        assert!(engine.report().is_clean());
+    }
+
+    #[test]
+    fn dispute_with_mismatching_amount_is_rejected() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 1, 1, Some(Amount::from(5))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.held, 0.into());
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(engine.is_disputed(1), Some(false));
+        assert_eq!(
+            engine.report().rejected,
+            [Rejection {
+                index: 1,
+                client: 1,
+                tx: 1,
+                reason: RejectReason::AmountMismatch,
+                referenced: Some(Type::Deposit),
+            }]
+        );
+    }
+
+    #[test]
+    fn dispute_of_unknown_tx_creates_no_account() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Dispute, 2, 7, None),
+            transaction(Type::Resolve, 3, 7, None),
+            transaction(Type::Chargeback, 4, 7, None),
+        ]));
+
+        assert!(engine.accounts().get(2).is_none());
+        assert!(engine.accounts().get(3).is_none());
+        assert!(engine.accounts().get(4).is_none());
+        assert_eq!(engine.account_count(), 1);
+        assert_eq!(engine.changed_clients(), vec![1]);
+    }
+
+    #[test]
+    fn zero_amount_deposit_creates_the_account() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![transaction(
+            Type::Deposit,
+            1,
+            1,
+            Some(Amount::ZERO),
+        )]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.total, Amount::ZERO);
+        assert_eq!(account.first_tx_index, 0);
+        assert_eq!(engine.changed_clients(), vec![1]);
+    }
+
+    #[test]
+    fn withdrawal_without_fee_is_unchanged() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(100))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(100))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, 0.into());
+        assert!(engine.report().is_clean());
+        assert!(engine.accounts().get(0).is_none());
+    }
+
+    #[test]
+    fn withdrawal_fee_is_credited_to_the_fee_account() {
+        let mut engine = Engine::new(Accounts::new()).with_withdrawal_fee(50, 999);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(200))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(100))),
+            transaction(Type::Deposit, 2, 3, Some(Amount::from(100))),
+            transaction(Type::Withdrawal, 2, 4, Some(Amount::from(100))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::new(995, 1));
+        assert_eq!(account.total, Amount::new(995, 1));
+
+        let fees = engine
+            .accounts()
+            .get(999)
+            .expect("Failed to get fee account");
+        assert_eq!(fees.available, Amount::new(5, 1));
+        assert_eq!((fees.first_tx_index, fees.last_tx_index), (1, 1));
+
+        // The available funds of client 2 cover the withdrawal but not its fee
+        assert_eq!(
+            engine.report().rejected,
+            vec![Rejection {
+                index: 3,
+                client: 2,
+                tx: 4,
+                reason: RejectReason::InsufficientFunds,
+                referenced: None,
+            }]
+        );
+
+        engine
+            .undo_last()
+            .expect("Failed to undo the rejected withdrawal");
+        engine.undo_last().expect("Failed to undo the deposit");
+        engine.undo_last().expect("Failed to undo the withdrawal");
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(200));
+
+        // The fee of the undone withdrawal created the fee account, so it's removed as well
+        assert!(engine.accounts().get(999).is_none());
+    }
+
+    #[test]
+    fn account_tracks_the_first_and_last_changing_transaction() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 9, None),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(4))),
+            transaction(Type::Withdrawal, 1, 4, Some(Amount::from(40))),
+        ]));
+
+        // The dispute of an unknown tx and the rejected withdrawal don't change the account
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.first_tx_index, 0);
+        assert_eq!(account.last_tx_index, 3);
+
+        let account = engine.accounts().get(2).expect("Failed to get account");
+        assert_eq!((account.first_tx_index, account.last_tx_index), (1, 1));
+    }
+
+    #[test]
+    fn engine_with_capacity_processes_like_a_new_engine() {
+        let mut engine = Engine::with_capacity(Accounts::new(), 100);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(4))),
+        ]));
+
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(6));
+        assert_eq!(engine.transactions().len(), 2);
+    }
+
+    #[test]
+    fn event_log_records_deposit_then_withdrawal() {
+        let mut engine = Engine::new(Accounts::new()).with_event_log();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(4))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(40))),
+        ]));
+
+        let event = |index, field, old: i64, new: i64, reason| BalanceEvent {
+            index,
+            client: 1,
+            currency: None,
+            field,
+            old: Amount::from(old),
+            new: Amount::from(new),
+            reason,
+            referenced: None,
+        };
+
+        // The rejected withdrawal doesn't change any balance
+        assert_eq!(
+            engine.event_log(),
+            [
+                event(0, BalanceField::Available, 0, 10, Type::Deposit),
+                event(0, BalanceField::Total, 0, 10, Type::Deposit),
+                event(1, BalanceField::Available, 10, 6, Type::Withdrawal),
+                event(1, BalanceField::Total, 10, 6, Type::Withdrawal),
+            ]
+        );
+    }
+
+    #[test]
+    fn undo_last_removes_the_events_of_the_undone_transaction() {
+        let mut engine = Engine::new(Accounts::new())
+            .with_event_log()
+            .with_withdrawal_fee(50, 999);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(200))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(100))),
+        ]));
+        // The fee credited to the fee account is recorded with the withdrawal
+        assert!(engine
+            .event_log()
+            .iter()
+            .any(|event| event.client == 999 && event.index == 1));
+
+        engine.undo_last().expect("Failed to undo withdrawal");
+        assert!(engine.event_log().iter().all(|event| event.index == 0));
+    }
+
+    #[test]
+    fn referenced_type_is_surfaced_in_rejections_and_events() {
+        let mut engine = Engine::new(Accounts::new()).with_event_log();
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(4))),
+            transaction(Type::Dispute, 1, 2, None),
+            transaction(Type::Chargeback, 1, 2, None),
+            transaction(Type::Dispute, 1, 2, None),
+        ]));
+
+        // The chargeback locked the account, so disputing the withdrawal again is rejected
+        let rejection = engine
+            .report()
+            .rejected
+            .last()
+            .expect("Expected a rejection");
+        assert_eq!(rejection.reason, RejectReason::AccountLocked);
+        assert_eq!(rejection.referenced, Some(Type::Withdrawal));
+
+        let events = engine.event_log();
+        assert_eq!(events[0].referenced, None);
+        assert!(events
+            .iter()
+            .filter(|event| event.index >= 2)
+            .all(|event| event.referenced == Some(Type::Withdrawal)));
     }
 
-    ///
-    /// Processes a new collection of transactions.
-    ///
-    pub fn process(&mut self, trxs: Transactions) {
-        self.transactions.extend(trxs);
+    #[test]
+    fn event_log_is_off_by_default() {
+        let mut engine = Engine::new(Accounts::new());
 
-        for index in self.last_processed_transaction_index..self.transactions.len() {
-            if let Some(transaction) = self.transactions.get(index) {
-                let client = transaction.client;
+        engine.process(Transactions::from(vec![transaction(
+            Type::Deposit,
+            1,
+            1,
+            Some(Amount::from(10)),
+        )]));
 
-                // Process current transaction
-                self.process_transaction(index, client);
-            }
-        }
+        assert!(engine.event_log().is_empty());
+    }
 
-        // Update the last processed transaction index so we don't have to reprocess all transactions from the start the next time
-        self.last_processed_transaction_index = self.transactions.len();
+    #[test]
+    fn independent_runs_have_the_same_checksum() {
+        let run = || {
+            let mut engine = Engine::new(Accounts::new());
+            engine.process(
+                Transactions::from_csv(Path::new("tests/resources/inputs/trx3.csv"))
+                    .expect("Failed to read transactions"),
+            );
+            engine.into_accounts()
+        };
+
+        let (first, second) = (run(), run());
+        assert!(!first.is_empty());
+        assert_eq!(first.checksum(), second.checksum());
     }
 
-    ///
-    /// Processes a single transaction
-    ///
-    fn process_transaction(&mut self, current_transaction_index: usize, client: u16) {
-        // Retrieve the account for the client
-        let account = self.accounts.get_mut(client);
+    #[test]
+    fn new_clients_above_max_clients_are_rejected() {
+        let mut engine = Engine::new(Accounts::new()).with_max_clients(2);
 
-        // Check if the account is locked, if so, skip the transaction
-        if account.locked {
-            return;
-        }
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Deposit, 3, 3, Some(Amount::from(7))),
+            transaction(Type::Withdrawal, 1, 4, Some(Amount::from(4))),
+            transaction(Type::Deposit, 2, 5, Some(Amount::from(1))),
+        ]));
 
-        let transaction = self.transactions.get(current_transaction_index);
-        if let Some(transaction) = transaction {
-            match transaction.r#type {
-                Type::Deposit => {
-                    // Check if the transaction is disputed, if so, skip the transaction
-                    if !transaction.disputed {
-                        if let Some(amount) = &transaction.amount {
-                            account.available += amount;
-                            account.total += amount;
-                        }
-                    }
-                }
-                Type::Withdrawal => {
-                    // Check if the transaction is disputed, if so, skip the transaction
-                    if !transaction.disputed {
-                        if let Some(amount) = &transaction.amount {
-                            // Check if the account has enough funds to withdraw
-                            if account.available < *amount {
-                                return;
-                            }
-
-                            account.available -= amount;
-                            account.total -= amount;
-                        }
-                    }
-                }
-                Type::Dispute => {
-                    // Retrieve the referenced transaction
-                    if let Some(tx) = self.transactions.get_tx_mut(transaction.tx) {
-                        // Check if the transaction is already disputed, if so, skip the transaction
-                        if tx.disputed {
-                            return;
-                        }
-
-                        if let Some(amount) = &tx.amount {
-                            account.available -= amount;
-                            account.held += amount;
-                            tx.disputed = true;
-                        }
-                    }
-                }
-                Type::Resolve => {
-                    // Retrieve the referenced transaction
-                    if let Some(tx) = self.transactions.get_tx_mut(transaction.tx) {
-                        // Check if the transaction is disputed, if not, skip the transaction
-                        if tx.disputed {
-                            if let Some(amount) = &tx.amount {
-                                account.available += amount;
-                                account.held -= amount;
-                                tx.disputed = false;
-                            }
-                        }
-                    }
-                }
-                Type::Chargeback => {
-                    // Retrieve the referenced transaction
-                    if let Some(tx) = self.transactions.get_tx_mut(transaction.tx) {
-                        if tx.disputed {
-                            if let Some(amount) = &tx.amount {
-                                account.held -= amount;
-                                account.total -= amount;
-
-                                // Lock the account
-                                account.locked = true;
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        assert_eq!(engine.accounts().len(), 2);
+        assert_eq!(engine.accounts().get(3), None);
+        assert_eq!(
+            engine.report().rejected,
+            [Rejection {
+                index: 2,
+                client: 3,
+                tx: 3,
+                reason: RejectReason::TooManyClients,
+                referenced: None,
+            }]
+        );
+
+        let first = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(first.available, Amount::from(6));
+        let second = engine.accounts().get(2).expect("Failed to get account");
+        assert_eq!(second.available, Amount::from(6));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::account::Accounts;
-    use crate::engine::Engine;
-    use crate::transaction::{Transaction, Transactions, Type};
-    use rust_decimal::prelude::FromPrimitive;
-    use rust_decimal::Decimal;
+    #[test]
+    fn max_clients_counts_clients_with_several_currencies_once() {
+        let mut engine = Engine::new(Accounts::new()).with_max_clients(1);
+
+        let mut in_currency = transaction(Type::Deposit, 1, 2, Some(Amount::from(3)));
+        in_currency.currency = Some("EUR".to_string());
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            in_currency,
+            transaction(Type::Deposit, 2, 3, Some(Amount::from(5))),
+        ]));
+
+        assert_eq!(engine.accounts().len(), 2);
+        assert_eq!(engine.accounts().client_count(), 1);
+        assert_eq!(
+            engine.report().rejected[0].reason,
+            RejectReason::TooManyClients
+        );
+    }
 
     #[test]
-    fn single_transaction_deposit_succeeds() {
+    fn withdrawals_rejected_for_insufficient_funds_are_counted() {
         let mut engine = Engine::new(Accounts::new());
-        let transaction = Transaction {
-            r#type: Type::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Decimal::from_f64(0.5),
-            disputed: false,
-        };
 
-        let transactions = Transactions::from(vec![transaction]);
-        engine.process(transactions);
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(20))),
+            transaction(Type::Withdrawal, 1, 3, Some(Amount::from(20))),
+            transaction(Type::Withdrawal, 1, 4, Some(Amount::from(20))),
+        ]));
 
         let account = engine.accounts().get(1).expect("Failed to get account");
-        assert_eq!(account.available, Decimal::from_f64(0.5).unwrap());
+        assert_eq!(account.rejected_withdrawals, 3);
+        assert_eq!(account.available, Amount::from(10));
+        assert_eq!(account.total, Amount::from(10));
+
+        engine.undo_last().expect("Failed to undo withdrawal");
+        let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.rejected_withdrawals, 2);
     }
 
     #[test]
-    fn single_transaction_withdrawal_succeeds() {
+    fn withdrawals_rejected_for_a_new_client_create_no_account() {
         let mut engine = Engine::new(Accounts::new());
-        let transaction = Transaction {
-            r#type: Type::Withdrawal,
-            client: 1,
-            tx: 1,
-            amount: Decimal::from_f64(0.5),
-            disputed: false,
-        };
 
-        let transactions = Transactions::from(vec![transaction]);
-        engine.process(transactions);
-        let account = engine.accounts().get(1).expect("Failed to get account");
-        assert_eq!(account.available, 0.into());
+        engine.process(Transactions::from(vec![
+            transaction(Type::Withdrawal, 5, 1, Some(Amount::from(20))),
+            transaction(Type::Withdrawal, 5, 2, Some(Amount::from(20))),
+        ]));
+
+        assert!(engine.accounts().get(5).is_none());
+        assert_eq!(engine.report().rejected.len(), 2);
+    }
+
+    fn transfer(client: u16, tx: u32, amount: Amount, destination: u16) -> Transaction {
+        Transaction {
+            dest_client: Some(destination),
+            ..transaction(Type::Transfer, client, tx, Some(amount))
+        }
     }
 
     #[test]
-    fn double_transaction_succeeds() {
+    fn transfer_moves_funds_between_clients() {
         let mut engine = Engine::new(Accounts::new());
-        let transaction1 = Transaction {
-            r#type: Type::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Decimal::from_f64(0.5),
-            disputed: false,
-        };
-        let transaction2 = Transaction {
-            r#type: Type::Withdrawal,
-            client: 1,
-            tx: 2,
-            amount: Decimal::from_f64(0.3),
-            disputed: false,
-        };
 
-        engine.process(Transactions::from(vec![transaction1, transaction2]));
-        let account = engine.accounts().get(1).expect("Failed to get account");
-        assert_eq!(account.available, Decimal::from_f64(0.2).unwrap());
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transfer(1, 2, Amount::from(4), 2),
+        ]));
+
+        let source = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(source.available, Amount::from(6));
+        assert_eq!(source.total, Amount::from(6));
+        assert_eq!(source.total_withdrawn, Amount::ZERO);
+
+        let destination = engine.accounts().get(2).expect("Failed to get account");
+        assert_eq!(destination.available, Amount::from(4));
+        assert_eq!(destination.total, Amount::from(4));
+        assert_eq!(destination.total_deposited, Amount::ZERO);
+        assert_eq!(destination.last_tx_index, 1);
+        assert!(engine.report().is_clean());
     }
 
     #[test]
-    fn dispute_transaction_succeeds() {
+    fn transfer_with_insufficient_funds_changes_neither_account() {
         let mut engine = Engine::new(Accounts::new());
 
-        let transaction1 = Transaction {
-            r#type: Type::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Decimal::from_f64(0.5),
-            disputed: false,
-        };
-
-        let transaction2 = Transaction {
-            r#type: Type::Dispute,
-            client: 1,
-            tx: 1,
-            amount: None,
-            disputed: false,
-        };
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(1))),
+            transfer(1, 3, Amount::from(11), 2),
+        ]));
 
-        engine.process(Transactions::from(vec![transaction1, transaction2]));
-        let account = engine.accounts().get(1).expect("Failed to get account");
-        assert_eq!(account.available, 0.into());
-        assert_eq!(account.held, Decimal::from_f64(0.5).unwrap());
+        let source = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(source.available, Amount::from(10));
+        assert_eq!(source.rejected_withdrawals, 0);
+        let destination = engine.accounts().get(2).expect("Failed to get account");
+        assert_eq!(destination.available, Amount::from(1));
+        assert_eq!(
+            engine.report().rejected,
+            [Rejection {
+                index: 2,
+                client: 1,
+                tx: 3,
+                reason: RejectReason::InsufficientFunds,
+                referenced: None,
+            }]
+        );
     }
 
     #[test]
-    fn resolve_transaction_succeeds() {
+    fn transfer_overflowing_the_destination_is_rejected() {
         let mut engine = Engine::new(Accounts::new());
 
-        let transaction1 = Transaction {
-            r#type: Type::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Decimal::from_f64(0.5),
-            disputed: false,
-        };
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::MAX)),
+            transfer(1, 3, Amount::from(4), 2),
+        ]));
 
-        let transaction2 = Transaction {
-            r#type: Type::Dispute,
-            client: 1,
-            tx: 1,
-            amount: None,
-            disputed: false,
-        };
+        let source = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(source.available, Amount::from(10));
+        let destination = engine.accounts().get(2).expect("Failed to get account");
+        assert_eq!(destination.total, Amount::MAX);
+        assert_eq!(engine.report().rejected[0].reason, RejectReason::Overflow);
+    }
 
-        let transaction3 = Transaction {
-            r#type: Type::Resolve,
-            client: 1,
-            tx: 1,
-            amount: None,
-            disputed: false,
-        };
+    #[test]
+    fn transfer_to_excluded_client_is_rejected() {
+        let mut engine = Engine::new(Accounts::new()).with_client_filter(|client| client != 2);
 
         engine.process(Transactions::from(vec![
-            transaction1,
-            transaction2,
-            transaction3,
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transfer(1, 2, Amount::from(4), 2),
         ]));
-        let account = engine.accounts().get(1).expect("Failed to get account");
-        assert_eq!(account.available, Decimal::from_f64(0.5).unwrap());
-        assert_eq!(account.held, 0.into());
+
+        let source = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(source.available, Amount::from(10));
+        assert!(engine.accounts().get(2).is_none());
+        assert_eq!(
+            engine.report().rejected[0].reason,
+            RejectReason::DestinationExcluded
+        );
     }
 
     #[test]
-    fn chargeback_transaction_succeeds() {
+    fn transfer_to_new_client_above_the_client_limit_is_rejected() {
+        let mut engine = Engine::new(Accounts::new()).with_max_clients(1);
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transfer(1, 2, Amount::from(4), 2),
+        ]));
+
+        let source = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(source.available, Amount::from(10));
+        assert!(engine.accounts().get(2).is_none());
+        assert_eq!(
+            engine.report().rejected[0].reason,
+            RejectReason::TooManyClients
+        );
+    }
+
+    #[test]
+    fn transfer_to_locked_account_is_rejected() {
         let mut engine = Engine::new(Accounts::new());
 
-        let transaction1 = Transaction {
-            r#type: Type::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Decimal::from_f64(0.5),
-            disputed: false,
-        };
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 2, 2, None),
+            transaction(Type::Chargeback, 2, 2, None),
+            transfer(1, 3, Amount::from(4), 2),
+        ]));
 
-        let transaction2 = Transaction {
-            r#type: Type::Dispute,
-            client: 1,
-            tx: 1,
-            amount: None,
-            disputed: false,
-        };
+        let source = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(source.available, Amount::from(10));
+        let destination = engine.accounts().get(2).expect("Failed to get account");
+        assert_eq!(destination.total, Amount::ZERO);
+        assert_eq!(
+            engine.report().rejected[0].reason,
+            RejectReason::DestinationLocked
+        );
 
-        let transaction3 = Transaction {
-            r#type: Type::Chargeback,
-            client: 1,
-            tx: 1,
-            amount: None,
-            disputed: false,
-        };
+        // Only the transactions of locked accounts count as dropped due to a lock
+        assert!(engine.report().dropped_due_to_lock.is_empty());
+    }
+
+    #[test]
+    fn transfer_from_locked_account_is_rejected() {
+        let mut engine = Engine::new(Accounts::new());
 
         engine.process(Transactions::from(vec![
-            transaction1,
-            transaction2,
-            transaction3,
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+            transaction(Type::Dispute, 1, 2, None),
+            transaction(Type::Chargeback, 1, 2, None),
+            transfer(1, 3, Amount::from(4), 2),
         ]));
-        let account = engine.accounts().get(1).expect("Failed to get account");
-        assert_eq!(account.available, 0.into());
-        assert_eq!(account.held, 0.into());
-        assert!(account.locked);
+
+        let source = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(source.available, Amount::from(10));
+        assert!(engine.accounts().get(2).is_none());
+        assert_eq!(
+            engine.report().rejected[0].reason,
+            RejectReason::AccountLocked
+        );
     }
 
     #[test]
-    fn locked_account_withdraw_fails() {
+    fn dispute_of_transfer_is_ignored() {
         let mut engine = Engine::new(Accounts::new());
 
-        let transaction1 = Transaction {
-            r#type: Type::Deposit,
-            client: 1,
-            tx: 1,
-            amount: Decimal::from_f64(0.5),
-            disputed: false,
-        };
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transfer(1, 2, Amount::from(4), 2),
+            transaction(Type::Dispute, 1, 2, None),
+        ]));
 
-        let transaction2 = Transaction {
-            r#type: Type::Dispute,
-            client: 1,
-            tx: 1,
-            amount: None,
-            disputed: false,
+        let source = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(source.available, Amount::from(6));
+        assert_eq!(source.held, Amount::ZERO);
+        assert_eq!(engine.is_disputed(2), None);
+    }
+
+    #[test]
+    fn transfer_is_not_reversible() {
+        let mut engine = Engine::new(Accounts::new());
+
+        engine.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transfer(1, 2, Amount::from(4), 2),
+        ]));
+
+        assert!(matches!(
+            engine.undo_last(),
+            Err(PaymentsError::NotReversible {
+                index: 1,
+                r#type: Type::Transfer
+            })
+        ));
+    }
+
+    #[test]
+    fn zero_client_is_rejected_only_when_enabled() {
+        let transactions = || {
+            Transactions::from(vec![
+                transaction(Type::Deposit, 0, 1, Some(Amount::from(10))),
+                transaction(Type::Deposit, 1, 2, Some(Amount::from(5))),
+                transfer(1, 3, Amount::from(2), 0),
+            ])
         };
 
-        engine.process(Transactions::from(vec![transaction1, transaction2]));
+        let mut engine = Engine::new(Accounts::new());
+        engine.process(transactions());
+        let account = engine.accounts().get(0).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(12));
+        assert!(engine.report().is_clean());
 
+        let mut engine = Engine::new(Accounts::new()).with_reject_zero_client();
+        engine.process(transactions());
+        assert!(engine.accounts().get(0).is_none());
         let account = engine.accounts().get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(5));
+        assert_eq!(
+            engine
+                .report()
+                .rejected
+                .iter()
+                .map(|rejection| (rejection.tx, rejection.reason))
+                .collect::<Vec<_>>(),
+            [(1, RejectReason::ZeroClient), (3, RejectReason::ZeroClient)]
+        );
+    }
 
-        assert_eq!(account.available, 0.into());
-        assert_eq!(account.held, Decimal::from_f32(0.5).unwrap());
-        assert!(!account.locked);
+    #[test]
+    fn reset_engine_matches_a_fresh_engine() {
+        let path = Path::new("tests/resources/inputs/trx3.csv");
+        let read = || Transactions::from_csv(path).expect("Failed to read transactions");
 
-        let transaction3 = Transaction {
-            r#type: Type::Chargeback,
-            client: 1,
-            tx: 1,
-            amount: None,
-            disputed: false,
-        };
+        let mut fresh = Engine::new(Accounts::new()).with_dedup().with_event_log();
+        fresh.process(read());
 
-        let transaction4 = Transaction {
-            r#type: Type::Withdrawal,
-            client: 1,
-            tx: 2,
-            amount: Decimal::from_f64(0.5),
-            disputed: false,
-        };
+        let mut reused = Engine::new(Accounts::new()).with_dedup().with_event_log();
+        reused.process(Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(20))),
+            transaction(Type::Dispute, 1, 1, None),
+        ]));
+        reused.reset();
 
-        engine.process(Transactions::from(vec![transaction3, transaction4]));
+        assert!(reused.accounts().is_empty());
+        assert!(reused.transactions().is_empty());
+        assert!(reused.report().is_clean());
+        assert!(reused.event_log().is_empty());
+        assert_eq!(reused.transaction_count(1), 0);
 
-        let account = engine.accounts().get(1).expect("Failed to get account");
-        assert_eq!(account.available, 0.into());
-        assert_eq!(account.held, 0.into());
-        assert!(account.locked);
+        reused.process(read());
+
+        assert_eq!(reused.accounts().checksum(), fresh.accounts().checksum());
+        assert_eq!(reused.report().rejected, fresh.report().rejected);
+        assert_eq!(reused.event_log(), fresh.event_log());
+        assert_eq!(reused.transactions().len(), fresh.transactions().len());
+        assert_eq!(reused.transaction_count(1), fresh.transaction_count(1));
+    }
+
+    #[test]
+    fn accounts_are_written_in_insertion_order() {
+        let mut engine = Engine::new(Accounts::new());
+        engine.process(
+            Transactions::from_csv_str(
+                "type,client,tx,amount\ndeposit,3,1,1\ndeposit,1,2,2\ndeposit,3,3,1\ndeposit,2,4,3\n",
+            )
+            .expect("Failed to read transactions"),
+        );
+
+        let mut output = vec![];
+        engine
+            .accounts()
+            .write_csv_insertion_order(&mut output)
+            .expect("Failed to write accounts");
+
+        assert_eq!(
+            String::from_utf8(output).expect("Output is not valid utf8"),
+            "client,available,held,total,locked\n3,2,0,2,false\n1,2,0,2,false\n2,3,0,3,false\n"
+        );
     }
 }
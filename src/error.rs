@@ -0,0 +1,130 @@
+use crate::transaction::Type;
+use std::path::PathBuf;
+
+///
+/// Errors returned by the public API of the library
+///
+#[derive(Debug, thiserror::Error)]
+pub enum PaymentsError {
+    #[error("Transactions csv file does not exist: '{}'", .0.display())]
+    FileNotFound(PathBuf),
+
+    #[error("Failed to open transactions csv file: '{}'", path.display())]
+    OpenFile {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("No transactions input was given in the command line arguments")]
+    MissingInput,
+
+    #[error("Transactions file '{}' is {size} bytes, more than the maximum of {max} bytes", path.display())]
+    FileTooLarge { path: PathBuf, size: u64, max: u64 },
+
+    #[error("Transactions path is not a directory: '{}'", .0.display())]
+    NotADirectory(PathBuf),
+
+    #[error("Failed to read the transactions csv header")]
+    CsvHeader(#[source] csv::Error),
+
+    #[error("Invalid transactions csv header, unexpected columns: {unexpected:?}, missing columns: {missing:?}")]
+    InvalidHeader {
+        unexpected: Vec<String>,
+        missing: Vec<String>,
+    },
+
+    #[error("Failed to parse transaction at index: '{index}'")]
+    CsvParse {
+        index: usize,
+        #[source]
+        source: csv::Error,
+    },
+
+    #[error("Transaction at index: '{index}' has a {field} of '{value}' which is out of range, expected 0 to {max}")]
+    IdOutOfRange {
+        index: usize,
+        field: &'static str,
+        value: String,
+        max: u64,
+    },
+
+    #[error("Transaction at index: '{index}' is a {type} without an amount")]
+    MissingAmount { index: usize, r#type: Type },
+
+    #[error("Transaction at index: '{index}' is a transfer without a dest_client")]
+    MissingDestination { index: usize },
+
+    #[error("Transaction at index: '{index}' has an amount of '{value}' which is not an integer amount in minor units")]
+    InvalidMinorUnits { index: usize, value: String },
+
+    #[error("Failed to serialize account to csv record for client: '{client}'")]
+    Serialize {
+        client: u16,
+        #[source]
+        source: csv::Error,
+    },
+
+    #[error("Failed to serialize transaction to csv record at index: '{index}'")]
+    SerializeTransaction {
+        index: usize,
+        #[source]
+        source: csv::Error,
+    },
+
+    #[error("Invalid transaction type: '{0}'")]
+    InvalidType(String),
+
+    #[error("Accounts can't be merged, both have an account for client: '{0}'")]
+    OverlappingClient(u16),
+
+    #[error("Transfer '{tx}' of client: '{client}' can't be processed by a shared engine, its clients may be on different shards")]
+    ShardedTransfer { client: u16, tx: u32 },
+
+    #[error("The engine of shard '{0}' stopped, its worker thread panicked")]
+    ShardStopped(usize),
+
+    #[error("There is no processed transaction to undo")]
+    NothingToUndo,
+
+    #[error("Transaction at index: '{index}' is a {type} which can't be undone")]
+    NotReversible { index: usize, r#type: Type },
+
+    #[error("Failed to read the transactions archive")]
+    Archive(#[source] zip::result::ZipError),
+
+    #[error("Failed to write or read the engine snapshot")]
+    Snapshot(#[source] bincode::Error),
+
+    #[error("Failed to write or read the binary accounts")]
+    Binary(#[source] bincode::Error),
+
+    #[error("Unsupported binary accounts version: '{0}'")]
+    UnsupportedBinaryVersion(u8),
+
+    #[error("Unsupported engine snapshot version: '{0}'")]
+    UnsupportedSnapshotVersion(u8),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+///
+/// Result type used by the public API of the library
+///
+pub type Result<T> = std::result::Result<T, PaymentsError>;
+
+///
+/// A hook called with the error of a record skipped instead of failing, e.g. to warn about it
+/// Hooks compare equal when they point to the same function, so the options holding them can still be compared
+///
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorHook(pub fn(&PaymentsError));
+
+impl PartialEq for ErrorHook {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::fn_addr_eq(self.0, other.0)
+    }
+}
+
+impl Eq for ErrorHook {}
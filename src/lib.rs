@@ -1,9 +1,45 @@
 pub use account::Account;
 pub use account::Accounts;
+pub use account::CsvOptions;
+pub use account::SortKey;
 pub use engine::Engine;
+pub use error::PaymentsError;
+pub use error::Result;
+pub use money::Amount;
+#[cfg(feature = "fixed-point")]
+pub use money::FixedPoint;
+pub use money::Money;
+#[cfg(feature = "fixed-point")]
+pub use money::ParseFixedPointError;
+pub use money::Rounding;
+#[cfg(feature = "net")]
+pub use net::{serve, serve_listener};
+pub use policy::LockedPolicy;
+pub use policy::WithdrawalPolicy;
+pub use reader::Field;
+pub use reader::TransactionsReader;
+pub use report::BalanceEvent;
+pub use report::BalanceField;
+pub use report::HeldDiscrepancy;
+pub use report::LedgerEntry;
+pub use report::PartialFill;
+pub use report::ProcessReport;
+pub use report::RejectReason;
+pub use report::Rejection;
+pub use shared::SharedEngine;
 pub use transaction::Transaction;
 pub use transaction::Transactions;
+pub use transaction::Type;
 
 mod account;
 mod engine;
+mod error;
+mod money;
+#[cfg(feature = "net")]
+mod net;
+mod policy;
+mod reader;
+mod report;
+mod shared;
+mod snapshot;
 mod transaction;
@@ -1,16 +1,253 @@
+//!
+//! Exit codes:
+//!
+//! - `0`: the transactions were processed and the accounts state was written
+//! - `1`: invalid arguments, or a fatal error reading the input or writing the output
+//! - `2`: only with `--strict`, the accounts state was written but at least one transaction was rejected
+//!   (including resolves and chargebacks of transactions that aren't disputed)
+//!
 use anyhow::{Context, Result};
-use toy_payments::{Accounts, Engine, Transactions};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use toy_payments::{Accounts, CsvOptions, Engine, Rounding, Transactions, TransactionsReader};
+
+///
+/// Exit code used for invalid arguments, the same as for any other fatal error
+///
+const EXIT_CODE_INVALID_ARGUMENTS: i32 = 1;
+
+///
+/// Exit code used in strict mode when at least one transaction was rejected
+///
+const EXIT_CODE_REJECTED: i32 = 2;
+
+///
+/// Simple payments engine processor
+///
+/// Without a command the arguments are those of the `process` command, e.g. `toy_payments transactions.csv`
+///
+#[derive(Parser)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    process: ProcessArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process transaction csv files and write the resulting state of the accounts
+    Process(ProcessArgs),
+}
+
+///
+/// Output formats of the accounts state
+///
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Csv with a record per account
+    Csv,
+    /// Aligned text table for interactive inspection
+    Table,
+    /// Version byte followed by the bincode encoded accounts
+    Bincode,
+}
+
+///
+/// Rounding modes of the csv amounts, mirroring `Rounding`
+///
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum RoundingMode {
+    HalfUp,
+    HalfEven,
+    Truncate,
+}
+
+impl From<RoundingMode> for Rounding {
+    fn from(rounding: RoundingMode) -> Self {
+        match rounding {
+            RoundingMode::HalfUp => Self::HalfUp,
+            RoundingMode::HalfEven => Self::HalfEven,
+            RoundingMode::Truncate => Self::Truncate,
+        }
+    }
+}
+
+///
+/// Options of the `process` command
+///
+#[derive(Args)]
+#[allow(clippy::struct_excessive_bools)]
+struct ProcessArgs {
+    /// Transaction csv files, processed in the given order, `-` reads from stdin
+    #[arg(required = true, value_name = "FILES")]
+    inputs: Vec<PathBuf>,
+
+    /// Output format of the accounts state
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+
+    /// Write the accounts state to a file (created or truncated) instead of stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Exit with code 2 if any transaction was rejected, including resolves and chargebacks of undisputed transactions
+    #[arg(long)]
+    strict: bool,
+
+    /// Reject the transactions of client id 0, which some upstream systems use for a missing client
+    #[arg(long)]
+    reject_zero_client: bool,
+
+    /// Only read and process the transactions without writing the accounts state, e.g. to validate the input
+    #[arg(long, conflicts_with = "output")]
+    check: bool,
+
+    /// Skip malformed rows with a warning instead of failing
+    #[arg(long)]
+    skip_bad_rows: bool,
+
+    /// Fail without reading an input file larger than the given number of bytes
+    #[arg(long, value_name = "BYTES")]
+    max_bytes: Option<u64>,
+
+    /// Add an overdrawn column to the accounts csv, or mark overdrawn accounts in the table status column
+    #[arg(long)]
+    overdrawn: bool,
+
+    /// Leave accounts without funds that aren't locked out of the csv or table
+    #[arg(long)]
+    omit_empty: bool,
+
+    /// Add the indexes of the first and last transaction that changed each account to the csv
+    #[arg(long)]
+    activity: bool,
+
+    /// Add the lifetime deposited and withdrawn totals to the csv
+    #[arg(long)]
+    verbose: bool,
+
+    /// Add the id of the chargeback that locked each account to the csv
+    #[arg(long)]
+    locked_by: bool,
+
+    /// Add the number of withdrawals rejected for insufficient funds of each account to the csv
+    #[arg(long)]
+    rejected_withdrawals: bool,
+
+    /// Write every amount with exactly 4 decimal places
+    #[arg(long)]
+    fixed_scale: bool,
+
+    /// Rounding of amounts to 4 decimal places in the csv or table
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = RoundingMode::HalfEven)]
+    rounding: RoundingMode,
+}
+
+impl ProcessArgs {
+    fn csv_options(&self) -> CsvOptions {
+        CsvOptions::new()
+            .with_overdrawn(self.overdrawn)
+            .with_omit_empty(self.omit_empty)
+            .with_activity(self.activity)
+            .with_lifetime_totals(self.verbose)
+            .with_locked_by(self.locked_by)
+            .with_rejected_withdrawals(self.rejected_withdrawals)
+            .with_fixed_scale(self.fixed_scale)
+            .with_rounding(self.rounding.into())
+    }
+
+    ///
+    /// Reads the transactions of every input in order, warning about inputs without any transaction
+    ///
+    fn read_transactions(&self) -> Result<Transactions> {
+        let mut reader = TransactionsReader::new()
+            .with_skip_bad_rows(self.skip_bad_rows)
+            .with_skipped_row_hook(|error| match error.source() {
+                Some(source) => eprintln!("Warning: skipping malformed row: {error}: {source}"),
+                None => eprintln!("Warning: skipping malformed row: {error}"),
+            });
+        if let Some(max_bytes) = self.max_bytes {
+            reader = reader.with_max_bytes(max_bytes);
+        }
+
+        let mut transactions = Transactions::default();
+        for input in &self.inputs {
+            let read = reader.read_input(input).with_context(|| {
+                format!("Failed to read transactions from '{}'", input.display())
+            })?;
+
+            // An input without any transaction may indicate an upstream failure, so let the caller know
+            if read.is_empty() {
+                eprintln!(
+                    "Warning: no transactions were read from '{}'",
+                    input.display()
+                );
+            }
+
+            transactions.extend(read);
+        }
+
+        Ok(transactions)
+    }
+}
+
+///
+/// Parses the command line arguments, exiting with usage information if they are invalid
+/// Help and version requests exit successfully, invalid arguments exit with code 1 since 2 is reserved for `--strict`
+///
+fn parse_args() -> ProcessArgs {
+    let cli = Cli::try_parse().unwrap_or_else(|error| {
+        let _ = error.print();
+        let code = if error.use_stderr() {
+            EXIT_CODE_INVALID_ARGUMENTS
+        } else {
+            0
+        };
+        std::process::exit(code);
+    });
+
+    cli.command
+        .map_or(cli.process, |Command::Process(args)| args)
+}
 
 fn main() -> Result<()> {
+    let options = parse_args();
+
     // Create a new accounts instance
     let accounts = Accounts::new();
 
-    // Create a new engine instance
+    // Create a new engine instance, in strict mode resolves and chargebacks of undisputed transactions are rejections
     let mut engine = Engine::new(accounts);
+    if options.strict {
+        engine = engine.with_strict_disputes();
+    }
+    if options.reject_zero_client {
+        engine = engine.with_reject_zero_client();
+    }
 
-    // Read the transactions from the csv file in the arguments
-    let transactions = Transactions::from_args()
-        .with_context(|| "Failed to retrieve transactions file in arguments")?;
+    // Read the transactions from the inputs in the arguments
+    let mut transactions = options.read_transactions()?;
+
+    // Disputes, resolves and chargebacks of unknown transactions are ignored by the engine, so point them out
+    for (index, transaction) in transactions.dangling_references() {
+        eprintln!(
+            "Warning: {} at index {index} references tx {} which is not a deposit or withdrawal",
+            transaction.r#type, transaction.tx
+        );
+    }
+
+    // Make sure the transactions are processed in time order if the file has timestamps
+    transactions.sort_by_time();
 
     // Feed the transactions to the engine and process them
     //
@@ -18,11 +255,37 @@ fn main() -> Result<()> {
     // we can call `engine.process(trxs)`` multiple times with more transactions
     engine.process(transactions);
 
-    // Write the state of the accounts to stdout as csv
-    engine
-        .accounts()
-        .print_state()
-        .with_context(|| "Failed to print accounts state to stdout")?;
+    // Write the state of the accounts to the output file if provided, otherwise to stdout
+    let csv_options = options.csv_options();
+    let write_accounts = |writer: &mut dyn Write| match options.format {
+        Format::Csv => engine.accounts().write_csv_with(&csv_options, writer),
+        Format::Table => engine.accounts().write_table_with(&csv_options, writer),
+        Format::Bincode => engine.accounts().write_binary(writer),
+    };
+
+    if options.check {
+        // Nothing is written, the warnings and the exit code are the result of the check
+    } else if let Some(output) = &options.output {
+        let file = File::create(output)
+            .with_context(|| format!("Failed to create output file: '{}'", output.display()))?;
+
+        write_accounts(&mut BufWriter::new(file)).with_context(|| {
+            format!(
+                "Failed to write accounts state to output file: '{}'",
+                output.display()
+            )
+        })?;
+    } else {
+        write_accounts(&mut std::io::stdout().lock())
+            .with_context(|| "Failed to print accounts state to stdout")?;
+    }
+
+    // In strict mode any rejected transaction makes the run fail
+    let report = engine.report();
+    if options.strict && !report.is_clean() {
+        eprintln!("{} transaction(s) were rejected", report.rejected.len());
+        std::process::exit(EXIT_CODE_REJECTED);
+    }
 
     Ok(())
 }
@@ -0,0 +1,85 @@
+use crate::account::{Account, CsvOptions};
+use crate::error::Result;
+use crate::reader::TransactionsReader;
+use crate::shared::SharedEngine;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+///
+/// Listens on the given address and applies the transactions received over tcp to the shared engine,
+/// see `serve_listener` for the protocol
+///
+/// # Errors
+///
+/// If the address can't be bound or accepting a connection fails
+///
+pub fn serve(addr: &str, engine: SharedEngine) -> Result<()> {
+    serve_listener(&TcpListener::bind(addr)?, engine)
+}
+
+///
+/// Applies the transactions received on the connections of an already bound listener to the shared engine
+///
+/// Every connection is handled on its own thread and sends one headerless csv line per transaction,
+/// with the `type, client, tx, amount` columns. Each line is answered with the resulting account of the client
+/// as a `client,available,held,total,locked` line, with a currency column after the client for an account
+/// in a currency and the amounts rounded like the accounts csv, or with an `error: <reason>` line if it fails
+/// to be parsed, the shared engine refuses it or the engine of its shard stopped
+/// The columns have no `dest_client`, so transfers can't be sent, the shared engine refuses them anyway
+/// Blank lines are ignored, and the connection is closed once the peer closes it
+///
+/// # Errors
+///
+/// If accepting a connection fails
+///
+pub fn serve_listener(listener: &TcpListener, engine: SharedEngine) -> Result<()> {
+    let engine = Arc::new(engine);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let engine = Arc::clone(&engine);
+
+        // A connection that fails only affects its own peer
+        thread::spawn(move || {
+            let _ = handle_connection(&stream, &engine);
+        });
+    }
+
+    Ok(())
+}
+
+///
+/// Reads the transaction lines of a connection until it is closed, answering each one
+///
+fn handle_connection(stream: &TcpStream, engine: &SharedEngine) -> std::io::Result<()> {
+    let reader = TransactionsReader::new();
+    let mut writer = stream;
+
+    for (index, line) in BufReader::new(stream).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match reader.parse_line(&line, index) {
+            Ok(transaction) => {
+                let client = transaction.client;
+                engine
+                    .submit_and_wait(transaction)
+                    .and_then(|account| {
+                        account
+                            .unwrap_or_else(|| Account::new(client))
+                            .to_csv_line(&CsvOptions::new())
+                    })
+                    .unwrap_or_else(|error| format!("error: {error}"))
+            }
+            Err(error) => format!("error: {error}"),
+        };
+
+        writeln!(writer, "{response}")?;
+    }
+
+    Ok(())
+}
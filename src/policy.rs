@@ -0,0 +1,129 @@
+use crate::money::Amount;
+use crate::transaction::Type;
+use serde::{Deserialize, Serialize};
+
+///
+/// Controls which transactions are still applied to an account after it has been locked by a chargeback
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockedPolicy {
+    /// Every transaction on a locked account is skipped
+    #[default]
+    RejectAll,
+    /// Only resolves are applied, so open disputes can still release their held funds
+    AllowResolveOnly,
+    /// Only deposits are applied, so a locked account can still receive funds
+    AllowDeposits,
+}
+
+///
+/// Controls how a withdrawal larger than the available funds is handled
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WithdrawalPolicy {
+    /// The withdrawal is rejected and no funds are withdrawn
+    #[default]
+    RejectOnInsufficient,
+    /// The available funds are withdrawn and the shortfall is recorded as a partial fill
+    PartialFill,
+}
+
+impl LockedPolicy {
+    ///
+    /// Returns true if a transaction of the given type may be applied to a locked account
+    ///
+    pub(crate) const fn allows(self, r#type: Type) -> bool {
+        match self {
+            Self::RejectAll => false,
+            Self::AllowResolveOnly => matches!(r#type, Type::Resolve),
+            Self::AllowDeposits => matches!(r#type, Type::Deposit),
+        }
+    }
+}
+
+impl WithdrawalPolicy {
+    ///
+    /// Returns the amount of a withdrawal that can be taken from the available funds,
+    /// or `None` if the withdrawal must be rejected
+    ///
+    pub(crate) fn withdrawable(self, available: Amount, requested: Amount) -> Option<Amount> {
+        if available >= requested {
+            return Some(requested);
+        }
+
+        match self {
+            Self::PartialFill if available > Amount::ZERO => Some(available),
+            Self::RejectOnInsufficient | Self::PartialFill => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_policy_is_reject_all() {
+        assert_eq!(LockedPolicy::default(), LockedPolicy::RejectAll);
+    }
+
+    #[test]
+    fn withdrawable_amounts() {
+        let ten = Amount::from(10);
+        let six = Amount::from(6);
+
+        assert_eq!(
+            WithdrawalPolicy::RejectOnInsufficient.withdrawable(ten, six),
+            Some(six)
+        );
+        assert_eq!(
+            WithdrawalPolicy::RejectOnInsufficient.withdrawable(six, ten),
+            None
+        );
+        assert_eq!(
+            WithdrawalPolicy::PartialFill.withdrawable(six, ten),
+            Some(six)
+        );
+        assert_eq!(
+            WithdrawalPolicy::PartialFill.withdrawable(Amount::ZERO, ten),
+            None
+        );
+    }
+
+    #[test]
+    fn default_withdrawal_policy_rejects() {
+        assert_eq!(
+            WithdrawalPolicy::default(),
+            WithdrawalPolicy::RejectOnInsufficient
+        );
+    }
+
+    #[test]
+    fn reject_all_allows_nothing() {
+        let policy = LockedPolicy::RejectAll;
+
+        assert!(!policy.allows(Type::Deposit));
+        assert!(!policy.allows(Type::Withdrawal));
+        assert!(!policy.allows(Type::Dispute));
+        assert!(!policy.allows(Type::Resolve));
+        assert!(!policy.allows(Type::Chargeback));
+    }
+
+    #[test]
+    fn allow_resolve_only_allows_resolve() {
+        let policy = LockedPolicy::AllowResolveOnly;
+
+        assert!(policy.allows(Type::Resolve));
+        assert!(!policy.allows(Type::Deposit));
+        assert!(!policy.allows(Type::Chargeback));
+    }
+
+    #[test]
+    fn allow_deposits_allows_deposit() {
+        let policy = LockedPolicy::AllowDeposits;
+
+        assert!(policy.allows(Type::Deposit));
+        assert!(!policy.allows(Type::Resolve));
+        assert!(!policy.allows(Type::Withdrawal));
+    }
+}
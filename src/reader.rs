@@ -0,0 +1,984 @@
+use crate::error::{ErrorHook, PaymentsError, Result};
+use crate::transaction::{Transaction, Transactions, Type};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+///
+/// The columns expected in the header of a transactions csv file
+///
+const EXPECTED_HEADERS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+///
+/// The columns that may optionally be present in the header of a transactions csv file
+/// The disputed column is written by `Transactions::write_csv` and ignored when reading
+///
+const OPTIONAL_HEADERS: [&str; 4] = ["timestamp", "currency", "dest_client", "disputed"];
+
+///
+/// Approximate size in bytes of a csv row, used to estimate the number of transactions from the file size
+/// Rows are usually a bit longer, so the estimate errs on reserving slightly more than needed
+///
+const ESTIMATED_ROW_BYTES: u64 = 24;
+
+///
+/// The input name that reads the transactions from stdin instead of a file
+///
+const STDIN_INPUT: &str = "-";
+
+///
+/// A field of a transaction, used to map the differently named columns of a source file to the expected ones
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Timestamp,
+    Currency,
+    DestClient,
+    Disputed,
+}
+
+impl Field {
+    ///
+    /// Returns the name of the column the field is read from by default
+    ///
+    pub const fn header(self) -> &'static str {
+        match self {
+            Self::Type => "type",
+            Self::Client => "client",
+            Self::Tx => "tx",
+            Self::Amount => "amount",
+            Self::Timestamp => "timestamp",
+            Self::Currency => "currency",
+            Self::DestClient => "dest_client",
+            Self::Disputed => "disputed",
+        }
+    }
+}
+
+///
+/// Reads transactions from csv files
+///
+/// The default reader is strict: the first malformed record fails the whole read.
+/// `Transactions::from_csv` and `Transactions::from_dir` use the default reader
+///
+/// Quoted fields are supported, so a quoted amount may contain commas, e.g. `"1,000.50"`
+/// Files exported on Windows are read as is: a leading UTF-8 BOM is stripped from the header and CRLF line endings are accepted
+///
+/// The reader isn't `Copy` since it owns its column map, clone it to reuse a configured reader
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransactionsReader {
+    skip_bad_rows: bool,
+    strip_thousands_separators: bool,
+    minor_units_scale: Option<u32>,
+    currency_symbol: Option<char>,
+    max_bytes: Option<u64>,
+    column_map: Option<HashMap<String, Field>>,
+    skipped_row_hook: Option<ErrorHook>,
+}
+
+impl TransactionsReader {
+    pub const fn new() -> Self {
+        Self {
+            skip_bad_rows: false,
+            strip_thousands_separators: false,
+            minor_units_scale: None,
+            currency_symbol: None,
+            max_bytes: None,
+            column_map: None,
+            skipped_row_hook: None,
+        }
+    }
+
+    ///
+    /// Skips records that fail to be parsed instead of failing the whole read
+    /// The skipped records are dropped silently unless a hook is set with `TransactionsReader::with_skipped_row_hook`
+    ///
+    #[must_use]
+    pub const fn with_skip_bad_rows(mut self, skip_bad_rows: bool) -> Self {
+        self.skip_bad_rows = skip_bad_rows;
+        self
+    }
+
+    ///
+    /// Calls the hook with the error of every record skipped with `TransactionsReader::with_skip_bad_rows`,
+    /// which identifies the record by its index, e.g. to warn about it
+    ///
+    #[must_use]
+    pub const fn with_skipped_row_hook(mut self, hook: fn(&PaymentsError)) -> Self {
+        self.skipped_row_hook = Some(ErrorHook(hook));
+        self
+    }
+
+    ///
+    /// Removes thousands separators (commas) from amounts before they are parsed, e.g. `"1,000.50"` is read as `1000.50`
+    /// Amounts with separators have to be quoted so the commas aren't read as field delimiters
+    ///
+    #[must_use]
+    pub const fn with_strip_thousands_separators(
+        mut self,
+        strip_thousands_separators: bool,
+    ) -> Self {
+        self.strip_thousands_separators = strip_thousands_separators;
+        self
+    }
+
+    ///
+    /// Removes a leading currency symbol from amounts before they are parsed, e.g. `$100.00` is read as `100.00`
+    /// The symbol may come after the sign, e.g. `-$5` or `+$5`. A leading plus sign is always accepted
+    ///
+    #[must_use]
+    pub const fn with_strip_currency_symbol(mut self, symbol: char) -> Self {
+        self.currency_symbol = Some(symbol);
+        self
+    }
+
+    ///
+    /// Reads amounts as integers in minor units scaled by `10^scale`, e.g. `12345` with a scale of 2 is read as `123.45`
+    /// Amounts that aren't integers fail to be parsed instead of being read as they are
+    ///
+    #[must_use]
+    pub const fn with_amount_in_minor_units(mut self, scale: u32) -> Self {
+        self.minor_units_scale = Some(scale);
+        self
+    }
+
+    ///
+    /// Rejects files larger than `max_bytes` before reading them, as a cheap guard against exhausting memory with
+    /// untrusted input. The limit applies to files and zip archives, the size of stdin and other readers isn't known upfront
+    ///
+    #[must_use]
+    pub const fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    ///
+    /// Reads the columns named in the map as the given fields, e.g. `clientId` as `Field::Client`
+    /// Header names are translated before the header is validated, columns that aren't in the map keep their name
+    ///
+    #[must_use]
+    pub fn with_column_map(mut self, column_map: HashMap<String, Field>) -> Self {
+        self.column_map = Some(column_map);
+        self
+    }
+
+    ///
+    /// Handles the csv parsing of a file by deserializing the records and returns a Transactions struct
+    /// A file that is empty or only has a header returns an empty Transactions, which can be checked with `is_empty`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file does not exist or is larger than the maximum size, if the header is invalid,
+    /// or if a record fails to be parsed and bad rows are not skipped
+    ///
+    pub fn read(&self, path: &Path) -> Result<Transactions> {
+        let file = self.open(path)?;
+
+        // Reserve room for the transactions upfront based on the file size, to avoid growing the collection while reading
+        let capacity = file
+            .metadata()
+            .map(|metadata| metadata.len() / ESTIMATED_ROW_BYTES)
+            .ok()
+            .and_then(|rows| usize::try_from(rows).ok())
+            .unwrap_or_default();
+
+        self.read_records(file, capacity)
+    }
+
+    ///
+    /// Reads the transactions of a command line input, which is stdin for `-` and a file path otherwise
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file does not exist or stdin can't be read, if the header is invalid,
+    /// or if a record fails to be parsed and bad rows are not skipped
+    ///
+    pub fn read_input(&self, input: &Path) -> Result<Transactions> {
+        if input == Path::new(STDIN_INPUT) {
+            return self.read_from(std::io::stdin().lock());
+        }
+
+        self.read(input)
+    }
+
+    ///
+    /// Handles the csv parsing of any reader, e.g. an in-memory buffer, with the same configuration as `read`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header is invalid, or if a record fails to be parsed and bad rows are not skipped
+    ///
+    pub fn read_from<R: Read>(&self, reader: R) -> Result<Transactions> {
+        self.read_records(reader, 0)
+    }
+
+    ///
+    /// Parses a single csv line without a header, with the `type, client, tx, amount` columns,
+    /// e.g. a message of a transactions stream. The index identifies the line in errors
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the line fails to be parsed, has an id out of range or fails to be deserialized
+    ///
+    pub fn parse_line(&self, line: &str, index: usize) -> Result<Transaction> {
+        let headers = csv::StringRecord::from(EXPECTED_HEADERS.to_vec());
+        let record = csv::ReaderBuilder::default()
+            .delimiter(b',')
+            .trim(csv::Trim::All)
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(line.as_bytes())
+            .records()
+            .next()
+            .unwrap_or_else(|| Ok(csv::StringRecord::new()));
+
+        self.parse_record(&headers, record, index)
+    }
+
+    ///
+    /// Deserializes the records of a csv reader, reserving room for `capacity` transactions upfront
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header is invalid, or if a record fails to be parsed and bad rows are not skipped
+    ///
+    fn read_records<R: Read>(&self, reader: R, capacity: usize) -> Result<Transactions> {
+        let mut csv_reader = csv::ReaderBuilder::default()
+            .delimiter(b',')
+            .trim(csv::Trim::All)
+            .has_headers(true)
+            .flexible(true)
+            .from_reader(reader);
+
+        // Validate the header before reading any records so a typo in a column name fails loudly
+        let headers = self.map_headers(csv_reader.headers().map_err(PaymentsError::CsvHeader)?);
+
+        // A completely empty file has no header and no records, it's read as zero transactions
+        if headers.is_empty() {
+            return Ok(Transactions::default());
+        }
+
+        Self::validate_headers(&headers)?;
+
+        let mut transactions = Transactions::with_capacity(capacity);
+        for (index, record) in csv_reader.records().enumerate() {
+            match self.parse_record(&headers, record, index) {
+                Ok(trx) => transactions.push(trx),
+                Err(error) if self.skip_bad_rows => {
+                    if let Some(ErrorHook(hook)) = self.skipped_row_hook {
+                        hook(&error);
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(transactions)
+    }
+
+    ///
+    /// Reads all `.csv` files in a directory in lexicographic filename order and concatenates their transactions
+    /// Other files and subdirectories are skipped
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path is not a directory, it can't be listed, or any csv file fails to be read
+    ///
+    pub fn read_dir(&self, path: &Path) -> Result<Transactions> {
+        if !path.is_dir() {
+            return Err(PaymentsError::NotADirectory(path.to_path_buf()));
+        }
+
+        let mut paths = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.retain(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+        });
+        paths.sort();
+
+        let mut transactions = Transactions::default();
+        for path in paths {
+            transactions.extend(self.read(&path)?);
+        }
+
+        Ok(transactions)
+    }
+
+    ///
+    /// Reads all `.csv` entries of a zip archive in entry order and concatenates their transactions
+    /// Entries in nested directories are read too, directory entries, other files and entries with unsafe paths are skipped
+    ///
+    /// The maximum size applies to the archive and to the uncompressed size of its csv entries,
+    /// and no more than its uncompressed size is read from an entry, so an archive can't expand past the limit
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive does not exist, it or its csv entries are larger than the maximum size,
+    /// it is not a valid zip archive, or any csv entry fails to be read
+    ///
+    pub fn read_zip(&self, path: &Path) -> Result<Transactions> {
+        let mut archive = zip::ZipArchive::new(self.open(path)?).map_err(PaymentsError::Archive)?;
+
+        let mut transactions = Transactions::default();
+        let mut uncompressed_size = 0_u64;
+        for index in 0..archive.len() {
+            let entry = archive.by_index(index).map_err(PaymentsError::Archive)?;
+            let is_csv = entry
+                .enclosed_name()
+                .and_then(|name| name.extension().map(ToOwned::to_owned))
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+
+            if entry.is_file() && is_csv {
+                let size = entry.size();
+                uncompressed_size = uncompressed_size.saturating_add(size);
+                if let Some(max) = self.max_bytes.filter(|max| uncompressed_size > *max) {
+                    return Err(PaymentsError::FileTooLarge {
+                        path: path.to_path_buf(),
+                        size: uncompressed_size,
+                        max,
+                    });
+                }
+
+                transactions.extend(self.read_from(entry.take(size))?);
+            }
+        }
+
+        Ok(transactions)
+    }
+
+    ///
+    /// Opens a file to be read, checking its size against the maximum size before anything is read
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file does not exist, can't be opened or is larger than the maximum size
+    ///
+    fn open(&self, path: &Path) -> Result<File> {
+        if !path.exists() {
+            return Err(PaymentsError::FileNotFound(path.to_path_buf()));
+        }
+
+        let open_error = |source| PaymentsError::OpenFile {
+            path: path.to_path_buf(),
+            source,
+        };
+        let file = File::open(path).map_err(open_error)?;
+
+        if let Some(max) = self.max_bytes {
+            let size = file.metadata().map_err(open_error)?.len();
+            if size > max {
+                return Err(PaymentsError::FileTooLarge {
+                    path: path.to_path_buf(),
+                    size,
+                    max,
+                });
+            }
+        }
+
+        Ok(file)
+    }
+
+    ///
+    /// Parses a single csv record into a transaction
+    ///
+    /// An amount cell with only whitespace is trimmed to an absent amount like an empty one,
+    /// since padded csv files write `, ,` for the amount of disputes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record can't be read, has an id out of range or fails to be deserialized,
+    /// or if it is a deposit or withdrawal without an amount
+    ///
+    fn parse_record(
+        &self,
+        headers: &csv::StringRecord,
+        record: csv::Result<csv::StringRecord>,
+        index: usize,
+    ) -> Result<Transaction> {
+        let mut record = record.map_err(|source| PaymentsError::CsvParse { index, source })?;
+
+        if self.strip_thousands_separators {
+            record = Self::map_amount(headers, &record, |amount| Ok(amount.replace(',', "")))?;
+        }
+
+        if let Some(symbol) = self.currency_symbol {
+            record = Self::map_amount(headers, &record, |amount| {
+                let unsigned = amount.trim_start_matches(['+', '-']);
+                let sign = &amount[..amount.len() - unsigned.len()];
+                let unsigned = unsigned.strip_prefix(symbol).unwrap_or(unsigned);
+
+                Ok(format!("{sign}{unsigned}"))
+            })?;
+        }
+
+        if let Some(scale) = self.minor_units_scale {
+            record = Self::map_amount(headers, &record, |amount| {
+                Self::from_minor_units(amount, scale, index)
+            })?;
+        }
+
+        // Report ids that don't fit their type explicitly instead of as a generic parse failure
+        Self::validate_id_bounds(headers, &record, index)?;
+
+        // Deserialize the csv record
+        let transaction = record
+            .deserialize::<Transaction>(Some(headers))
+            .map_err(|source| PaymentsError::CsvParse { index, source })?;
+
+        // Only disputes, resolves and chargebacks may leave the amount out
+        if transaction.amount.is_none()
+            && matches!(
+                transaction.r#type,
+                Type::Deposit | Type::Withdrawal | Type::Transfer
+            )
+        {
+            return Err(PaymentsError::MissingAmount {
+                index,
+                r#type: transaction.r#type,
+            });
+        }
+
+        if transaction.r#type == Type::Transfer && transaction.dest_client.is_none() {
+            return Err(PaymentsError::MissingDestination { index });
+        }
+
+        Ok(transaction)
+    }
+
+    ///
+    /// Translates the source header names with the column map of the reader
+    ///
+    fn map_headers(&self, headers: &csv::StringRecord) -> csv::StringRecord {
+        headers
+            .iter()
+            .map(|header| {
+                self.column_map
+                    .as_ref()
+                    .and_then(|column_map| column_map.get(header))
+                    .map_or(header, |field| field.header())
+            })
+            .collect()
+    }
+
+    ///
+    /// Returns a copy of the record with its amount field, if it isn't empty, transformed by the given function
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the function if the amount can't be transformed
+    ///
+    fn map_amount(
+        headers: &csv::StringRecord,
+        record: &csv::StringRecord,
+        map: impl Fn(&str) -> Result<String>,
+    ) -> Result<csv::StringRecord> {
+        let amount = headers.iter().position(|header| header == "amount");
+
+        record
+            .iter()
+            .enumerate()
+            .map(|(position, field)| {
+                if Some(position) == amount && !field.is_empty() {
+                    map(field)
+                } else {
+                    Ok(field.to_string())
+                }
+            })
+            .collect()
+    }
+
+    ///
+    /// Converts an integer amount in minor units scaled by `10^scale` to a decimal amount
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the value if it isn't an integer or the scale is too large to be represented
+    ///
+    fn from_minor_units(amount: &str, scale: u32, index: usize) -> Result<String> {
+        amount
+            .parse::<i64>()
+            .ok()
+            .and_then(|minor| Decimal::try_from_i128_with_scale(i128::from(minor), scale).ok())
+            .map(|amount| amount.to_string())
+            .ok_or_else(|| PaymentsError::InvalidMinorUnits {
+                index,
+                value: amount.to_string(),
+            })
+    }
+
+    ///
+    /// Validates that the client and tx ids of a record are integers within the bounds of their types
+    ///
+    /// Values that aren't integers at all are left for the deserializer to report
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the field and the value if an id is negative or too large
+    ///
+    fn validate_id_bounds(
+        headers: &csv::StringRecord,
+        record: &csv::StringRecord,
+        index: usize,
+    ) -> Result<()> {
+        let bounds = [
+            ("client", u64::from(u16::MAX)),
+            ("tx", u64::from(u32::MAX)),
+            ("dest_client", u64::from(u16::MAX)),
+        ];
+
+        for (field, max) in bounds {
+            let Some(value) = headers
+                .iter()
+                .position(|header| header == field)
+                .and_then(|position| record.get(position))
+            else {
+                continue;
+            };
+
+            let Ok(id) = value.parse::<i128>() else {
+                continue;
+            };
+
+            if id < 0 || id > i128::from(max) {
+                return Err(PaymentsError::IdOutOfRange {
+                    index,
+                    field,
+                    value: value.to_string(),
+                    max,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Validates that the csv header contains exactly the expected columns
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the unexpected and missing columns if the header does not match
+    ///
+    fn validate_headers(headers: &csv::StringRecord) -> Result<()> {
+        let unexpected = headers
+            .iter()
+            .filter(|header| {
+                !EXPECTED_HEADERS.contains(header) && !OPTIONAL_HEADERS.contains(header)
+            })
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        let missing = EXPECTED_HEADERS
+            .iter()
+            .filter(|expected| !headers.iter().any(|header| header == **expected))
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        if unexpected.is_empty() && missing.is_empty() {
+            return Ok(());
+        }
+
+        Err(PaymentsError::InvalidHeader {
+            unexpected,
+            missing,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Amount;
+    use crate::transaction::Type;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn parse_line_parses_a_headerless_row() {
+        let reader = TransactionsReader::new();
+
+        let deposit = reader
+            .parse_line("deposit, 1, 2, 1.5", 0)
+            .expect("Failed to parse line");
+        assert_eq!(deposit.r#type, Type::Deposit);
+        assert_eq!((deposit.client, deposit.tx), (1, 2));
+        assert_eq!(deposit.amount, Some(Amount::new(15, 1)));
+
+        let dispute = reader
+            .parse_line("dispute,1,2", 1)
+            .expect("Failed to parse line");
+        assert_eq!(dispute.r#type, Type::Dispute);
+        assert_eq!(dispute.amount, None);
+
+        assert!(matches!(
+            reader.parse_line("deposit,1,x,1.0", 2),
+            Err(PaymentsError::CsvParse { index: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn default_reader_fails_on_bad_row() {
+        let error = TransactionsReader::new()
+            .read(Path::new("tests/resources/inputs/bad_row.csv"))
+            .err()
+            .expect("Expected an error for a malformed row");
+
+        assert!(matches!(error, PaymentsError::CsvParse { index: 2, .. }));
+    }
+
+    #[test]
+    fn skip_bad_rows_keeps_good_rows() {
+        static SKIPPED_ROWS: AtomicUsize = AtomicUsize::new(0);
+
+        let transactions = TransactionsReader::new()
+            .with_skip_bad_rows(true)
+            .with_skipped_row_hook(|error| {
+                assert!(matches!(error, PaymentsError::CsvParse { index: 2, .. }));
+                SKIPPED_ROWS.fetch_add(1, Ordering::Relaxed);
+            })
+            .read(Path::new("tests/resources/inputs/bad_row.csv"))
+            .expect("Failed to read transactions from csv");
+
+        let txs = transactions.iter().map(|trx| trx.tx).collect::<Vec<_>>();
+        assert_eq!(txs, [1, 2, 4, 5]);
+        assert_eq!(SKIPPED_ROWS.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn quoted_fields_are_read() {
+        let data =
+            "type,client,tx,amount\n\"deposit\",\"1\",\"1\",\"1.5\"\n\"withdrawal\",1,2,\"0.5\"\n";
+
+        let transactions = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        let types = transactions
+            .iter()
+            .map(|trx| trx.r#type)
+            .collect::<Vec<_>>();
+        assert_eq!(types, [Type::Deposit, Type::Withdrawal]);
+        assert_eq!(
+            transactions.get_tx(1).and_then(|trx| trx.amount),
+            Some(Amount::new(15, 1))
+        );
+    }
+
+    #[test]
+    fn bom_and_crlf_are_read() {
+        for data in [
+            "\u{feff}type,client,tx,amount\r\ndeposit,1,1,1.5\r\n",
+            "\u{feff}\"type\",client,tx,amount\r\ndeposit,1,1,1.5\r\n",
+        ] {
+            let transactions = TransactionsReader::new()
+                .read_from(data.as_bytes())
+                .expect("Failed to read transactions");
+
+            assert_eq!(
+                transactions.get_tx(1).and_then(|trx| trx.amount),
+                Some(Amount::new(15, 1))
+            );
+        }
+    }
+
+    #[test]
+    fn grouped_amount_fails_without_stripping() {
+        let data = "type,client,tx,amount\ndeposit,1,1,\"1,000.50\"\n";
+
+        let error = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .err()
+            .expect("Expected an error for a grouped amount");
+
+        assert!(matches!(error, PaymentsError::CsvParse { index: 0, .. }));
+    }
+
+    #[test]
+    fn strip_thousands_separators_reads_grouped_amounts() {
+        let data = "type,client,tx,amount\ndeposit,1,1,\"1,000.50\"\nwithdrawal,1,2,\"1,234,567\"\ndispute,1,1,\n";
+
+        let transactions = TransactionsReader::new()
+            .with_strip_thousands_separators(true)
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        assert_eq!(
+            transactions.get_tx(1).and_then(|trx| trx.amount),
+            Some(Amount::new(100_050, 2))
+        );
+        assert_eq!(
+            transactions.get_tx(2).and_then(|trx| trx.amount),
+            Some(Amount::from(1_234_567))
+        );
+        assert_eq!(transactions.len(), 3);
+    }
+
+    #[test]
+    fn empty_amount_is_absent_on_disputes() {
+        let data = "type,client,tx,amount\ndeposit,1,1,5\ndispute,1,1,\nresolve,1,1\n";
+
+        let transactions = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        let amounts = transactions
+            .iter()
+            .map(|trx| trx.amount)
+            .collect::<Vec<_>>();
+        assert_eq!(amounts, [Some(Amount::from(5)), None, None]);
+    }
+
+    #[test]
+    fn whitespace_only_amount_is_absent_on_disputes() {
+        let data = "type, client, tx, amount, timestamp\ndeposit, 1, 1, 5, \ndispute, 1, 1,   , \n";
+
+        let transactions = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        let dispute = transactions.get(1).expect("Failed to get dispute");
+        assert_eq!(dispute.r#type, Type::Dispute);
+        assert_eq!(dispute.amount, None);
+    }
+
+    #[test]
+    fn whitespace_only_amount_is_rejected_on_deposits() {
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 5\ndeposit, 1, 2, \t \n";
+
+        let error = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .err()
+            .expect("Expected an error for a whitespace only amount");
+
+        assert!(matches!(
+            error,
+            PaymentsError::MissingAmount {
+                index: 1,
+                r#type: Type::Deposit
+            }
+        ));
+    }
+
+    #[test]
+    fn padded_amount_is_trimmed() {
+        let data = "type, client, tx, amount\ndeposit, 1, 1,  2.5 \n";
+
+        let transactions = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        assert_eq!(
+            transactions.get_tx(1).and_then(|trx| trx.amount),
+            Some(Amount::new(25, 1))
+        );
+    }
+
+    #[test]
+    fn empty_amount_is_rejected_on_deposits_and_withdrawals() {
+        for (row, r#type) in [
+            ("deposit,1,1,", Type::Deposit),
+            ("withdrawal,1,1", Type::Withdrawal),
+        ] {
+            let data = format!("type,client,tx,amount\n{row}\n");
+
+            let error = TransactionsReader::new()
+                .read_from(data.as_bytes())
+                .err()
+                .expect("Expected an error for a missing amount");
+
+            assert!(matches!(
+                error,
+                PaymentsError::MissingAmount { index: 0, r#type: error_type } if error_type == r#type
+            ));
+        }
+    }
+
+    #[test]
+    fn leading_plus_sign_is_accepted() {
+        let data = "type,client,tx,amount\ndeposit,1,1,+100.00\n";
+
+        let transactions = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        assert_eq!(
+            transactions.get_tx(1).and_then(|trx| trx.amount),
+            Some(Amount::from(100))
+        );
+    }
+
+    #[test]
+    fn strip_currency_symbol_reads_prefixed_amounts() {
+        let data = "type,client,tx,amount\ndeposit,1,1,$100.00\ndeposit,1,2,+$2.5\nwithdrawal,1,3,-$1\ndispute,1,1,\n";
+
+        let transactions = TransactionsReader::new()
+            .with_strip_currency_symbol('$')
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        let amounts = transactions
+            .iter()
+            .map(|trx| trx.amount)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            amounts,
+            [
+                Some(Amount::from(100)),
+                Some(Amount::new(25, 1)),
+                Some(Amount::from(-1)),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn currency_symbol_fails_without_strip_option() {
+        let data = "type,client,tx,amount\ndeposit,1,1,$100.00\n";
+
+        let error = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .err()
+            .expect("Expected an error for an amount with a currency symbol");
+
+        assert!(matches!(error, PaymentsError::CsvParse { index: 0, .. }));
+    }
+
+    #[test]
+    fn amount_in_minor_units_reads_scaled_integers() {
+        let data = "type,client,tx,amount\ndeposit,1,1,12345\nwithdrawal,1,2,-5\ndispute,1,1,\n";
+
+        let transactions = TransactionsReader::new()
+            .with_amount_in_minor_units(2)
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        assert_eq!(
+            transactions.get_tx(1).and_then(|trx| trx.amount),
+            Some(Amount::new(12_345, 2))
+        );
+        assert_eq!(
+            transactions.get_tx(2).and_then(|trx| trx.amount),
+            Some(Amount::new(-5, 2))
+        );
+        assert_eq!(transactions.len(), 3);
+    }
+
+    #[test]
+    fn amount_in_minor_units_fails_on_decimal_amount() {
+        let data = "type,client,tx,amount\ndeposit,1,1,123.45\n";
+
+        let error = TransactionsReader::new()
+            .with_amount_in_minor_units(2)
+            .read_from(data.as_bytes())
+            .err()
+            .expect("Expected an error for an amount that isn't an integer");
+
+        assert!(matches!(
+            error,
+            PaymentsError::InvalidMinorUnits { index: 0, value } if value == "123.45"
+        ));
+    }
+
+    #[test]
+    fn column_map_reads_differently_named_headers() {
+        let data = "txType,clientId,txId,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,0.5\n";
+        let column_map = HashMap::from([
+            ("txType".to_string(), Field::Type),
+            ("clientId".to_string(), Field::Client),
+            ("txId".to_string(), Field::Tx),
+        ]);
+
+        let transactions = TransactionsReader::new()
+            .with_column_map(column_map)
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        let ids = transactions
+            .iter()
+            .map(|trx| (trx.r#type, trx.client, trx.tx))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [(Type::Deposit, 1, 1), (Type::Withdrawal, 1, 2)]);
+
+        let error = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .err()
+            .expect("Expected an error without the column map");
+        assert!(matches!(error, PaymentsError::InvalidHeader { .. }));
+    }
+
+    #[test]
+    fn skip_bad_rows_still_fails_on_bad_header() {
+        let error = TransactionsReader::new()
+            .with_skip_bad_rows(true)
+            .read(Path::new("tests/resources/inputs/bad_header.csv"))
+            .err()
+            .expect("Expected an error for a misspelled header");
+
+        assert!(matches!(error, PaymentsError::InvalidHeader { .. }));
+    }
+
+    #[test]
+    fn transfer_reads_the_destination_client() {
+        let data = "type,client,tx,amount,dest_client\ndeposit,1,1,5,\ntransfer,1,2,2.5,7\n";
+
+        let transactions = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .expect("Failed to read transactions");
+
+        let transfer = transactions.get(1).expect("Failed to get transfer");
+        assert_eq!(transfer.r#type, Type::Transfer);
+        assert_eq!(transfer.amount, Some(Amount::new(25, 1)));
+        assert_eq!(transfer.dest_client, Some(7));
+        assert_eq!(transactions.get(0).and_then(|trx| trx.dest_client), None);
+    }
+
+    #[test]
+    fn transfer_without_destination_fails() {
+        let data = "type,client,tx,amount,dest_client\ntransfer,1,1,2.5,\n";
+
+        let error = TransactionsReader::new()
+            .read_from(data.as_bytes())
+            .err()
+            .expect("Expected an error for a transfer without a destination");
+
+        assert!(matches!(
+            error,
+            PaymentsError::MissingDestination { index: 0 }
+        ));
+    }
+
+    #[test]
+    fn file_larger_than_max_bytes_is_rejected() {
+        let path = Path::new("tests/resources/inputs/trx1.csv");
+        let size = std::fs::metadata(path)
+            .expect("Failed to read file metadata")
+            .len();
+
+        let error = TransactionsReader::new()
+            .with_max_bytes(16)
+            .read(path)
+            .err()
+            .expect("Expected an error for a file larger than the maximum size");
+
+        assert!(matches!(
+            error,
+            PaymentsError::FileTooLarge { size: error_size, max: 16, .. } if error_size == size
+        ));
+
+        let transactions = TransactionsReader::new()
+            .with_max_bytes(size)
+            .read(path)
+            .expect("Failed to read file of exactly the maximum size");
+        assert_eq!(transactions.len(), 5);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::money::Amount;
+use crate::transaction::{Transaction, Type};
+use serde::{Deserialize, Serialize};
+
+///
+/// The reason a transaction was rejected by the engine
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The account is locked and the locked policy does not allow the transaction
+    AccountLocked,
+    /// The account does not have enough available funds for a withdrawal
+    InsufficientFunds,
+    /// The deposit or withdrawal was already applied (only with deduplication enabled)
+    Duplicate,
+    /// The amount carried by the transaction is not valid for the operation
+    InvalidAmount,
+    /// The referenced transaction was already charged back
+    ChargedBack,
+    /// The amount of the deposit or withdrawal has more decimal places than the engine allows
+    TooManyDecimals,
+    /// The resolve or chargeback references a transaction that isn't disputed (only with strict disputes enabled)
+    NotDisputed,
+    /// The amount of the deposit or withdrawal is above the engine's transaction limit
+    ExceedsLimit,
+    /// The client already has the maximum number of open disputes the engine allows
+    TooManyDisputes,
+    /// The dispute carries an amount that doesn't match the amount of the referenced transaction
+    AmountMismatch,
+    /// The transaction is of a new client and the engine already has the maximum number of clients it allows
+    TooManyClients,
+    /// The transaction is of client id `0` (or a transfer to it) and the engine rejects the zero client
+    ZeroClient,
+    /// The destination account of the transfer is locked, the account of its client may not be
+    DestinationLocked,
+    /// The destination client of the transfer is excluded by the client filter of the engine
+    DestinationExcluded,
+    /// Applying the transaction would overflow a balance of the account
+    Overflow,
+}
+
+///
+/// A transaction that was rejected while processing
+///
+/// The index is the position of the transaction in the engine's transaction store
+/// The referenced type is the type of the deposit or withdrawal referenced by a rejected dispute, resolve or chargeback,
+/// since their semantics differ by referenced type, and `None` for deposits and withdrawals
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    pub index: usize,
+    pub client: u16,
+    pub tx: u32,
+    pub reason: RejectReason,
+    pub referenced: Option<Type>,
+}
+
+///
+/// A withdrawal that was only partially applied because of insufficient funds
+///
+/// Only recorded with `WithdrawalPolicy::PartialFill`
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartialFill {
+    pub index: usize,
+    pub client: u16,
+    pub tx: u32,
+    pub requested: Amount,
+    pub withdrawn: Amount,
+}
+
+///
+/// A resolve or chargeback that settled more funds than its account held
+///
+/// The account's held funds are the sum of its open disputes and never go negative,
+/// so the settled amount was clamped to the held funds
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeldDiscrepancy {
+    pub index: usize,
+    pub client: u16,
+    pub tx: u32,
+    pub settled: Amount,
+    pub held: Amount,
+}
+
+///
+/// A balance field of an account
+///
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalanceField {
+    Available,
+    Held,
+    Total,
+}
+
+///
+/// A change of a balance field of an account, recorded in the engine's event log
+///
+/// The index is the position of the transaction that changed the balance in the engine's transaction store,
+/// and the reason is its type. The referenced type is the type of the transaction referenced by a dispute,
+/// resolve or chargeback, see `Rejection`
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BalanceEvent {
+    pub index: usize,
+    pub client: u16,
+    pub currency: Option<String>,
+    pub field: BalanceField,
+    pub old: Amount,
+    pub new: Amount,
+    pub reason: Type,
+    pub referenced: Option<Type>,
+}
+
+///
+/// A transaction of a client's ledger, with the balances of the client's account right after it was applied
+///
+/// The index is the position of the transaction in the engine's transaction store
+///
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct LedgerEntry<'a> {
+    pub index: usize,
+    pub transaction: &'a Transaction,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+///
+/// Summary of the problems found while processing transactions
+///
+/// Rejections and partial fills are accumulated across all `Engine::process` calls
+///
+/// The transactions dropped because their account was locked are also listed as (client, tx) pairs,
+/// to reconcile balance changes that didn't happen after a chargeback locked the account
+///
+#[derive(Debug, Default)]
+pub struct ProcessReport {
+    pub rejected: Vec<Rejection>,
+    pub partial_fills: Vec<PartialFill>,
+    pub held_discrepancies: Vec<HeldDiscrepancy>,
+    pub dropped_due_to_lock: Vec<(u16, u32)>,
+}
+
+impl ProcessReport {
+    ///
+    /// Returns true if no transaction was rejected
+    ///
+    pub const fn is_clean(&self) -> bool {
+        self.rejected.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_report_is_clean() {
+        let report = ProcessReport::default();
+
+        assert!(report.is_clean());
+    }
+
+    #[test]
+    fn report_with_rejection_is_not_clean() {
+        let mut report = ProcessReport::default();
+        report.rejected.push(Rejection {
+            index: 0,
+            client: 1,
+            tx: 1,
+            reason: RejectReason::InsufficientFunds,
+            referenced: None,
+        });
+
+        assert!(!report.is_clean());
+    }
+}
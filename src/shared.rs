@@ -0,0 +1,387 @@
+use crate::account::{Account, Accounts};
+use crate::engine::Engine;
+use crate::error::{PaymentsError, Result};
+use crate::report::ProcessReport;
+use crate::transaction::{Transaction, Type};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+///
+/// A thread-safe front for several engines, so multiple worker threads can feed transactions at once
+///
+/// The clients are sharded across the engines by client id, each engine runs on its own thread
+/// and processes the transactions routed to it from a queue in the order they were submitted
+/// The transactions of a client always go to the same engine, so they are applied in submission order,
+/// while the transactions of clients in different shards are processed concurrently
+///
+/// Transfers are refused since they change the accounts of two clients that may be on different shards,
+/// every other transaction only changes the account of its own client
+///
+/// The merged accounts are the same as processing every transaction on a single engine, as long as a dispute
+/// only references transactions of its own client and no withdrawal fee account is configured
+/// (the fee account would be credited by every shard)
+///
+pub struct SharedEngine {
+    queues: Vec<Sender<Submission>>,
+    workers: Vec<JoinHandle<(Accounts, ProcessReport)>>,
+}
+
+///
+/// A queued transaction, with the channel to send the resulting account of its client to if the submitter waits for it
+///
+type Submission = (Transaction, Option<Sender<Option<Account>>>);
+
+impl SharedEngine {
+    ///
+    /// Starts `shards` worker threads, each processing its clients on an engine built with `engine`
+    /// A `shards` count of zero is treated as one
+    ///
+    pub fn new(shards: usize, engine: impl Fn() -> Engine + Send + Clone + 'static) -> Self {
+        let (queues, workers) = (0..shards.max(1))
+            .map(|_| {
+                let (queue, receiver) = mpsc::channel::<Submission>();
+                let engine = engine.clone();
+
+                let worker = thread::spawn(move || {
+                    let mut engine = engine();
+                    for (transaction, reply) in receiver {
+                        let (client, currency) = (transaction.client, transaction.currency.clone());
+                        engine.process_one(transaction);
+
+                        if let Some(reply) = reply {
+                            let account = engine.accounts().get_in(client, currency.as_deref());
+                            let _ = reply.send(account.cloned());
+                        }
+                    }
+                    engine.into_parts()
+                });
+
+                (queue, worker)
+            })
+            .unzip();
+
+        Self { queues, workers }
+    }
+
+    ///
+    /// Queues a transaction on the engine of its client, it is processed after all the transactions
+    /// of the same client submitted before it
+    ///
+    /// # Errors
+    ///
+    /// Returns `PaymentsError::ShardedTransfer` for a transfer, which isn't queued
+    ///
+    pub fn submit(&self, transaction: Transaction) -> Result<()> {
+        self.queue(transaction, None).map(|_| ())
+    }
+
+    ///
+    /// Queues a transaction like `SharedEngine::submit` and waits until it is processed,
+    /// returning the resulting account of its client, `None` if the client has no account
+    ///
+    /// # Errors
+    ///
+    /// Returns `PaymentsError::ShardedTransfer` for a transfer, which isn't queued,
+    /// or `PaymentsError::ShardStopped` if the worker of the client's shard panicked and can't answer
+    ///
+    pub fn submit_and_wait(&self, transaction: Transaction) -> Result<Option<Account>> {
+        let (reply, response) = mpsc::channel();
+        let shard = self.queue(transaction, Some(reply))?;
+
+        response
+            .recv()
+            .map_err(|_| PaymentsError::ShardStopped(shard))
+    }
+
+    ///
+    /// Queues a transaction on the engine of its shard and returns the shard
+    ///
+    fn queue(
+        &self,
+        transaction: Transaction,
+        reply: Option<Sender<Option<Account>>>,
+    ) -> Result<usize> {
+        if transaction.r#type == Type::Transfer {
+            return Err(PaymentsError::ShardedTransfer {
+                client: transaction.client,
+                tx: transaction.tx,
+            });
+        }
+
+        let shard = usize::from(transaction.client) % self.queues.len();
+
+        // The receiver only goes away if its worker panicked, which is surfaced by `SharedEngine::finish`
+        let _ = self.queues[shard].send((transaction, reply));
+
+        Ok(shard)
+    }
+
+    ///
+    /// Waits until every submitted transaction is processed and returns the merged accounts of all the shards
+    ///
+    /// The insertion order of the merged accounts is the order of the shards, each keeping its own insertion order,
+    /// not the order the clients first appeared in the submitted transactions
+    ///
+    /// # Errors
+    ///
+    /// Returns `PaymentsError::OverlappingClient` if several shards have an account for the same client,
+    /// which only happens for a withdrawal fee account credited by several shards
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of a worker thread if one of the engines panicked
+    ///
+    pub fn finish(self) -> Result<Accounts> {
+        self.finish_with_reports().map(|(accounts, _)| accounts)
+    }
+
+    ///
+    /// Waits until every submitted transaction is processed and returns the merged accounts of all the shards,
+    /// with the process report of every shard in shard order
+    /// The indexes in a report are the positions of the transactions in the store of its shard
+    ///
+    /// # Errors
+    ///
+    /// Returns `PaymentsError::OverlappingClient` if several shards have an account for the same client,
+    /// which only happens for a withdrawal fee account credited by several shards
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of a worker thread if one of the engines panicked
+    ///
+    pub fn finish_with_reports(self) -> Result<(Accounts, Vec<ProcessReport>)> {
+        drop(self.queues);
+
+        let mut accounts = Accounts::new();
+        let mut reports = Vec::with_capacity(self.workers.len());
+        for worker in self.workers {
+            let (shard, report) = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+            accounts.merge(shard)?;
+            reports.push(report);
+        }
+
+        Ok((accounts, reports))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Amount;
+    use crate::report::RejectReason;
+    use crate::transaction::transaction;
+
+    fn shared_engine() -> SharedEngine {
+        SharedEngine::new(4, || Engine::new(Accounts::new()))
+    }
+
+    #[test]
+    fn concurrent_submissions_for_disjoint_clients() {
+        let engine = shared_engine();
+
+        thread::scope(|scope| {
+            for client in 1..=8_u16 {
+                let engine = &engine;
+                scope.spawn(move || {
+                    for n in 0..1000 {
+                        let tx = u32::from(client) * 10_000 + n;
+                        engine
+                            .submit(transaction(Type::Deposit, client, tx, Some(2.into())))
+                            .expect("Failed to submit transaction");
+                        engine
+                            .submit(transaction(
+                                Type::Withdrawal,
+                                client,
+                                tx + 5000,
+                                Some(1.into()),
+                            ))
+                            .expect("Failed to submit transaction");
+                    }
+                });
+            }
+        });
+
+        let accounts = engine.finish().expect("Failed to merge the shards");
+        assert_eq!(accounts.len(), 8);
+        for account in accounts.iter() {
+            assert_eq!(account.available, Amount::from(1000));
+            assert_eq!(account.total, Amount::from(1000));
+        }
+    }
+
+    #[test]
+    fn concurrent_submissions_for_overlapping_clients_keep_client_order() {
+        let engine = shared_engine();
+
+        // Every thread disputes its own deposits right after submitting them, and leaves every other one disputed,
+        // a dispute processed before its deposit would be ignored and leave less funds held
+        thread::scope(|scope| {
+            for worker in 0..8_u32 {
+                let engine = &engine;
+                scope.spawn(move || {
+                    for n in 0..1000_u32 {
+                        let client = u16::try_from(n % 5 + 1).expect("Client id out of range");
+                        let tx = worker * 10_000 + n;
+                        engine
+                            .submit(transaction(Type::Deposit, client, tx, Some(1.into())))
+                            .expect("Failed to submit transaction");
+                        engine
+                            .submit(transaction(Type::Dispute, client, tx, None))
+                            .expect("Failed to submit transaction");
+                        if n % 2 == 0 {
+                            engine
+                                .submit(transaction(Type::Resolve, client, tx, None))
+                                .expect("Failed to submit transaction");
+                        }
+                    }
+                });
+            }
+        });
+
+        let accounts = engine.finish().expect("Failed to merge the shards");
+        assert_eq!(accounts.len(), 5);
+        for account in accounts.iter() {
+            assert_eq!(account.total, Amount::from(1600));
+            assert_eq!(account.held, Amount::from(800));
+            assert_eq!(account.available, Amount::from(800));
+        }
+    }
+
+    #[test]
+    fn submit_and_wait_returns_the_resulting_account() {
+        let engine = shared_engine();
+
+        engine
+            .submit(transaction(Type::Deposit, 1, 1, Some(3.into())))
+            .expect("Failed to submit transaction");
+        let account = engine
+            .submit_and_wait(transaction(Type::Withdrawal, 1, 2, Some(1.into())))
+            .expect("Failed to submit transaction")
+            .expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(2));
+
+        // A dispute of an unknown transaction creates no account
+        assert_eq!(
+            engine
+                .submit_and_wait(transaction(Type::Dispute, 2, 3, None))
+                .expect("Failed to submit transaction"),
+            None
+        );
+    }
+
+    #[test]
+    fn submit_and_wait_fails_if_the_worker_stopped() {
+        let engine = SharedEngine::new(2, || {
+            let mut engine = Engine::new(Accounts::new());
+            engine.set_low_balance_hook(Amount::from(5), |_, _| panic!("injected worker failure"));
+            engine
+        });
+
+        engine
+            .submit(transaction(Type::Deposit, 1, 1, Some(3.into())))
+            .expect("Failed to submit transaction");
+        assert!(matches!(
+            engine.submit_and_wait(transaction(Type::Withdrawal, 1, 2, Some(1.into()))),
+            Err(PaymentsError::ShardStopped(1))
+        ));
+    }
+
+    #[test]
+    fn transfers_are_refused() {
+        let engine = SharedEngine::new(2, || Engine::new(Accounts::new()));
+        engine
+            .submit(transaction(Type::Deposit, 1, 1, Some(3.into())))
+            .expect("Failed to submit transaction");
+        engine
+            .submit(transaction(Type::Deposit, 2, 2, Some(3.into())))
+            .expect("Failed to submit transaction");
+
+        let transfer = Transaction {
+            dest_client: Some(2),
+            ..transaction(Type::Transfer, 1, 3, Some(1.into()))
+        };
+        assert!(matches!(
+            engine.submit(transfer),
+            Err(PaymentsError::ShardedTransfer { client: 1, tx: 3 })
+        ));
+
+        let accounts = engine.finish().expect("Failed to merge the shards");
+        assert_eq!(
+            accounts.get(1).map(|account| account.total),
+            Some(Amount::from(3))
+        );
+        assert_eq!(
+            accounts.get(2).map(|account| account.total),
+            Some(Amount::from(3))
+        );
+    }
+
+    #[test]
+    fn finish_with_reports_keeps_the_report_of_every_shard() {
+        let engine = SharedEngine::new(2, || Engine::new(Accounts::new()));
+        engine
+            .submit(transaction(Type::Deposit, 1, 1, Some(3.into())))
+            .expect("Failed to submit transaction");
+        engine
+            .submit(transaction(Type::Withdrawal, 1, 2, Some(5.into())))
+            .expect("Failed to submit transaction");
+        engine
+            .submit(transaction(Type::Withdrawal, 2, 3, Some(5.into())))
+            .expect("Failed to submit transaction");
+
+        let (accounts, reports) = engine
+            .finish_with_reports()
+            .expect("Failed to merge the shards");
+        // The rejected withdrawal of client 2 doesn't create its account
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(reports.len(), 2);
+
+        // Client 2 is on the first shard and client 1 on the second one
+        assert_eq!(reports[0].rejected[0].tx, 3);
+        assert_eq!(
+            reports[1].rejected[0].reason,
+            RejectReason::InsufficientFunds
+        );
+    }
+
+    #[test]
+    fn finish_fails_for_a_fee_account_credited_by_several_shards() {
+        let engine = SharedEngine::new(2, || {
+            Engine::new(Accounts::new()).with_withdrawal_fee(100, 9)
+        });
+        for client in 1..=2 {
+            let tx = u32::from(client) * 10;
+            engine
+                .submit(transaction(Type::Deposit, client, tx, Some(10.into())))
+                .expect("Failed to submit transaction");
+            engine
+                .submit(transaction(
+                    Type::Withdrawal,
+                    client,
+                    tx + 1,
+                    Some(5.into()),
+                ))
+                .expect("Failed to submit transaction");
+        }
+
+        assert!(matches!(
+            engine.finish(),
+            Err(PaymentsError::OverlappingClient(9))
+        ));
+    }
+
+    #[test]
+    fn zero_shards_are_treated_as_one() {
+        let engine = SharedEngine::new(0, || Engine::new(Accounts::new()));
+        engine
+            .submit(transaction(Type::Deposit, 1, 1, Some(3.into())))
+            .expect("Failed to submit transaction");
+
+        let accounts = engine.finish().expect("Failed to merge the shards");
+        let account = accounts.get(1).expect("Failed to get account");
+        assert_eq!(account.available, Amount::from(3));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::account::Account;
+use crate::money::Amount;
+use crate::policy::{LockedPolicy, WithdrawalPolicy};
+use crate::report::{BalanceEvent, HeldDiscrepancy, PartialFill, Rejection};
+use crate::transaction::{Transaction, Type};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+///
+/// The serialized state of an engine, written by `Engine::save_snapshot` and read by `Engine::load_snapshot`
+///
+/// The snapshot has its own representation of accounts and transactions instead of reusing their csv one,
+/// since the binary format requires every field to be written and read back in the same order
+///
+#[derive(Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct EngineSnapshot {
+    pub accounts: AccountsSnapshot,
+    pub transactions: TransactionsSnapshot,
+    pub last_processed_transaction_index: usize,
+    pub locked_policy: LockedPolicy,
+    pub withdrawal_policy: WithdrawalPolicy,
+    pub max_scale: u32,
+    pub max_transaction_amount: Option<Amount>,
+    pub strict_disputes: bool,
+    pub allow_dispute_resolution_when_locked: bool,
+    pub allow_direct_chargeback: bool,
+    pub max_open_disputes_per_client: Option<usize>,
+    pub max_clients: Option<usize>,
+    pub reject_zero_client: bool,
+    pub open_disputes: HashMap<u16, usize>,
+    pub withdrawal_fee_bps: u32,
+    pub fee_account: u16,
+    pub transaction_counts: HashMap<u16, u64>,
+    pub applied_transactions: Option<HashSet<u32>>,
+    pub rejected: Vec<Rejection>,
+    pub partial_fills: Vec<PartialFill>,
+    pub held_discrepancies: Vec<HeldDiscrepancy>,
+    pub dropped_due_to_lock: Vec<(u16, u32)>,
+    pub event_log: Option<Vec<BalanceEvent>>,
+}
+
+///
+/// The accounts of a snapshot, in client id (and currency) order, also written by `Accounts::write_binary`
+///
+#[derive(Serialize, Deserialize)]
+pub struct AccountsSnapshot {
+    pub accounts: Vec<AccountSnapshot>,
+    pub base_currency: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    client: u16,
+    currency: Option<String>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    locked_by: Option<u32>,
+    total_deposited: Amount,
+    total_withdrawn: Amount,
+    rejected_withdrawals: u32,
+    first_tx_index: usize,
+    last_tx_index: usize,
+    sequence: u64,
+}
+
+impl From<(&Account, u64)> for AccountSnapshot {
+    fn from((account, sequence): (&Account, u64)) -> Self {
+        Self {
+            client: account.client,
+            currency: account.currency.clone(),
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            locked_by: account.locked_by,
+            total_deposited: account.total_deposited,
+            total_withdrawn: account.total_withdrawn,
+            rejected_withdrawals: account.rejected_withdrawals,
+            first_tx_index: account.first_tx_index,
+            last_tx_index: account.last_tx_index,
+            sequence,
+        }
+    }
+}
+
+impl From<AccountSnapshot> for (Account, u64) {
+    fn from(snapshot: AccountSnapshot) -> Self {
+        let account = Account {
+            client: snapshot.client,
+            currency: snapshot.currency,
+            available: snapshot.available,
+            held: snapshot.held,
+            total: snapshot.total,
+            locked: snapshot.locked,
+            locked_by: snapshot.locked_by,
+            total_deposited: snapshot.total_deposited,
+            total_withdrawn: snapshot.total_withdrawn,
+            rejected_withdrawals: snapshot.rejected_withdrawals,
+            first_tx_index: snapshot.first_tx_index,
+            last_tx_index: snapshot.last_tx_index,
+        };
+
+        (account, snapshot.sequence)
+    }
+}
+
+///
+/// The stored transactions of a snapshot, in processing order, with the amount applied by each applied one
+/// and the amount still held by each disputed one with the client whose dispute holds it
+///
+#[derive(Serialize, Deserialize)]
+pub struct TransactionsSnapshot {
+    pub transactions: Vec<TransactionSnapshot>,
+    pub applied: HashMap<u32, Amount>,
+    pub disputed: HashMap<u32, (Amount, u16)>,
+    pub charged_back: HashSet<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransactionSnapshot {
+    r#type: Type,
+    client: u16,
+    tx: u32,
+    amount: Option<Amount>,
+    timestamp: Option<DateTime<Utc>>,
+    currency: Option<String>,
+    dest_client: Option<u16>,
+    disputed: bool,
+}
+
+impl From<&Transaction> for TransactionSnapshot {
+    fn from(transaction: &Transaction) -> Self {
+        Self {
+            r#type: transaction.r#type,
+            client: transaction.client,
+            tx: transaction.tx,
+            amount: transaction.amount,
+            timestamp: transaction.timestamp,
+            currency: transaction.currency.clone(),
+            dest_client: transaction.dest_client,
+            disputed: transaction.disputed,
+        }
+    }
+}
+
+impl From<TransactionSnapshot> for Transaction {
+    fn from(snapshot: TransactionSnapshot) -> Self {
+        Self {
+            r#type: snapshot.r#type,
+            client: snapshot.client,
+            tx: snapshot.tx,
+            amount: snapshot.amount,
+            timestamp: snapshot.timestamp,
+            currency: snapshot.currency,
+            dest_client: snapshot.dest_client,
+            disputed: snapshot.disputed,
+        }
+    }
+}
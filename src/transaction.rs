@@ -1,29 +1,103 @@
-use anyhow::{Context, Result};
-use rust_decimal::Decimal;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::fs::File;
-use std::path::{Path, PathBuf};
+use crate::error::{PaymentsError, Result};
+use crate::money::Amount;
+use crate::reader::TransactionsReader;
+use crate::snapshot::TransactionsSnapshot;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
 
 ///
 /// Represents all possible transaction types
 ///
-#[derive(Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
+/// Types are serialized in lowercase, but parsed case-insensitively and ignoring surrounding whitespace
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Type {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Transfer,
+}
+
+impl Type {
+    ///
+    /// All the transaction types
+    ///
+    pub const ALL: [Self; 6] = [
+        Self::Deposit,
+        Self::Withdrawal,
+        Self::Dispute,
+        Self::Resolve,
+        Self::Chargeback,
+        Self::Transfer,
+    ];
+
+    ///
+    /// Returns the lowercase name of the type, matching its csv representation
+    ///
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Transfer => "transfer",
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Type {
+    type Err = PaymentsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let trimmed = s.trim();
+
+        Self::ALL
+            .into_iter()
+            .find(|r#type| r#type.as_str().eq_ignore_ascii_case(trimmed))
+            .ok_or_else(|| PaymentsError::InvalidType(s.to_string()))
+    }
+}
+
+impl Serialize for Type {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Type {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let value = Cow::<str>::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
 }
 
 ///
 /// The actual transaction struct that holds the transaction data.
+///
 /// The disputed field is not part of the CSV file, but is used internally to keep track of disputed transactions
-/// Since only two transaction types have amounts, the amount field is optional.
+/// Since only deposits and withdrawals require amounts (a chargeback may carry a partial amount), the amount field is optional.
+/// The timestamp is an optional ISO-8601 column used to order transactions with `Transactions::sort_by_time`
+/// The currency is an optional column, transactions without one are booked in the base currency of the accounts
+/// The destination client is an optional column only used by transfers, which move the amount from the client to it
+/// When serialized, the columns are written in field order, with the disputed flag as the last column
 ///
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "lowercase")]
 pub struct Transaction {
     pub r#type: Type,
@@ -31,21 +105,46 @@ pub struct Transaction {
     pub tx: u32,
 
     #[serde(default)]
-    pub amount: Option<Decimal>,
+    pub amount: Option<Amount>,
+
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+
+    #[serde(default)]
+    pub currency: Option<String>,
+
+    #[serde(default)]
+    pub dest_client: Option<u16>,
 
-    #[serde(skip, default)]
+    #[serde(skip_deserializing, default)]
     pub disputed: bool,
 }
 
 ///
 /// Represents a collection of transactions
+///
 /// All the transactions are stored in a vec.
 /// A Hashmap is used as a way to quickly find the transaction vec index by a tx id.
+/// Another Hashmap keeps the tx ids of the deposits and withdrawals that were applied with the amount actually applied,
+/// only those can be disputed and a dispute holds that amount.
+/// Another Hashmap keeps the tx ids that are currently disputed with the amount still held for each and the client
+/// whose dispute holds it, so resolves and chargebacks can skip non-disputed references without touching the transaction.
+/// Another Hashset keeps the tx ids that were charged back, which is a terminal state.
+///
+/// The dispute state machine of a deposit or withdrawal is:
+/// - processed -> disputed (dispute), only if the transaction was applied
+/// - disputed -> disputed (partial resolve), part of the held amount is released and the rest stays held
+/// - disputed -> processed (resolve), the transaction may be disputed again afterwards
+/// - disputed -> charged back (chargeback), no further dispute, resolve or chargeback applies
 ///
 #[derive(Default)]
+#[allow(clippy::struct_field_names)]
 pub struct Transactions {
     transactions: Vec<Transaction>,
     tx_index_map: HashMap<u32, usize>,
+    applied_map: HashMap<u32, Amount>,
+    disputed_map: HashMap<u32, (Amount, u16)>,
+    charged_back_set: HashSet<u32>,
 }
 
 impl From<Vec<Transaction>> for Transactions {
@@ -53,32 +152,139 @@ impl From<Vec<Transaction>> for Transactions {
         let mut transactions = Self {
             transactions,
             tx_index_map: HashMap::new(),
+            applied_map: HashMap::new(),
+            disputed_map: HashMap::new(),
+            charged_back_set: HashSet::new(),
         };
 
-        transactions.populate_map();
+        transactions.populate_map(0);
         transactions
     }
 }
 
 impl Transactions {
+    ///
+    /// Creates an empty collection with room for `capacity` transactions without reallocating
+    ///
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            transactions: Vec::with_capacity(capacity),
+            tx_index_map: HashMap::with_capacity(capacity),
+            applied_map: HashMap::new(),
+            disputed_map: HashMap::new(),
+            charged_back_set: HashSet::new(),
+        }
+    }
+
     ///
     /// Extends Transactions with another collection of Transactions.
     /// This is useful when reading multiple csv files
-    /// The hashmap is repopulated after the transactions are extended
+    /// The new transactions are appended in their order, after all the existing ones
+    /// Only the new transactions are added to the hashmap, existing tx ids keep their mapping
     ///
     pub fn extend(&mut self, trxs: Self) {
+        let start = self.transactions.len();
         self.transactions.extend(trxs.transactions);
-        self.populate_map();
+        self.populate_map(start);
     }
 
     ///
-    /// Populates the hashmap with the transaction id as the key and the index of the transaction in the vec as the value
-    /// Only deposit and withdrawal transactions are added to the hashmap
+    /// Appends a single transaction, indexing it if it is a deposit or withdrawal
     ///
-    fn populate_map(&mut self) {
-        for (index, transaction) in self.transactions.iter().enumerate() {
-            if transaction.r#type == Type::Deposit || transaction.r#type == Type::Withdrawal {
-                self.tx_index_map.insert(transaction.tx, index);
+    pub fn push(&mut self, transaction: Transaction) {
+        self.transactions.push(transaction);
+        self.populate_map(self.transactions.len() - 1);
+    }
+
+    ///
+    /// Removes every transaction and its dispute state, keeping the allocated capacity
+    ///
+    pub fn clear(&mut self) {
+        self.transactions.clear();
+        self.tx_index_map.clear();
+        self.applied_map.clear();
+        self.disputed_map.clear();
+        self.charged_back_set.clear();
+    }
+
+    ///
+    /// Removes the last transaction, and its id mapping if the id points to it
+    ///
+    pub(crate) fn pop(&mut self) -> Option<Transaction> {
+        let transaction = self.transactions.pop()?;
+
+        let index = self.transactions.len();
+        if self.tx_index_map.get(&transaction.tx) == Some(&index) {
+            self.tx_index_map.remove(&transaction.tx);
+            self.applied_map.remove(&transaction.tx);
+            self.disputed_map.remove(&transaction.tx);
+            self.charged_back_set.remove(&transaction.tx);
+        }
+
+        Some(transaction)
+    }
+
+    ///
+    /// Removes the transaction at the given index, and its id mapping if the id points to it
+    /// The transactions after it move up by one, so the hashmap is repopulated unless it was the last one
+    ///
+    pub(crate) fn remove(&mut self, index: usize) -> Option<Transaction> {
+        if index + 1 == self.transactions.len() {
+            return self.pop();
+        }
+        if index >= self.transactions.len() {
+            return None;
+        }
+
+        let transaction = self.transactions.remove(index);
+        if self.tx_index_map.get(&transaction.tx) == Some(&index) {
+            self.applied_map.remove(&transaction.tx);
+            self.disputed_map.remove(&transaction.tx);
+            self.charged_back_set.remove(&transaction.tx);
+        }
+
+        self.tx_index_map.clear();
+        self.populate_map(0);
+
+        Some(transaction)
+    }
+
+    ///
+    /// Stably sorts the transactions by their timestamp so they are processed in time order
+    /// Transactions with the same timestamp (or without one) keep their file order,
+    /// and transactions without a timestamp are ordered before the ones with a timestamp
+    /// The hashmap is repopulated after sorting since the vec indexes change
+    ///
+    pub fn sort_by_time(&mut self) {
+        self.transactions
+            .sort_by_key(|transaction| transaction.timestamp);
+
+        self.tx_index_map.clear();
+        self.populate_map(0);
+    }
+
+    ///
+    /// Populates the hashmap with the transaction id as the key and the index of the transaction in the vec as the value,
+    /// for the transactions from index `start` onwards
+    /// Only deposit and withdrawal transactions are added to the hashmap, and a tx id that is already mapped
+    /// keeps its first transaction, so a reused tx id never moves the mapping of an already processed transaction
+    /// A newly mapped transaction flagged as disputed is added to the disputed map, holding its full amount
+    /// for its own client, unless its dispute is already tracked
+    ///
+    fn populate_map(&mut self, start: usize) {
+        for (index, transaction) in self.transactions.iter().enumerate().skip(start) {
+            if transaction.r#type != Type::Deposit && transaction.r#type != Type::Withdrawal {
+                continue;
+            }
+
+            if let Entry::Vacant(entry) = self.tx_index_map.entry(transaction.tx) {
+                entry.insert(index);
+
+                if transaction.disputed {
+                    self.disputed_map.entry(transaction.tx).or_insert_with(|| {
+                        (transaction.amount.unwrap_or_default(), transaction.client)
+                    });
+                }
             }
         }
     }
@@ -87,19 +293,57 @@ impl Transactions {
         self.transactions.get(index)
     }
 
-    pub fn len(&self) -> usize {
+    ///
+    /// Returns an iterator over all stored transactions in processing order
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.transactions.iter()
+    }
+
+    pub const fn len(&self) -> usize {
         self.transactions.len()
     }
 
-    pub fn is_empty(&self) -> bool {
+    pub const fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
+    ///
+    /// Captures the stored transactions and their dispute state for an engine snapshot
+    ///
+    pub(crate) fn snapshot(&self) -> TransactionsSnapshot {
+        TransactionsSnapshot {
+            transactions: self.transactions.iter().map(Into::into).collect(),
+            applied: self.applied_map.clone(),
+            disputed: self.disputed_map.clone(),
+            charged_back: self.charged_back_set.clone(),
+        }
+    }
+
+    ///
+    /// Restores the transactions of an engine snapshot, rebuilding the hashmap
+    ///
+    pub(crate) fn from_snapshot(snapshot: TransactionsSnapshot) -> Self {
+        let mut transactions = Self::from(
+            snapshot
+                .transactions
+                .into_iter()
+                .map(Transaction::from)
+                .collect::<Vec<_>>(),
+        );
+
+        transactions.applied_map = snapshot.applied;
+        transactions.disputed_map = snapshot.disputed;
+        transactions.charged_back_set = snapshot.charged_back;
+
+        transactions
+    }
+
     ///
     /// Returns a mutable reference to a transaction by transaction id
     /// Uses a hashmap to quickly find the index of the transaction
     ///
-    pub fn get_tx_mut(&mut self, tx: u32) -> Option<&mut Transaction> {
+    pub(crate) fn get_tx_mut(&mut self, tx: u32) -> Option<&mut Transaction> {
         if let Some(index) = self.tx_index_map.get(&tx) {
             return self.transactions.get_mut(*index);
         }
@@ -108,59 +352,301 @@ impl Transactions {
     }
 
     ///
-    /// Parses the command line arguments to get the input file path from the first argument and returns a Transactions struct
+    /// Returns a reference to a transaction by transaction id
+    ///
+    pub fn get_tx(&self, tx: u32) -> Option<&Transaction> {
+        self.tx_index_map
+            .get(&tx)
+            .and_then(|index| self.transactions.get(*index))
+    }
+
+    ///
+    /// Returns all stored transactions of a client, in the order they are stored (file order unless sorted by time)
+    /// This is a linear scan over every transaction, unlike the tx id lookups
+    ///
+    pub fn for_client(&self, client: u16) -> Vec<&Transaction> {
+        self.transactions
+            .iter()
+            .filter(|transaction| transaction.client == client)
+            .collect()
+    }
+
+    ///
+    /// Returns the disputes, resolves and chargebacks that reference a tx id without any deposit or withdrawal,
+    /// with their index in processing order
+    /// The engine silently ignores them, so this allows warning about them right after parsing
+    ///
+    pub fn dangling_references(&self) -> Vec<(usize, &Transaction)> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, transaction)| {
+                matches!(
+                    transaction.r#type,
+                    Type::Dispute | Type::Resolve | Type::Chargeback
+                ) && !self.tx_index_map.contains_key(&transaction.tx)
+            })
+            .collect()
+    }
+
+    ///
+    /// Returns the type of the deposit or withdrawal referenced by a dispute, resolve or chargeback,
+    /// `None` for deposits and withdrawals or if the referenced transaction doesn't exist
+    ///
+    pub fn referenced_type(&self, transaction: &Transaction) -> Option<Type> {
+        if !matches!(
+            transaction.r#type,
+            Type::Dispute | Type::Resolve | Type::Chargeback
+        ) {
+            return None;
+        }
+
+        self.get_tx(transaction.tx)
+            .map(|referenced| referenced.r#type)
+    }
+
+    ///
+    /// Returns the current dispute state of the transaction with the given id,
+    /// or `None` if no deposit or withdrawal has that id
+    ///
+    pub fn is_disputed(&self, tx: u32) -> Option<bool> {
+        self.get_tx(tx).map(|transaction| transaction.disputed)
+    }
+
+    ///
+    /// Returns true if the transaction with the given id is currently disputed
+    /// This is an O(1) lookup on the disputed map and does not touch the transaction itself
+    ///
+    pub(crate) fn is_tx_disputed(&self, tx: u32) -> bool {
+        self.disputed_map.contains_key(&tx)
+    }
+
+    ///
+    /// Records the amount actually applied by the deposit or withdrawal at the given index,
+    /// unless its tx id maps to another transaction
+    ///
+    pub(crate) fn set_applied(&mut self, index: usize, amount: Amount) {
+        let Some(transaction) = self.transactions.get(index) else {
+            return;
+        };
+
+        if self.tx_index_map.get(&transaction.tx) == Some(&index) {
+            self.applied_map.insert(transaction.tx, amount);
+        }
+    }
+
+    ///
+    /// Returns the amount actually applied by the deposit or withdrawal with the given id,
+    /// or `None` if it wasn't applied (e.g. a withdrawal rejected for insufficient funds)
+    ///
+    pub(crate) fn applied_amount(&self, tx: u32) -> Option<Amount> {
+        self.applied_map.get(&tx).copied()
+    }
+
+    ///
+    /// Returns the amount of a disputed transaction that is still held, or `None` if it isn't disputed
+    ///
+    pub(crate) fn held_amount(&self, tx: u32) -> Option<Amount> {
+        self.disputed_map.get(&tx).map(|(held, _)| *held)
+    }
+
+    ///
+    /// Returns the client whose dispute holds the funds of a disputed transaction, or `None` if it isn't disputed
+    ///
+    pub(crate) fn disputing_client(&self, tx: u32) -> Option<u16> {
+        self.disputed_map.get(&tx).map(|(_, client)| *client)
+    }
+
+    ///
+    /// Flags a transaction as disputed by the given client, or not disputed with `None`, by transaction id
+    /// A disputed transaction holds the full amount it applied, and the disputed map is updated
+    /// together with the transaction flag so both stay in sync
+    ///
+    pub(crate) fn set_disputed(&mut self, tx: u32, disputed_by: Option<u16>) {
+        let amount = self.applied_amount(tx).unwrap_or_default();
+        if let Some(transaction) = self.get_tx_mut(tx) {
+            transaction.disputed = disputed_by.is_some();
+
+            if let Some(client) = disputed_by {
+                self.disputed_map.insert(tx, (amount, client));
+            } else {
+                self.disputed_map.remove(&tx);
+            }
+        }
+    }
+
+    ///
+    /// Releases part of the amount held by a disputed transaction
+    /// The transaction is no longer disputed once nothing is held
+    ///
+    pub(crate) fn release_held(&mut self, tx: u32, amount: Amount) {
+        if let Some((held, _)) = self.disputed_map.get_mut(&tx) {
+            *held -= amount;
+
+            if *held == Amount::ZERO {
+                self.set_disputed(tx, None);
+            }
+        }
+    }
+
+    ///
+    /// Returns true if the transaction with the given id was charged back
+    ///
+    pub(crate) fn is_tx_charged_back(&self, tx: u32) -> bool {
+        self.charged_back_set.contains(&tx)
+    }
+
+    ///
+    /// Returns true if the transaction with the given id is a withdrawal
+    ///
+    pub(crate) fn is_withdrawal(&self, tx: u32) -> bool {
+        self.get_tx(tx)
+            .is_some_and(|transaction| transaction.r#type == Type::Withdrawal)
+    }
+
+    ///
+    /// Moves a disputed transaction to the terminal charged back state
+    /// The transaction is no longer disputed since its held funds have left the account
+    ///
+    pub(crate) fn set_charged_back(&mut self, tx: u32) {
+        if self.is_tx_disputed(tx) {
+            self.set_disputed(tx, None);
+            self.charged_back_set.insert(tx);
+        }
+    }
+
+    ///
+    /// Writes all stored transactions in a CSV format to the given writer, in the order they are stored
+    /// The disputed column holds the current dispute state of each transaction
     ///
     /// # Errors
     ///
-    /// Returns an error if the input file path is not provided in the command line arguments
+    /// If the csv writer fails to serialize a transaction to a csv record or to flush the writer
     ///
-    pub fn from_args() -> Result<Self> {
-        let arguments = std::env::args().collect::<Vec<_>>();
-        if arguments.len() < 2 {
-            eprintln!("Usage: {} <csv transactions input file>", arguments[0]);
-            std::process::exit(1);
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<()> {
+        let mut csv_writer = csv::WriterBuilder::default()
+            .delimiter(b',')
+            .has_headers(true)
+            .from_writer(writer);
+
+        for (index, transaction) in self.transactions.iter().enumerate() {
+            csv_writer
+                .serialize(transaction)
+                .map_err(|source| PaymentsError::SerializeTransaction { index, source })?;
         }
 
-        let transactions_path = PathBuf::from(&arguments[1].trim());
-        Self::from_csv(&transactions_path)
+        csv_writer.flush()?;
+
+        Ok(())
+    }
+
+    ///
+    /// Reads the transactions of the input given as the first command line argument, `-` reads them from stdin
+    /// This is a shortcut for simple programs, see the `toy_payments` binary for the full command line
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the input is not provided in the command line arguments or fails to be read
+    ///
+    pub fn from_args() -> Result<Self> {
+        let Some(input) = std::env::args_os().nth(1) else {
+            return Err(PaymentsError::MissingInput);
+        };
+
+        TransactionsReader::new().read_input(Path::new(&input))
     }
 
     ///
     /// Handles the csv parsing of a file by deserializing the records and returns a Transactions struct
+    /// A file that is empty or only has a header returns an empty Transactions, which can be checked with `is_empty`
+    /// Use a `TransactionsReader` to configure how the file is read
     ///
     /// # Errors
     ///
     /// Returns an error if the file does not exist or if the csv parsing fails
     ///
     pub fn from_csv(path: &Path) -> Result<Self> {
-        if !path.exists() {
-            return Err(anyhow::anyhow!(
-                "Transactions csv file does not exist: '{path:?}'"
-            ));
-        }
+        TransactionsReader::new().read(path)
+    }
 
-        let file = File::open(path)
-            .with_context(|| format!("Failed to open transactions file: '{path:?}'"))?;
+    ///
+    /// Handles the csv parsing of any reader with the same configuration as `from_csv`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header is invalid or if the csv parsing fails
+    ///
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        TransactionsReader::new().read_from(reader)
+    }
 
-        let mut csv_reader = csv::ReaderBuilder::default()
-            .delimiter(b',')
-            .trim(csv::Trim::All)
-            .has_headers(true)
-            .flexible(true)
-            .from_reader(file);
+    ///
+    /// Parses transactions from an in-memory csv string with the same configuration as `from_csv`,
+    /// without touching the filesystem
+    ///
+    /// ```
+    /// use toy_payments::Transactions;
+    ///
+    /// let transactions = Transactions::from_csv_str(
+    ///     "type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,0.5\n",
+    /// )?;
+    ///
+    /// assert_eq!(transactions.len(), 2);
+    /// # Ok::<(), toy_payments::PaymentsError>(())
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header is invalid or if the csv parsing fails
+    ///
+    pub fn from_csv_str(data: &str) -> Result<Self> {
+        Self::from_reader(data.as_bytes())
+    }
 
-        let mut transactions = vec![];
-        for (index, record) in csv_reader.records().enumerate() {
-            // Deserialize the csv record
-            let trx = record?
-                .deserialize::<Transaction>(None)
-                .with_context(|| format!("Failed to parse transaction at index: '{index}'"))?;
+    ///
+    /// Reads all `.csv` files in a directory in lexicographic filename order and concatenates their transactions
+    /// Other files and subdirectories are skipped
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path is not a directory, it can't be listed, or any csv file fails to be read
+    ///
+    pub fn from_dir(path: &Path) -> Result<Self> {
+        TransactionsReader::new().read_dir(path)
+    }
 
-            // Push the transaction into the vec
-            transactions.push(trx);
-        }
+    ///
+    /// Reads all `.csv` entries of a zip archive in entry order and concatenates their transactions
+    /// Entries in nested directories are read too, directory entries and other files are skipped
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the archive can't be read, or any csv entry fails to be read
+    ///
+    pub fn from_zip(path: &Path) -> Result<Self> {
+        TransactionsReader::new().read_zip(path)
+    }
+}
 
-        Ok(Self::from(transactions))
+///
+/// Creates a transaction without a timestamp, currency or destination, shared by the unit tests of the engines
+///
+#[cfg(test)]
+pub const fn transaction(
+    r#type: Type,
+    client: u16,
+    tx: u32,
+    amount: Option<Amount>,
+) -> Transaction {
+    Transaction {
+        r#type,
+        client,
+        tx,
+        amount,
+        timestamp: None,
+        currency: None,
+        dest_client: None,
+        disputed: false,
     }
 }
 
@@ -187,4 +673,517 @@ mod tests {
 
         assert_eq!(tx.client, 2);
     }
+
+    #[test]
+    fn test_transactions_set_disputed_updates_set() {
+        let mut transactions = Transactions::from_csv(Path::new("tests/resources/inputs/trx1.csv"))
+            .expect("Failed to read transactions from csv");
+
+        transactions.set_disputed(1, Some(1));
+        assert!(transactions.is_tx_disputed(1));
+        assert!(
+            transactions
+                .get_tx(1)
+                .expect("Failed to get transaction")
+                .disputed
+        );
+
+        transactions.set_disputed(1, None);
+        assert!(!transactions.is_tx_disputed(1));
+        assert!(
+            !transactions
+                .get_tx(1)
+                .expect("Failed to get transaction")
+                .disputed
+        );
+    }
+
+    #[test]
+    fn test_transactions_is_disputed() {
+        let mut transactions = Transactions::from_csv(Path::new("tests/resources/inputs/trx1.csv"))
+            .expect("Failed to read transactions from csv");
+
+        assert_eq!(transactions.is_disputed(1), Some(false));
+        assert_eq!(transactions.is_disputed(100), None);
+
+        transactions.set_disputed(1, Some(1));
+        assert_eq!(transactions.is_disputed(1), Some(true));
+    }
+
+    #[test]
+    fn test_transactions_from_vec_tracks_disputed_flags() {
+        let transactions = Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(3))),
+            Transaction {
+                disputed: true,
+                ..transaction(Type::Deposit, 2, 2, Some(Amount::from(5)))
+            },
+        ]);
+
+        assert!(!transactions.is_tx_disputed(1));
+        assert!(transactions.is_tx_disputed(2));
+        assert_eq!(transactions.held_amount(2), Some(Amount::from(5)));
+        assert_eq!(transactions.disputing_client(2), Some(2));
+    }
+
+    #[test]
+    fn test_transactions_set_disputed_unknown_tx() {
+        let mut transactions = Transactions::from_csv(Path::new("tests/resources/inputs/trx1.csv"))
+            .expect("Failed to read transactions from csv");
+
+        transactions.set_disputed(100, Some(1));
+        assert!(!transactions.is_tx_disputed(100));
+    }
+
+    #[test]
+    fn test_transactions_from_csv_misspelled_header_fails() {
+        let error = Transactions::from_csv(Path::new("tests/resources/inputs/bad_header.csv"))
+            .err()
+            .expect("Expected an error for a misspelled header");
+
+        let message = error.to_string();
+        assert!(message.contains("ammount"));
+        assert!(message.contains("amount"));
+        assert!(matches!(error, PaymentsError::InvalidHeader { .. }));
+    }
+
+    #[test]
+    fn test_transactions_from_csv_client_out_of_range_fails() {
+        let error =
+            Transactions::from_csv(Path::new("tests/resources/inputs/client_out_of_range.csv"))
+                .err()
+                .expect("Expected an error for a client id out of range");
+
+        assert_eq!(
+            error.to_string(),
+            "Transaction at index: '1' has a client of '70000' which is out of range, expected 0 to 65535"
+        );
+    }
+
+    #[test]
+    fn test_transactions_from_csv_tx_out_of_range_fails() {
+        let error = Transactions::from_csv(Path::new("tests/resources/inputs/tx_out_of_range.csv"))
+            .err()
+            .expect("Expected an error for a tx id out of range");
+
+        assert!(matches!(
+            error,
+            PaymentsError::IdOutOfRange {
+                index: 2,
+                field: "tx",
+                max: 4_294_967_295,
+                ..
+            }
+        ));
+        assert!(error.to_string().contains("4294967296"));
+    }
+
+    #[test]
+    fn test_transactions_from_dir_concatenates_in_filename_order() {
+        let transactions = Transactions::from_dir(Path::new("tests/resources/inputs/batch"))
+            .expect("Failed to read transactions from directory");
+
+        let txs = transactions.iter().map(|trx| trx.tx).collect::<Vec<_>>();
+        assert_eq!(txs, [1, 2, 3, 1, 4, 1, 2]);
+
+        let tx = transactions
+            .get_tx(4)
+            .expect("Failed to get transaction by id");
+        assert_eq!(tx.client, 2);
+    }
+
+    #[test]
+    fn test_transactions_from_dir_not_a_directory_fails() {
+        let error = Transactions::from_dir(Path::new("tests/resources/inputs/trx1.csv"))
+            .err()
+            .expect("Expected an error for a file path");
+
+        assert!(matches!(error, PaymentsError::NotADirectory(_)));
+    }
+
+    #[test]
+    fn test_transactions_from_zip_concatenates_in_entry_order() {
+        let transactions = Transactions::from_zip(Path::new("tests/resources/inputs/batch.zip"))
+            .expect("Failed to read transactions from archive");
+
+        // The nested `day/02_second.csv` entry comes before `01_first.csv` in the archive
+        let txs = transactions.iter().map(|trx| trx.tx).collect::<Vec<_>>();
+        assert_eq!(txs, [1, 4, 1, 2, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_transactions_read_zip_checks_the_uncompressed_size() {
+        let path = Path::new("tests/resources/inputs/compressible.zip");
+
+        // The archive is smaller than the maximum, but its csv entry expands past it
+        let error = TransactionsReader::new()
+            .with_max_bytes(4096)
+            .read_zip(path)
+            .err()
+            .expect("Expected an error for an entry larger than the maximum");
+        assert!(matches!(
+            error,
+            PaymentsError::FileTooLarge {
+                size: 8914,
+                max: 4096,
+                ..
+            }
+        ));
+
+        let transactions = TransactionsReader::new()
+            .with_max_bytes(10_000)
+            .read_zip(path)
+            .expect("Failed to read transactions from archive");
+        assert_eq!(transactions.len(), 500);
+    }
+
+    #[test]
+    fn test_transactions_from_zip_fails_on_a_plain_csv() {
+        let error = Transactions::from_zip(Path::new("tests/resources/inputs/trx1.csv"))
+            .err()
+            .expect("Expected an error for a file that isn't an archive");
+
+        assert!(matches!(error, PaymentsError::Archive(_)));
+    }
+
+    #[test]
+    fn test_transactions_extend_keeps_mapping_of_reused_tx_id() {
+        let mut transactions = Transactions::from_csv(Path::new("tests/resources/inputs/trx1.csv"))
+            .expect("Failed to read transactions from csv");
+        transactions.set_disputed(1, Some(1));
+
+        let reused = Transactions::from(vec![Transaction {
+            r#type: Type::Deposit,
+            client: 2,
+            tx: 1,
+            amount: Some(Amount::from(7)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        }]);
+        transactions.extend(reused);
+
+        let tx = transactions
+            .get_tx(1)
+            .expect("Failed to get transaction by id");
+        assert_eq!(tx.client, 1);
+        assert!(tx.disputed);
+        assert_eq!(transactions.is_disputed(1), Some(true));
+    }
+
+    #[test]
+    fn test_transactions_dangling_references() {
+        let transaction = |r#type, tx| Transaction {
+            r#type,
+            client: 1,
+            tx,
+            amount: (r#type == Type::Deposit).then(|| Amount::from(5)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+        let transactions = Transactions::from(vec![
+            transaction(Type::Dispute, 2),
+            transaction(Type::Deposit, 1),
+            transaction(Type::Dispute, 1),
+            transaction(Type::Chargeback, 99),
+            transaction(Type::Deposit, 2),
+        ]);
+
+        let dangling = transactions
+            .dangling_references()
+            .into_iter()
+            .map(|(index, trx)| (index, trx.tx))
+            .collect::<Vec<_>>();
+        assert_eq!(dangling, [(3, 99)]);
+    }
+
+    #[test]
+    fn test_transactions_for_client() {
+        let transaction = |r#type, client, tx| Transaction {
+            r#type,
+            client,
+            tx,
+            amount: matches!(r#type, Type::Deposit | Type::Withdrawal).then(|| Amount::from(5)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        };
+        let transactions = Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1),
+            transaction(Type::Deposit, 2, 2),
+            transaction(Type::Withdrawal, 1, 3),
+            transaction(Type::Dispute, 1, 1),
+            transaction(Type::Dispute, 2, 2),
+            transaction(Type::Chargeback, 1, 1),
+        ]);
+
+        let client_transactions = transactions
+            .for_client(1)
+            .into_iter()
+            .map(|trx| (trx.r#type, trx.tx))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            client_transactions,
+            [
+                (Type::Deposit, 1),
+                (Type::Withdrawal, 3),
+                (Type::Dispute, 1),
+                (Type::Chargeback, 1)
+            ]
+        );
+        assert!(transactions.for_client(3).is_empty());
+    }
+
+    #[test]
+    fn test_transactions_with_capacity() {
+        let transactions = Transactions::with_capacity(100);
+
+        assert!(transactions.is_empty());
+        assert!(transactions.transactions.capacity() >= 100);
+        assert!(transactions.tx_index_map.capacity() >= 100);
+    }
+
+    #[test]
+    fn test_transactions_sort_by_time() {
+        let mut transactions =
+            Transactions::from_csv(Path::new("tests/resources/inputs/timestamps_shuffled.csv"))
+                .expect("Failed to read transactions from csv");
+
+        transactions.sort_by_time();
+
+        let txs = transactions.iter().map(|trx| trx.tx).collect::<Vec<_>>();
+        assert_eq!(txs, [1, 2, 1, 3, 1, 4, 5]);
+
+        let tx = transactions
+            .get_tx(4)
+            .expect("Failed to get transaction by id");
+        assert_eq!(tx.client, 1);
+    }
+
+    #[test]
+    fn test_transactions_sort_by_time_without_timestamps_keeps_order() {
+        let mut transactions = Transactions::from_csv(Path::new("tests/resources/inputs/trx4.csv"))
+            .expect("Failed to read transactions from csv");
+
+        let before = transactions.iter().map(|trx| trx.tx).collect::<Vec<_>>();
+        transactions.sort_by_time();
+        let after = transactions.iter().map(|trx| trx.tx).collect::<Vec<_>>();
+
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_transactions_from_csv_missing_file_fails() {
+        let error = Transactions::from_csv(Path::new("tests/resources/inputs/missing.csv"))
+            .err()
+            .expect("Expected an error for a missing file");
+
+        assert!(matches!(error, PaymentsError::FileNotFound(_)));
+    }
+
+    #[test]
+    #[cfg(not(feature = "fixed-point"))]
+    fn test_transactions_write_csv() {
+        let mut transactions = Transactions::from_csv(Path::new("tests/resources/inputs/trx1.csv"))
+            .expect("Failed to read transactions from csv");
+        transactions.set_disputed(2, Some(1));
+
+        let mut output = vec![];
+        transactions
+            .write_csv(&mut output)
+            .expect("Failed to write transactions");
+
+        let output = String::from_utf8(output).expect("Output is not valid utf8");
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next(),
+            Some("type,client,tx,amount,timestamp,currency,dest_client,disputed")
+        );
+        assert_eq!(lines.next(), Some("deposit,1,1,1.0,,,,false"));
+        assert_eq!(lines.next(), Some("deposit,2,2,2.0,,,,true"));
+        assert_eq!(lines.count(), 3);
+    }
+
+    #[test]
+    fn test_transactions_write_csv_round_trip() {
+        let transactions = Transactions::from_csv(Path::new("tests/resources/inputs/trx2.csv"))
+            .expect("Failed to read transactions from csv");
+
+        let mut output = vec![];
+        transactions
+            .write_csv(&mut output)
+            .expect("Failed to write transactions");
+
+        let path = std::env::temp_dir().join(format!(
+            "toy_payments_ledger_round_trip_{}.csv",
+            std::process::id()
+        ));
+        std::fs::write(&path, &output).expect("Failed to write ledger file");
+
+        let read_back = Transactions::from_csv(&path);
+        std::fs::remove_file(&path).expect("Failed to remove ledger file");
+        let read_back = read_back.expect("Failed to read ledger back");
+        assert_eq!(read_back.len(), transactions.len());
+    }
+
+    #[test]
+    fn test_transactions_read_ignores_the_disputed_column() {
+        let mut transactions = Transactions::from(vec![
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(1))),
+            transaction(Type::Deposit, 2, 2, Some(Amount::from(2))),
+        ]);
+        transactions.set_disputed(2, Some(2));
+
+        let mut output = vec![];
+        transactions
+            .write_csv(&mut output)
+            .expect("Failed to write transactions");
+
+        let read_back = TransactionsReader::new()
+            .read_from(output.as_slice())
+            .expect("Failed to read ledger back");
+        assert_eq!(read_back.is_disputed(2), Some(false));
+        assert_eq!(read_back.disputing_client(2), None);
+    }
+
+    #[test]
+    fn test_transactions_set_charged_back_clears_dispute() {
+        let mut transactions = Transactions::from_csv(Path::new("tests/resources/inputs/trx1.csv"))
+            .expect("Failed to read transactions from csv");
+
+        transactions.set_charged_back(1);
+        assert!(!transactions.is_tx_charged_back(1));
+
+        transactions.set_disputed(1, Some(1));
+        transactions.set_charged_back(1);
+        assert!(transactions.is_tx_charged_back(1));
+        assert!(!transactions.is_tx_disputed(1));
+    }
+
+    #[test]
+    fn test_type_display_from_str_round_trip() {
+        for r#type in Type::ALL {
+            let parsed = r#type
+                .to_string()
+                .parse::<Type>()
+                .expect("Failed to parse type");
+
+            assert_eq!(parsed, r#type);
+        }
+    }
+
+    #[test]
+    fn test_type_display_matches_serde() {
+        let mut writer = csv::WriterBuilder::default()
+            .has_headers(false)
+            .from_writer(vec![]);
+
+        for r#type in Type::ALL {
+            writer.serialize(r#type).expect("Failed to serialize type");
+        }
+
+        let output = String::from_utf8(writer.into_inner().expect("Failed to flush writer"))
+            .expect("Output is not valid utf8");
+        let expected = Type::ALL.map(|r#type| format!("{type}\n")).concat();
+
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_type_from_str_unknown_fails() {
+        let error = "refund"
+            .parse::<Type>()
+            .expect_err("Expected an error for an unknown type");
+
+        assert!(matches!(error, PaymentsError::InvalidType(ref name) if name == "refund"));
+    }
+
+    #[test]
+    fn test_transactions_push_indexes_transaction() {
+        let mut transactions = Transactions::default();
+
+        transactions.push(Transaction {
+            r#type: Type::Deposit,
+            client: 1,
+            tx: 7,
+            amount: Some(Amount::from(1)),
+            timestamp: None,
+            currency: None,
+            dest_client: None,
+            disputed: false,
+        });
+
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions.get_tx(7).map(|trx| trx.client), Some(1));
+    }
+
+    #[test]
+    fn test_type_from_str_is_case_insensitive() {
+        for r#type in Type::ALL {
+            let name = r#type.as_str();
+            let capitalized = name[..1].to_uppercase() + &name[1..];
+
+            assert_eq!(name.to_uppercase().parse::<Type>().ok(), Some(r#type));
+            assert_eq!(capitalized.parse::<Type>().ok(), Some(r#type));
+            assert_eq!(format!(" {name} ").parse::<Type>().ok(), Some(r#type));
+        }
+    }
+
+    #[test]
+    fn test_type_deserialize_mixed_case() {
+        let data = "type,client,tx,amount\nDeposit,1,1,1.0\nWITHDRAWAL,1,2,0.5\nDiSpUtE,1,1,\nResolve,1,1,\nCHARGEBACK,1,1,\nTransfer,1,3,0.5\n";
+        let mut reader = csv::ReaderBuilder::default().from_reader(data.as_bytes());
+
+        let types = reader
+            .deserialize::<Transaction>()
+            .map(|trx| trx.expect("Failed to deserialize transaction").r#type)
+            .collect::<Vec<_>>();
+
+        assert_eq!(types, Type::ALL);
+    }
+
+    #[test]
+    fn test_transactions_from_csv_str() {
+        let transactions = Transactions::from_csv_str(
+            "type, client, tx, amount\ndeposit, 1, 1, 1.5\ndispute, 1, 1,\n",
+        )
+        .expect("Failed to read transactions from string");
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(
+            transactions.get_tx(1).and_then(|trx| trx.amount),
+            Some(Amount::new(15, 1))
+        );
+    }
+
+    #[test]
+    fn test_transactions_from_csv_str_bad_row_fails() {
+        let error =
+            Transactions::from_csv_str("type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,x,1\n")
+                .err()
+                .expect("Expected an error for a malformed row");
+
+        assert!(matches!(error, PaymentsError::CsvParse { index: 1, .. }));
+    }
+
+    #[test]
+    fn test_transactions_from_csv_header_only_is_empty() {
+        let transactions =
+            Transactions::from_csv(Path::new("tests/resources/inputs/header_only.csv"))
+                .expect("Failed to read transactions from csv");
+
+        assert!(transactions.is_empty());
+    }
+
+    #[test]
+    fn test_transactions_from_csv_empty_file_is_empty() {
+        let transactions = Transactions::from_csv(Path::new("tests/resources/inputs/empty.csv"))
+            .expect("Failed to read transactions from csv");
+
+        assert!(transactions.is_empty());
+    }
 }
@@ -0,0 +1,98 @@
+use rust_decimal::Decimal;
+use std::path::Path;
+#[cfg(feature = "fixed-point")]
+use toy_payments::FixedPoint;
+use toy_payments::{Accounts, CsvOptions, Engine, TransactionsReader};
+
+///
+/// The fixtures of the cli tests, with the options they are written with
+///
+const FIXTURES: [(&str, &str, bool); 7] = [
+    ("trx1.csv", "trx1.csv", false),
+    ("trx2.csv", "trx2.csv", false),
+    ("trx3.csv", "trx3.csv", false),
+    ("trx4.csv", "trx2.csv", false),
+    ("timestamps_shuffled.csv", "timestamps.csv", false),
+    ("currencies.csv", "currencies.csv", false),
+    ("overdrawn.csv", "overdrawn.csv", true),
+];
+
+///
+/// Processes an input fixture with the amount backend the tests are built with and returns the accounts csv
+///
+fn process_fixture(input: &str, overdrawn: bool) -> String {
+    let mut transactions = TransactionsReader::new()
+        .read(&Path::new("tests/resources/inputs").join(input))
+        .expect("Failed to read transactions from csv");
+    transactions.sort_by_time();
+
+    let mut engine = Engine::new(Accounts::new());
+    engine.process(transactions);
+
+    let mut output = vec![];
+    engine
+        .accounts()
+        .write_csv_with(&CsvOptions::new().with_overdrawn(overdrawn), &mut output)
+        .expect("Failed to write accounts");
+
+    String::from_utf8(output).expect("Output is not valid utf8")
+}
+
+///
+/// Compares the amounts of the fixtures by value, since the fixed-point backend doesn't keep trailing zeros
+///
+/// Run with `cargo test --features fixed-point --test backends` to check the fixed-point backend
+///
+#[test]
+fn amount_backend_matches_fixture_outputs() {
+    for (input, output, overdrawn) in FIXTURES {
+        let actual = process_fixture(input, overdrawn);
+        let expected = std::fs::read_to_string(Path::new("tests/resources/outputs").join(output))
+            .expect("Failed to read expected output");
+
+        assert_eq!(actual.lines().count(), expected.lines().count(), "{input}");
+        for (actual, expected) in actual.lines().zip(expected.lines()) {
+            for (actual, expected) in actual.split(',').zip(expected.split(',')) {
+                match (actual.parse::<Decimal>(), expected.parse::<Decimal>()) {
+                    (Ok(actual), Ok(expected)) => assert_eq!(actual, expected, "{input}"),
+                    _ => assert_eq!(actual, expected, "{input}"),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "fixed-point")]
+#[test]
+fn fixed_point_parses_and_displays_like_decimal() {
+    for value in ["1.5", "2", "-0.0001", "1234.5678", "0.25"] {
+        let fixed = value.parse::<FixedPoint>().expect("Failed to parse amount");
+        let decimal = value.parse::<Decimal>().expect("Failed to parse amount");
+
+        assert_eq!(fixed.to_string(), decimal.normalize().to_string());
+        assert_eq!(format!("{fixed:.4}"), format!("{decimal:.4}"));
+    }
+}
+
+#[cfg(feature = "fixed-point")]
+#[test]
+fn fixed_point_rejects_too_many_decimal_places() {
+    assert!("1.00001".parse::<FixedPoint>().is_err());
+    assert_eq!(
+        "1.50000".parse::<FixedPoint>().ok(),
+        Some(FixedPoint::from_ten_thousandths(15_000))
+    );
+}
+
+#[cfg(feature = "fixed-point")]
+#[test]
+fn fixed_point_basis_points_round_half_even() {
+    use toy_payments::Money;
+
+    let amount = |value: i64| FixedPoint::from_ten_thousandths(value);
+
+    assert_eq!(amount(1_000_000).basis_points(50), amount(5_000));
+    assert_eq!(amount(1_000).basis_points(5), amount(0));
+    assert_eq!(amount(3_000).basis_points(5), amount(2));
+    assert_eq!(amount(-3_000).basis_points(5), amount(-2));
+}
@@ -0,0 +1,77 @@
+// Each test and benchmark that includes this module only uses some of its helpers
+#![allow(dead_code)]
+
+use toy_payments::{Amount, Transaction, Transactions, Type};
+
+///
+/// Generates a deterministic synthetic collection of `size` transactions for benchmarks and tests
+///
+/// Most rows are deposits and withdrawals spread over a thousand clients, with occasional disputes
+/// of earlier deposits that are then either resolved or charged back
+///
+pub fn synthetic_transactions(size: usize) -> Transactions {
+    // A small linear congruential generator keeps the output reproducible without extra dependencies
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move || {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        state >> 33
+    };
+
+    let mut transactions = Vec::with_capacity(size);
+    let mut tx = 0_u32;
+    while transactions.len() < size {
+        let client = u16::try_from(next() % 1_000).unwrap_or_default() + 1;
+        let cents = i64::try_from(next() % 100_000).unwrap_or_default();
+
+        let (r#type, tx, amount) = match next() % 100 {
+            // Dispute an earlier transaction, which is resolved or charged back right after
+            0..=1 if tx > 0 => {
+                let disputed = u32::try_from(next() % u64::from(tx)).unwrap_or_default() + 1;
+                transactions.push(transaction(Type::Dispute, client, disputed, None));
+
+                let settle = if next() % 4 == 0 {
+                    Type::Chargeback
+                } else {
+                    Type::Resolve
+                };
+                (settle, disputed, None)
+            }
+            2..=59 => {
+                tx += 1;
+                (Type::Deposit, tx, Some(Amount::new(cents, 2)))
+            }
+            _ => {
+                tx += 1;
+                (Type::Withdrawal, tx, Some(Amount::new(cents / 2, 2)))
+            }
+        };
+
+        transactions.push(transaction(r#type, client, tx, amount));
+    }
+
+    transactions.truncate(size);
+    Transactions::from(transactions)
+}
+
+///
+/// Creates a transaction in the base currency, without a timestamp or a destination client
+///
+pub const fn transaction(
+    r#type: Type,
+    client: u16,
+    tx: u32,
+    amount: Option<Amount>,
+) -> Transaction {
+    Transaction {
+        r#type,
+        client,
+        tx,
+        amount,
+        timestamp: None,
+        currency: None,
+        dest_client: None,
+        disputed: false,
+    }
+}
@@ -0,0 +1,58 @@
+use proptest::prelude::*;
+use toy_payments::{Accounts, Amount, Engine, Transaction, Type};
+
+mod common;
+
+///
+/// Generates a transaction with a small client and tx id range so disputes often reference existing transactions
+///
+fn transaction_strategy() -> impl Strategy<Value = Transaction> {
+    (
+        prop::sample::select(Type::ALL.to_vec()),
+        1..4_u16,
+        1..20_u32,
+        0..100_000_i64,
+        prop::bool::weighted(0.2),
+        1..4_u16,
+    )
+        .prop_map(|(r#type, client, tx, cents, partial, destination)| {
+            let amount = match r#type {
+                Type::Deposit | Type::Withdrawal | Type::Transfer => Some(Amount::new(cents, 2)),
+                Type::Chargeback if partial => Some(Amount::new(cents, 2)),
+                Type::Dispute | Type::Resolve | Type::Chargeback => None,
+            };
+
+            Transaction {
+                dest_client: (r#type == Type::Transfer).then_some(destination),
+                ..common::transaction(r#type, client, tx, amount)
+            }
+        })
+}
+
+proptest! {
+    #[test]
+    fn total_is_available_plus_held_after_every_transaction(
+        transactions in prop::collection::vec(transaction_strategy(), 1..200)
+    ) {
+        let mut engine = Engine::new(Accounts::new());
+
+        for transaction in transactions {
+            engine.process_one(transaction);
+
+            for account in engine.accounts().iter() {
+                prop_assert_eq!(account.total, account.available + account.held);
+            }
+        }
+    }
+}
+
+#[test]
+fn total_is_available_plus_held_for_synthetic_transactions() {
+    let mut engine = Engine::new(Accounts::new());
+
+    engine.process(common::synthetic_transactions(10_000));
+
+    for account in engine.accounts().iter() {
+        assert_eq!(account.total, account.available + account.held);
+    }
+}
@@ -1,4 +1,5 @@
-use std::process::{Command, Output};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
 
 #[test]
 fn basic_transaction_1_succeeds() {
@@ -35,17 +36,367 @@ fn basic_transaction_3_succeeds() {
     assert_eq!(output.stdout, include_bytes!("resources/outputs/trx3.csv"));
 }
 
+#[test]
+fn shuffled_timestamps_equal_sorted_timestamps_succeeds() {
+    let output = start_program("tests/resources/inputs/timestamps_sorted.csv");
+    let output2 = start_program("tests/resources/inputs/timestamps_shuffled.csv");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        output.stdout,
+        include_bytes!("resources/outputs/timestamps.csv")
+    );
+    assert_eq!(output2.status.code(), Some(0));
+    assert_eq!(
+        output2.stdout,
+        include_bytes!("resources/outputs/timestamps.csv")
+    );
+}
+
+#[test]
+fn currencies_are_written_per_account_succeeds() {
+    let output = start_program("tests/resources/inputs/currencies.csv");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        output.stdout,
+        include_bytes!("resources/outputs/currencies.csv")
+    );
+}
+
+#[test]
+fn header_only_input_warns_succeeds() {
+    let output = start_program("tests/resources/inputs/header_only.csv");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no transactions were read"));
+}
+
+#[test]
+fn dangling_dispute_warns_succeeds() {
+    let output = start_program("tests/resources/inputs/dangling_dispute.csv");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        output.stdout,
+        b"client,available,held,total,locked\n1,0.0,2.0,2.0,false\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("dispute at index 1 references tx 99 which is not a deposit or withdrawal"));
+}
+
+#[test]
+fn overdrawn_flag_adds_column_succeeds() {
+    let output = start_program_with_args(&["--overdrawn", "tests/resources/inputs/overdrawn.csv"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        output.stdout,
+        include_bytes!("resources/outputs/overdrawn.csv")
+    );
+}
+
+#[test]
+fn bom_and_crlf_input_succeeds() {
+    let output = start_program("tests/resources/inputs/bom_crlf.csv");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        output.stdout,
+        include_bytes!("resources/outputs/bom_crlf.csv")
+    );
+}
+
+#[test]
+fn fixed_scale_flag_pads_amounts_succeeds() {
+    let output = start_program_with_args(&["--fixed-scale", "tests/resources/inputs/trx1.csv"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        output.stdout,
+        b"client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n2,2.0000,0.0000,2.0000,false\n"
+    );
+}
+
+#[test]
+fn omit_empty_flag_skips_empty_accounts_succeeds() {
+    let output =
+        start_program_with_args(&["--omit-empty", "tests/resources/inputs/omit_empty.csv"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        output.stdout,
+        include_bytes!("resources/outputs/omit_empty.csv")
+    );
+}
+
+#[test]
+fn format_bincode_succeeds() {
+    let output =
+        start_program_with_args(&["--format", "bincode", "tests/resources/inputs/trx1.csv"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let accounts = toy_payments::Accounts::read_binary(output.stdout.as_slice())
+        .expect("Failed to read binary accounts");
+    assert_eq!(
+        accounts.get(1).map(|account| account.total),
+        Some(toy_payments::Amount::new(15, 1))
+    );
+    assert_eq!(
+        accounts.get(2).map(|account| account.total),
+        Some(toy_payments::Amount::from(2))
+    );
+}
+
+#[test]
+fn format_table_succeeds() {
+    let output = start_program_with_args(&["--format", "table", "tests/resources/inputs/trx1.csv"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        output.stdout,
+        include_bytes!("resources/outputs/trx1_table.txt")
+    );
+}
+
+#[test]
+fn unknown_format_prints_usage() {
+    let output = start_program_with_args(&["--format", "xml", "tests/resources/inputs/trx1.csv"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn skip_bad_rows_succeeds() {
+    let output =
+        start_program_with_args(&["--skip-bad-rows", "tests/resources/inputs/bad_row.csv"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        output.stdout,
+        include_bytes!("resources/outputs/bad_row.csv")
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("index: '2'"));
+}
+
+#[test]
+fn bad_row_without_skip_fails() {
+    let output = start_program("tests/resources/inputs/bad_row.csv");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn strict_with_rejected_transaction_fails() {
+    // The second withdrawal of client 2 in trx1 is rejected for insufficient funds
+    let output = start_program_with_args(&["--strict", "tests/resources/inputs/trx1.csv"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(output.stdout, include_bytes!("resources/outputs/trx1.csv"));
+}
+
+#[test]
+fn strict_with_undisputed_resolve_fails() {
+    let output =
+        start_program_with_args(&["--strict", "tests/resources/inputs/undisputed_resolve.csv"]);
+
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = start_program("tests/resources/inputs/undisputed_resolve.csv");
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn strict_without_rejected_transaction_succeeds() {
+    let output = start_program_with_args(&["--strict", "tests/resources/inputs/trx3.csv"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, include_bytes!("resources/outputs/trx3.csv"));
+}
+
+#[test]
+fn reject_zero_client_leaves_client_zero_out() {
+    let output = start_program("tests/resources/inputs/zero_client.csv");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\n0,10.0,0,10.0,false\n"));
+
+    let output = start_program_with_args(&[
+        "--strict",
+        "--reject-zero-client",
+        "tests/resources/inputs/zero_client.csv",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        output.stdout,
+        include_bytes!("resources/outputs/zero_client_rejected.csv")
+    );
+}
+
+#[test]
+fn max_bytes_rejects_larger_input() {
+    let output = start_program_with_args(&["--max-bytes", "16", "tests/resources/inputs/trx1.csv"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("more than the maximum of 16 bytes"));
+}
+
+#[test]
+fn output_flag_writes_file_succeeds() {
+    let path = std::env::temp_dir().join(format!(
+        "toy_payments_output_flag_{}.csv",
+        std::process::id()
+    ));
+
+    let output = start_program_with_args(&[
+        "--output",
+        path.to_str().expect("Temp path is not valid utf8"),
+        "tests/resources/inputs/trx2.csv",
+    ]);
+
+    let written = std::fs::read(&path);
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        written.expect("Failed to read output file"),
+        include_bytes!("resources/outputs/trx2.csv")
+    );
+}
+
+#[test]
+fn output_flag_invalid_path_fails() {
+    let output = start_program_with_args(&[
+        "--output",
+        "tests/resources/missing_dir/out.csv",
+        "tests/resources/inputs/trx2.csv",
+    ]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to create output file"));
+}
+
+#[test]
+fn missing_input_fails_with_usage() {
+    let output = start_program_with_args(&["--strict"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Usage"));
+}
+
+#[test]
+fn help_lists_the_process_command() {
+    let output = start_program_with_args(&["--help"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let help = String::from_utf8_lossy(&output.stdout);
+    assert!(help.contains("Usage"));
+    assert!(help.contains("process"));
+    assert!(help.contains("--strict"));
+}
+
+#[test]
+fn process_command_succeeds() {
+    let output = start_program_with_args(&["process", "tests/resources/inputs/trx1.csv"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, include_bytes!("resources/outputs/trx1.csv"));
+}
+
+#[test]
+fn process_command_with_multiple_inputs_succeeds() {
+    let output = start_program_with_args(&[
+        "process",
+        "tests/resources/inputs/batch/01_first.csv",
+        "tests/resources/inputs/batch/02_second.csv",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, include_bytes!("resources/outputs/batch.csv"));
+}
+
+#[test]
+fn stdin_input_succeeds() {
+    let output = start_program_with_stdin(
+        &["process", "-"],
+        include_bytes!("resources/inputs/trx1.csv"),
+    );
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, include_bytes!("resources/outputs/trx1.csv"));
+}
+
+#[test]
+fn check_writes_nothing() {
+    let output =
+        start_program_with_args(&["process", "--check", "tests/resources/inputs/trx1.csv"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    let output = start_program_with_args(&[
+        "process",
+        "--check",
+        "--strict",
+        "tests/resources/inputs/trx1.csv",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
 ///
 /// # Panics
 ///
 /// Panics if the command fails to run with cargo
 ///
 pub fn start_program(input: &str) -> Output {
+    start_program_with_args(&[input])
+}
+
+///
+/// # Panics
+///
+/// Panics if the command fails to run with cargo
+///
+pub fn start_program_with_args(args: &[&str]) -> Output {
     Command::new("cargo")
         .current_dir(env!("CARGO_MANIFEST_DIR"))
         .arg("run")
         .arg("--")
-        .arg(input)
+        .args(args)
         .output()
         .expect("Failed to run command with cargo")
 }
+
+///
+/// # Panics
+///
+/// Panics if the command fails to run with cargo or its stdin can't be written
+///
+pub fn start_program_with_stdin(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new("cargo")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg("run")
+        .arg("--")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run command with cargo");
+
+    child
+        .stdin
+        .take()
+        .expect("Failed to open stdin")
+        .write_all(input)
+        .expect("Failed to write stdin");
+
+    child
+        .wait_with_output()
+        .expect("Failed to wait for command")
+}
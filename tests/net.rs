@@ -0,0 +1,99 @@
+#![cfg(feature = "net")]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use toy_payments::{serve_listener, Accounts, Engine, SharedEngine};
+
+///
+/// Serves a shared engine on a loopback port picked by the os and connects to it
+///
+fn connect() -> (TcpStream, BufReader<TcpStream>) {
+    connect_with(|| Engine::new(Accounts::new()))
+}
+
+///
+/// Serves a shared engine whose shards are built with `engine` and connects to it
+///
+fn connect_with(
+    engine: impl Fn() -> Engine + Send + Clone + 'static,
+) -> (TcpStream, BufReader<TcpStream>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let addr = listener
+        .local_addr()
+        .expect("Failed to get listener address");
+
+    thread::spawn(move || {
+        let engine = SharedEngine::new(2, engine);
+        serve_listener(&listener, engine).expect("Failed to serve");
+    });
+
+    let stream = TcpStream::connect(addr).expect("Failed to connect");
+    let reader = BufReader::new(stream.try_clone().expect("Failed to clone stream"));
+    (stream, reader)
+}
+
+fn send(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, line: &str) -> String {
+    writeln!(stream, "{line}").expect("Failed to send line");
+
+    let mut response = String::new();
+    reader
+        .read_line(&mut response)
+        .expect("Failed to read response");
+    response.trim_end().to_string()
+}
+
+#[test]
+fn serve_answers_with_the_resulting_balance_succeeds() {
+    let (mut stream, mut reader) = connect();
+
+    assert_eq!(
+        send(&mut stream, &mut reader, "deposit, 1, 1, 3"),
+        "1,3,0,3,false"
+    );
+    assert_eq!(
+        send(&mut stream, &mut reader, "deposit, 2, 2, 4"),
+        "2,4,0,4,false"
+    );
+    assert_eq!(
+        send(&mut stream, &mut reader, "dispute, 1, 1,"),
+        "1,0,3,3,false"
+    );
+    assert_eq!(
+        send(&mut stream, &mut reader, "chargeback, 1, 1,"),
+        "1,0,0,0,true"
+    );
+}
+
+#[test]
+fn serve_answers_malformed_lines_with_an_error_succeeds() {
+    let (mut stream, mut reader) = connect();
+
+    let response = send(&mut stream, &mut reader, "deposit, 1, x, 1.0");
+    assert!(response.starts_with("error: "));
+
+    // The connection keeps working after a malformed line
+    assert_eq!(
+        send(&mut stream, &mut reader, "deposit, 1, 1, 1"),
+        "1,1,0,1,false"
+    );
+}
+
+#[test]
+fn serve_answers_with_the_currency_of_the_account_succeeds() {
+    let (mut stream, mut reader) =
+        connect_with(|| Engine::new(Accounts::new().with_base_currency("EUR")));
+
+    assert_eq!(
+        send(&mut stream, &mut reader, "deposit, 1, 1, 3"),
+        "1,EUR,3,0,3,false"
+    );
+}
+
+#[test]
+fn serve_answers_transfers_with_an_error_succeeds() {
+    let (mut stream, mut reader) = connect();
+
+    let response = send(&mut stream, &mut reader, "transfer, 1, 1, 3");
+    assert!(response.starts_with("error: "), "{response}");
+}
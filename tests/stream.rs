@@ -0,0 +1,60 @@
+#![cfg(feature = "async")]
+
+use futures_util::stream;
+use tokio::sync::mpsc;
+use toy_payments::{Accounts, Amount, Engine, Type};
+
+mod common;
+
+use common::transaction;
+
+#[tokio::test]
+async fn process_stream_from_channel() {
+    let (sender, mut receiver) = mpsc::channel(4);
+
+    let producer = tokio::spawn(async move {
+        let transactions = [
+            transaction(Type::Deposit, 1, 1, Some(Amount::from(10))),
+            transaction(Type::Withdrawal, 1, 2, Some(Amount::from(3))),
+            transaction(Type::Deposit, 2, 3, Some(Amount::from(5))),
+            transaction(Type::Dispute, 2, 3, None),
+        ];
+        for transaction in transactions {
+            sender
+                .send(transaction)
+                .await
+                .expect("Failed to send transaction");
+        }
+    });
+
+    // The stream ends once the producer is done and the sender is dropped
+    let mut engine = Engine::new(Accounts::new());
+    engine
+        .process_stream(stream::poll_fn(|cx| receiver.poll_recv(cx)))
+        .await;
+    producer.await.expect("Producer panicked");
+
+    let first = engine.accounts().get(1).expect("Failed to get account");
+    assert_eq!(first.available, Amount::from(7));
+
+    let second = engine.accounts().get(2).expect("Failed to get account");
+    assert_eq!(second.available, Amount::ZERO);
+    assert_eq!(second.held, Amount::from(5));
+}
+
+#[tokio::test]
+async fn process_stream_continues_after_previous_transactions() {
+    let mut engine = Engine::new(Accounts::new());
+    engine.process_one(transaction(Type::Deposit, 1, 1, Some(Amount::from(4))));
+
+    engine
+        .process_stream(stream::iter([
+            transaction(Type::Dispute, 1, 1, None),
+            transaction(Type::Resolve, 1, 1, None),
+        ]))
+        .await;
+
+    let account = engine.accounts().get(1).expect("Failed to get account");
+    assert_eq!(account.available, Amount::from(4));
+    assert_eq!(account.held, Amount::ZERO);
+}